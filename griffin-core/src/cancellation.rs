@@ -0,0 +1,256 @@
+//!
+//! Ties the upstream stream to the downstream call.
+//! When the client goes away before the response is finished,
+//! the upstream stream is reset with CANCEL right away
+//! instead of running until the upstream ends it.
+//! The same happens when the call deadline passes.
+//!
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tokio::sync::oneshot;
+use tokio::time::{Instant, Sleep};
+use tower::BoxError;
+
+use crate::ProxyResponse;
+use crate::telemetry::metrics::Metrics;
+
+/// Wrap the request body of a call,
+/// the returned guard cancels it when dropped
+/// before the response is finished
+/// or when `deadline` passes
+pub(crate) fn cancellation<B>(
+    body: B,
+    deadline: Option<Instant>,
+    metrics: Arc<Metrics>,
+    path: String,
+) -> (CancellableBody<B>, CancelOnDrop) {
+    let (cancel, cancelled) = oneshot::channel();
+    let body = CancellableBody {
+        inner: body,
+        cancelled: Some(cancelled),
+        expired: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline))),
+    };
+    let guard = CancelOnDrop {
+        cancel: Some(cancel),
+        finished: false,
+        deadline,
+        metrics,
+        path,
+    };
+    (body, guard)
+}
+
+/// reset stream reason, hyper sends it
+/// to the upstream when the request body fails with it
+fn cancel_error() -> BoxError {
+    h2::Error::from(h2::Reason::CANCEL).into()
+}
+
+/// Request body sent to the upstream
+pub(crate) struct CancellableBody<B> {
+    inner: B,
+    cancelled: Option<oneshot::Receiver<()>>,
+    expired: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B> Body for CancellableBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        if let Some(cancelled) = &mut self.cancelled {
+            match Pin::new(cancelled).poll(cx) {
+                Poll::Ready(Ok(())) => return Poll::Ready(Some(Err(cancel_error()))),
+                // the response finished normally
+                Poll::Ready(Err(_)) => self.cancelled = None,
+                Poll::Pending => {}
+            }
+        }
+        // still sending when the deadline passed
+        if let Some(expired) = &mut self.expired
+            && expired.as_mut().poll(cx).is_ready()
+        {
+            return Poll::Ready(Some(Err(cancel_error())));
+        }
+        // the client went away while still sending
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        Poll::Ready(frame.map(|frame| frame.map_err(|_| cancel_error())))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Held by the response body of a call
+pub(crate) struct CancelOnDrop {
+    cancel: Option<oneshot::Sender<()>>,
+    finished: bool,
+    deadline: Option<Instant>,
+    metrics: Arc<Metrics>,
+    path: String,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+        // the proxy ended it, not the client
+        if self
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            return;
+        }
+        self.metrics
+            .cancelled_rpcs()
+            .with_label_values(&[self.path.as_str()])
+            .inc();
+    }
+}
+
+/// Response body of a call, it marks the call
+/// finished once the last frame was read
+struct DownstreamBody<B: Body> {
+    inner: B,
+    guard: CancelOnDrop,
+}
+
+impl<B> Body for DownstreamBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        let finished = match &frame {
+            None | Some(Err(_)) => true,
+            Some(Ok(frame)) => frame.is_trailers() || self.inner.is_end_stream(),
+        };
+        if finished {
+            self.guard.finished = true;
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B: Body> Drop for DownstreamBody<B> {
+    fn drop(&mut self) {
+        // hyper never polls a body that is already
+        // at its end, such as a Trailers-Only response
+        if self.inner.is_end_stream() {
+            self.guard.finished = true;
+        }
+    }
+}
+
+/// cancel the call if `res` is dropped unfinished
+pub(crate) fn cancel_on_drop(res: ProxyResponse, guard: CancelOnDrop) -> ProxyResponse {
+    res.map(|inner| DownstreamBody { inner, guard }.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{Full, StreamBody};
+
+    fn path_count(path: &str) -> f64 {
+        Metrics::new()
+            .cancelled_rpcs()
+            .with_label_values(&[path])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn test_dropped_response_cancels_request_body() {
+        let path = "/test.Cancel/Dropped";
+        let pending = StreamBody::new(futures_util::stream::pending::<
+            Result<Frame<Bytes>, BoxError>,
+        >());
+        let (mut body, guard) = cancellation(pending, None, Arc::new(Metrics::new()), path.into());
+
+        drop(guard);
+        let err = body.frame().await.unwrap().unwrap_err();
+        let reason = err.downcast_ref::<h2::Error>().and_then(h2::Error::reason);
+        assert_eq!(reason, Some(h2::Reason::CANCEL));
+        assert_eq!(path_count(path), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_deadline_cancels_request_body() {
+        let path = "/test.Cancel/Deadline";
+        let pending = StreamBody::new(futures_util::stream::pending::<
+            Result<Frame<Bytes>, BoxError>,
+        >());
+        let deadline = Instant::now() + std::time::Duration::from_millis(20);
+        let (mut body, guard) = cancellation(
+            pending,
+            Some(deadline),
+            Arc::new(Metrics::new()),
+            path.into(),
+        );
+
+        let err = body.frame().await.unwrap().unwrap_err();
+        let reason = err.downcast_ref::<h2::Error>().and_then(h2::Error::reason);
+        assert_eq!(reason, Some(h2::Reason::CANCEL));
+        // not counted as a client cancellation
+        drop(guard);
+        assert_eq!(path_count(path), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_finished_response_is_not_cancelled() {
+        let path = "/test.Cancel/Finished";
+        let (mut body, guard) = cancellation(
+            Full::new(Bytes::from_static(b"hello")),
+            None,
+            Arc::new(Metrics::new()),
+            path.into(),
+        );
+        let res = cancel_on_drop(
+            crate::telemetry::metrics::from_full_bytes(Full::default()),
+            guard,
+        );
+        res.into_body().collect().await.unwrap();
+
+        assert_eq!(
+            body.frame().await.unwrap().unwrap().into_data().unwrap(),
+            "hello"
+        );
+        assert!(body.frame().await.is_none());
+        assert_eq!(path_count(path), 0.0);
+    }
+}
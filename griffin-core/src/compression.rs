@@ -0,0 +1,423 @@
+//!
+//! Message compression between client and upstream.
+//! On matching routes Griffin recompresses client messages
+//! into the encoding the upstream wants, and upstream messages
+//! into one the client listed in `grpc-accept-encoding`.
+//! <https://github.com/grpc/grpc/blob/master/doc/compression.md>
+//!
+use async_stream::stream;
+use bytes::{Bytes, BytesMut};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use http::{HeaderMap, HeaderValue, Request, header};
+use http_body::{Body, Frame};
+use http_body_util::{BodyExt, StreamBody};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+use crate::error::ProxyError;
+use crate::frame::{COMPRESSED, Deframer, TRAILERS, encode_frame};
+use crate::message_size::Violation;
+use crate::status;
+use crate::{ProxyResponse, UpstreamBody};
+
+pub const GRPC_ENCODING: &str = "grpc-encoding";
+pub const GRPC_ACCEPT_ENCODING: &str = "grpc-accept-encoding";
+/// largest decompressed message of a call without a
+/// message size limit, the default receive limit of gRPC
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    /// zlib format, as every gRPC implementation sends it
+    Deflate,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "identity" => Some(Encoding::Identity),
+            "gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// `grpc-encoding` of a call, identity when missing
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ProxyError> {
+        match headers.get(GRPC_ENCODING) {
+            None => Ok(Encoding::Identity),
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(Self::from_name)
+                .ok_or_else(|| ProxyError::UnsupportedEncoding(value.clone())),
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Bytes> {
+        let compressed = match self {
+            Encoding::Identity => data.to_vec(),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Encoding::Zstd => zstd::encode_all(data, 0)?,
+        };
+        Ok(Bytes::from(compressed))
+    }
+
+    /// the message in `data`, decompression stops and
+    /// fails with RESOURCE_EXHAUSTED past `limit` bytes
+    pub fn decompress(&self, data: &[u8], limit: usize) -> Result<Bytes, ProxyError> {
+        let invalid = |err: io::Error| {
+            ProxyError::MalformedMessage(format!("cannot decompress message: {}", err))
+        };
+        let decoder: Box<dyn Read + '_> = match self {
+            Encoding::Identity => Box::new(data),
+            Encoding::Gzip => Box::new(GzDecoder::new(data)),
+            Encoding::Deflate => Box::new(ZlibDecoder::new(data)),
+            Encoding::Zstd => {
+                Box::new(zstd::stream::read::Decoder::with_buffer(data).map_err(invalid)?)
+            }
+        };
+        let mut decompressed = Vec::new();
+        decoder
+            .take((limit as u64).saturating_add(1))
+            .read_to_end(&mut decompressed)
+            .map_err(invalid)?;
+        if decompressed.len() > limit {
+            return Err(ProxyError::MessageTooLarge(format!(
+                "message decompresses to more than {} bytes",
+                limit
+            )));
+        }
+        Ok(Bytes::from(decompressed))
+    }
+}
+
+/// supported encodings listed in `grpc-accept-encoding`,
+/// in the client order
+pub fn accepted_encodings(headers: &HeaderMap) -> Vec<Encoding> {
+    headers
+        .get_all(GRPC_ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(Encoding::from_name)
+        .collect()
+}
+
+/// ```yaml
+/// compression:
+///   - prefix: /helloworld.Greeter/
+///     upstream_encoding: gzip
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CompressionRule {
+    /// calls whose path starts with it
+    pub prefix: String,
+    /// encoding of the messages sent to the upstream,
+    /// the client one is kept when missing
+    pub upstream_encoding: Option<Encoding>,
+}
+
+impl Default for CompressionRule {
+    fn default() -> Self {
+        Self {
+            prefix: "/".into(),
+            upstream_encoding: None,
+        }
+    }
+}
+
+impl CompressionRule {
+    pub fn matches(&self, path: &str) -> bool {
+        path.starts_with(&self.prefix)
+    }
+}
+
+/// Encodings of one call
+pub struct Recompression {
+    /// encoding of the client messages
+    client: Encoding,
+    /// encodings the client can read
+    accepted: Vec<Encoding>,
+    upstream: Option<Encoding>,
+}
+
+impl Recompression {
+    /// a client encoding Griffin cannot read is UNIMPLEMENTED
+    pub(crate) fn new(rule: &CompressionRule, headers: &HeaderMap) -> Result<Self, ProxyError> {
+        Ok(Self {
+            client: Encoding::from_headers(headers)?,
+            accepted: accepted_encodings(headers),
+            upstream: rule.upstream_encoding,
+        })
+    }
+
+    /// recompress the client messages for the upstream,
+    /// a message decompressing past `limit` fails the request
+    /// body and is kept in the violation
+    pub(crate) fn request(
+        &self,
+        req: &mut Request<UpstreamBody>,
+        limit: Option<usize>,
+        violation: &Violation,
+    ) {
+        let target = self.upstream.unwrap_or(self.client);
+        if target == self.client {
+            return;
+        }
+        set_encoding(req.headers_mut(), target);
+        req.headers_mut().remove(header::CONTENT_LENGTH);
+
+        let mut recoder = Recoder::new(self.client, target, limit);
+        let found = violation.clone();
+        let mut body = std::mem::take(req.body_mut());
+        let recoded = stream! {
+            while let Some(frame) = body.frame().await {
+                let recoded = match frame?.into_data() {
+                    Ok(data) => recoder.push(&data).map(Frame::data),
+                    Err(frame) => Ok(frame),
+                };
+                match recoded {
+                    Ok(frame) => yield Ok(frame),
+                    Err(err) => {
+                        yield Err(found.fail(err));
+                        return;
+                    }
+                }
+            }
+            if let Err(err) = recoder.finish() {
+                yield Err(found.fail(err));
+            }
+        };
+        *req.body_mut() = StreamBody::new(recoded).boxed_unsync();
+    }
+
+    /// recompress the upstream messages into
+    /// an encoding the client accepts, a message
+    /// that cannot be recompressed ends the call with INTERNAL,
+    /// one decompressing past `limit` with RESOURCE_EXHAUSTED
+    pub(crate) fn response(&self, res: ProxyResponse, limit: Option<usize>) -> ProxyResponse {
+        let Ok(upstream) = Encoding::from_headers(res.headers()) else {
+            return res;
+        };
+        if upstream == Encoding::Identity
+            || self.accepted.contains(&upstream)
+            || res.body().is_end_stream()
+        {
+            return res;
+        }
+        let target = self.accepted.first().copied().unwrap_or(Encoding::Identity);
+
+        let (mut parts, mut body) = res.into_parts();
+        set_encoding(&mut parts.headers, target);
+        let mut recoder = Recoder::new(upstream, target, limit);
+        let recoded = stream! {
+            while let Some(frame) = body.frame().await {
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        yield Err(err);
+                        break;
+                    }
+                };
+                let data = match frame.into_data() {
+                    Ok(data) => data,
+                    Err(frame) => {
+                        yield Ok(frame);
+                        continue;
+                    }
+                };
+                match recoder.push(&data) {
+                    Ok(data) => yield Ok(Frame::data(data)),
+                    Err(err) => {
+                        let message = format!("cannot recompress upstream message: {}", err);
+                        yield Ok(Frame::trailers(status::status_headers(err.code(), &message)));
+                        break;
+                    }
+                }
+            }
+        };
+        ProxyResponse::from_parts(parts, StreamBody::new(recoded).boxed())
+    }
+}
+
+fn set_encoding(headers: &mut HeaderMap, encoding: Encoding) {
+    match encoding {
+        Encoding::Identity => headers.remove(GRPC_ENCODING),
+        encoding => headers.insert(GRPC_ENCODING, HeaderValue::from_static(encoding.name())),
+    };
+}
+
+/// Recompresses the messages of a stream
+struct Recoder {
+    deframer: Deframer,
+    from: Encoding,
+    to: Encoding,
+    /// largest decompressed message
+    limit: usize,
+}
+
+impl Recoder {
+    /// messages are limited to [`DEFAULT_MAX_DECOMPRESSED_BYTES`]
+    /// when the call has no limit
+    fn new(from: Encoding, to: Encoding, limit: Option<usize>) -> Self {
+        Self {
+            deframer: Deframer::default(),
+            from,
+            to,
+            limit: limit.unwrap_or(DEFAULT_MAX_DECOMPRESSED_BYTES),
+        }
+    }
+
+    /// recompressed frames of the messages
+    /// completed by `data`
+    fn push(&mut self, data: &[u8]) -> Result<Bytes, ProxyError> {
+        self.deframer.push(data);
+        let mut out = BytesMut::new();
+        while let Some((flag, payload)) = self.deframer.next_frame().map_err(malformed)? {
+            if flag & TRAILERS != 0 {
+                out.extend_from_slice(&encode_frame(flag, &payload).map_err(malformed)?);
+                continue;
+            }
+            let message = if flag & COMPRESSED == 0 {
+                payload
+            } else if self.from == Encoding::Identity {
+                return Err(malformed("compressed message without grpc-encoding"));
+            } else {
+                self.from.decompress(&payload, self.limit)?
+            };
+            let frame = match self.to {
+                Encoding::Identity => encode_frame(0, &message),
+                to => encode_frame(COMPRESSED, &to.compress(&message).map_err(malformed)?),
+            };
+            out.extend_from_slice(&frame.map_err(malformed)?);
+        }
+        Ok(out.freeze())
+    }
+
+    fn finish(&self) -> Result<(), ProxyError> {
+        if self.deframer.is_empty() {
+            Ok(())
+        } else {
+            Err(malformed("truncated message"))
+        }
+    }
+}
+
+fn malformed(err: impl std::fmt::Display) -> ProxyError {
+    ProxyError::MalformedMessage(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Deframer;
+    use crate::status::Code;
+
+    #[test]
+    fn test_round_trip() {
+        let message = b"hello griffin hello griffin hello griffin";
+        for encoding in [
+            Encoding::Identity,
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Zstd,
+        ] {
+            let compressed = encoding.compress(message).unwrap();
+            assert_eq!(
+                encoding.decompress(&compressed, message.len()).unwrap(),
+                &message[..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_decompression_stops_at_limit() {
+        let message = vec![0; 1024 * 1024];
+        for encoding in [
+            Encoding::Identity,
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Zstd,
+        ] {
+            let compressed = encoding.compress(&message).unwrap();
+            let err = encoding.decompress(&compressed, 1024).unwrap_err();
+            assert_eq!(err.code(), Code::ResourceExhausted);
+        }
+    }
+
+    #[test]
+    fn test_accepted_encodings() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            GRPC_ACCEPT_ENCODING,
+            HeaderValue::from_static("snappy, zstd,gzip"),
+        );
+        assert_eq!(
+            accepted_encodings(&headers),
+            vec![Encoding::Zstd, Encoding::Gzip]
+        );
+
+        headers.insert(GRPC_ENCODING, HeaderValue::from_static("snappy"));
+        assert_eq!(
+            Encoding::from_headers(&headers).unwrap_err().code(),
+            Code::Unimplemented
+        );
+    }
+
+    #[test]
+    fn test_recoder() {
+        let gzipped = Encoding::Gzip.compress(b"hello").unwrap();
+        let mut stream = encode_frame(COMPRESSED, &gzipped).unwrap().to_vec();
+        stream.extend_from_slice(&encode_frame(0, b"plain").unwrap());
+
+        let mut recoder = Recoder::new(Encoding::Gzip, Encoding::Zstd, None);
+        let (a, b) = stream.split_at(3);
+        let mut out = recoder.push(a).unwrap().to_vec();
+        out.extend_from_slice(&recoder.push(b).unwrap());
+        recoder.finish().unwrap();
+
+        let mut deframer = Deframer::default();
+        deframer.push(&out);
+        for expected in [&b"hello"[..], b"plain"] {
+            let (flag, payload) = deframer.next_frame().unwrap().unwrap();
+            assert_eq!(flag, COMPRESSED);
+            assert_eq!(Encoding::Zstd.decompress(&payload, 5).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_compressed_message_without_encoding() {
+        let mut recoder = Recoder::new(Encoding::Identity, Encoding::Gzip, None);
+        assert!(
+            recoder
+                .push(&encode_frame(COMPRESSED, b"hello").unwrap())
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use crate::compression::CompressionRule;
+use crate::cors::CorsPolicy;
+use crate::deadline::DeadlineConfig;
+use crate::message_size::MessageSizeConfig;
+use crate::readiness::ReadinessGate;
+use crate::routing::Router;
+use crate::telemetry::metrics::Metrics;
+use crate::transcoding::Transcoder;
+use crate::upstream::cluster::Cluster;
+use crate::upstream::connection_pool::{ConnectionPool, DEFAULT_MAX_CONCURRENT_STREAMS};
+
+/// Everything a request needs to know
+/// about the listener it arrived on.
+/// Built once per listener from its config
+/// and shared by all of its connections
+pub struct ProxyContext {
+    /// replicas the listener forwards
+    /// calls matching no route to
+    pub cluster: Cluster,
+    /// routes of calls to other clusters
+    pub router: Router,
+    pub metrics: Arc<Metrics>,
+    pub cors: Option<CorsPolicy>,
+    pub pool: Arc<ConnectionPool>,
+    /// streams per upstream connection
+    /// before the pool opens another one
+    pub max_concurrent_streams: usize,
+    /// startup gate, calls are never held when missing
+    pub readiness: Option<Arc<ReadinessGate>>,
+    /// default and maximum call deadlines
+    pub deadline: DeadlineConfig,
+    /// routes whose messages are recompressed,
+    /// the first matching one is used
+    pub compression: Vec<CompressionRule>,
+    /// largest messages of calls, per route
+    pub message_size: MessageSizeConfig,
+    /// REST endpoints of annotated methods,
+    /// only gRPC calls are accepted when missing
+    pub transcoder: Option<Transcoder>,
+}
+
+impl ProxyContext {
+    pub fn new(cluster: Cluster, metrics: Arc<Metrics>) -> Self {
+        Self {
+            cluster,
+            router: Router::default(),
+            metrics,
+            cors: None,
+            pool: Arc::new(ConnectionPool::new()),
+            max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            readiness: None,
+            deadline: DeadlineConfig::default(),
+            compression: Vec::new(),
+            message_size: MessageSizeConfig::default(),
+            transcoder: None,
+        }
+    }
+
+    /// share upstream connections with other listeners
+    pub fn with_pool(mut self, pool: Arc<ConnectionPool>, max_concurrent_streams: usize) -> Self {
+        self.pool = pool;
+        self.max_concurrent_streams = max_concurrent_streams;
+        self
+    }
+
+    pub fn with_router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    pub fn with_readiness(mut self, readiness: Arc<ReadinessGate>) -> Self {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: DeadlineConfig) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Vec<CompressionRule>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_message_size(mut self, message_size: MessageSizeConfig) -> Self {
+        self.message_size = message_size;
+        self
+    }
+
+    pub fn with_transcoder(mut self, transcoder: Transcoder) -> Self {
+        self.transcoder = Some(transcoder);
+        self
+    }
+
+    pub fn with_cors(mut self, cors: CorsPolicy) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+}
@@ -0,0 +1,178 @@
+//!
+//! gRPC content types, `application/grpc`, `application/grpc-web`
+//! and `application/grpc-web-text`, each with an optional
+//! codec suffix such as `+proto` or `+json` and optional parameters.
+//! Connect streaming calls use `application/connect+codec`,
+//! Connect unary calls `application/proto` or `application/json`.
+//!
+use http::{HeaderMap, HeaderValue, header::CONTENT_TYPE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Grpc,
+    GrpcWeb,
+    GrpcWebText,
+    Connect,
+    /// the subtype is the codec
+    ConnectUnary,
+}
+
+impl Protocol {
+    fn subtype(&self) -> &'static str {
+        match self {
+            Protocol::Grpc => "grpc",
+            Protocol::GrpcWeb => "grpc-web",
+            Protocol::GrpcWebText => "grpc-web-text",
+            Protocol::Connect => "connect",
+            Protocol::ConnectUnary => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub protocol: Protocol,
+    /// message codec, proto when missing
+    pub codec: Option<String>,
+}
+
+impl ContentType {
+    /// Type and subtype are case-insensitive,
+    /// parameters such as `charset` are ignored
+    pub fn parse(value: &HeaderValue) -> Option<Self> {
+        let essence = value.to_str().ok()?.split(';').next()?.trim();
+        let essence = essence.to_ascii_lowercase();
+        let subtype = essence.strip_prefix("application/")?;
+        let (subtype, codec) = match subtype.split_once('+') {
+            Some((subtype, codec)) if is_token(codec) => (subtype, Some(codec.to_string())),
+            Some(_) => return None,
+            None => (subtype, None),
+        };
+        let protocol = match subtype {
+            "grpc" => Protocol::Grpc,
+            "grpc-web" => Protocol::GrpcWeb,
+            "grpc-web-text" => Protocol::GrpcWebText,
+            "connect" => Protocol::Connect,
+            "proto" | "json" if codec.is_none() => {
+                return Some(Self {
+                    protocol: Protocol::ConnectUnary,
+                    codec: Some(subtype.to_string()),
+                });
+            }
+            _ => return None,
+        };
+        Some(Self { protocol, codec })
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers.get(CONTENT_TYPE).and_then(Self::parse)
+    }
+
+    pub fn to_header_value(&self) -> HeaderValue {
+        let codec = self.codec.as_deref();
+        let value = match (self.protocol, codec) {
+            (Protocol::ConnectUnary, codec) => format!("application/{}", codec.unwrap_or("proto")),
+            (protocol, Some(codec)) => format!("application/{}+{}", protocol.subtype(), codec),
+            (protocol, None) => format!("application/{}", protocol.subtype()),
+        };
+        HeaderValue::from_str(&value).expect("parsed from a header value")
+    }
+}
+
+/// content type of a gRPC-Web response, the codec
+/// comes from the upstream response, then from the request
+pub(crate) fn web_response_content_type(
+    protocol: Protocol,
+    upstream: &HeaderMap,
+    requested: Option<&str>,
+) -> HeaderValue {
+    let codec = ContentType::from_headers(upstream)
+        .and_then(|content_type| content_type.codec)
+        .or(requested.map(str::to_string))
+        .unwrap_or_else(|| "proto".into());
+    ContentType {
+        protocol,
+        codec: Some(codec),
+    }
+    .to_header_value()
+}
+
+/// RFC 9110 token
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &'static str) -> Option<ContentType> {
+        ContentType::parse(&HeaderValue::from_static(value))
+    }
+
+    #[test]
+    fn test_parse() {
+        let web_json = ContentType {
+            protocol: Protocol::GrpcWeb,
+            codec: Some("json".into()),
+        };
+        assert_eq!(parse("application/grpc-web+json"), Some(web_json.clone()));
+        assert_eq!(
+            parse("Application/GRPC-Web+JSON; charset=utf-8"),
+            Some(web_json)
+        );
+        assert_eq!(
+            parse("application/grpc"),
+            Some(ContentType {
+                protocol: Protocol::Grpc,
+                codec: None
+            })
+        );
+        assert_eq!(
+            parse("application/grpc-web-text+thrift")
+                .unwrap()
+                .codec
+                .as_deref(),
+            Some("thrift")
+        );
+        assert_eq!(
+            parse("application/json; charset=utf-8"),
+            Some(ContentType {
+                protocol: Protocol::ConnectUnary,
+                codec: Some("json".into())
+            })
+        );
+        assert_eq!(
+            parse("application/connect+proto").unwrap().protocol,
+            Protocol::Connect
+        );
+        assert_eq!(parse("application/grpc-web+"), None);
+        assert_eq!(parse("application/proto+json"), None);
+        assert_eq!(parse("application/grpc-webby"), None);
+        assert_eq!(parse("application/xml"), None);
+    }
+
+    #[test]
+    fn test_web_response_content_type() {
+        let mut upstream = HeaderMap::new();
+        assert_eq!(
+            web_response_content_type(Protocol::GrpcWeb, &upstream, None),
+            "application/grpc-web+proto"
+        );
+        assert_eq!(
+            web_response_content_type(Protocol::GrpcWeb, &upstream, Some("json")),
+            "application/grpc-web+json"
+        );
+        upstream.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc+cbor"),
+        );
+        assert_eq!(
+            web_response_content_type(Protocol::GrpcWebText, &upstream, Some("json")),
+            "application/grpc-web-text+cbor"
+        );
+    }
+}
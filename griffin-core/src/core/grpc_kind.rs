@@ -1,49 +1,147 @@
 use bytes::Bytes;
-use http::{HeaderValue, Request, Response};
-use http_body_util::combinators::BoxBody;
-use hyper::client::conn::http2;
+use http::{HeaderMap, HeaderValue, Request, Response, header::CONTENT_TYPE};
+use http_body::Frame;
+use http_body_util::{BodyExt, combinators::BoxBody};
 use tower::BoxError;
 
-use crate::core::{grpc_kind_plain::GrpcKindPlain, grpc_kind_web::GrpcKindWeb};
+use crate::ProxyResponse;
+use crate::compression::Recompression;
+use crate::core::content_type::{ContentType, Protocol};
+use crate::core::{
+    grpc_kind_connect::GrpcKindConnect, grpc_kind_json::GrpcKindJson,
+    grpc_kind_plain::GrpcKindPlain, grpc_kind_web::GrpcKindWeb,
+    grpc_kind_web_text::GrpcKindWebText,
+};
+use crate::error::ProxyError;
+use crate::message_size::{MessageLimits, Violation};
+use crate::mirror::Mirror;
+use crate::status::Code;
+use crate::telemetry::cluster_call::ClusterCall;
+use crate::upstream::cluster::UpstreamProtocol;
+use crate::upstream::connection_pool::UpstreamSender;
+use crate::upstream::grpc_web;
 
 pub enum GrpcKind {
     Web(GrpcKindWeb),
+    WebText(GrpcKindWebText),
     Plain(GrpcKindPlain),
+    Connect(GrpcKindConnect),
+    /// transcoded HTTP/JSON calls,
+    /// never picked from the content type
+    Json(GrpcKindJson),
 }
 impl GrpcKind {
     pub fn from_content_type(content_type: &HeaderValue) -> Option<Self> {
-        if content_type == "application/grpc" {
-            Some(GrpcKind::Plain(GrpcKindPlain))
-        } else if content_type == "application/grpc-web"
-            || content_type == "application/grpc-web+proto"
-        {
-            Some(GrpcKind::Web(GrpcKindWeb))
-        } else {
-            None
-        }
+        let ContentType { protocol, codec } = ContentType::parse(content_type)?;
+        let kind = match protocol {
+            Protocol::Grpc => GrpcKind::Plain(GrpcKindPlain),
+            Protocol::GrpcWeb => GrpcKind::Web(GrpcKindWeb { codec }),
+            Protocol::GrpcWebText => GrpcKind::WebText(GrpcKindWebText { codec }),
+            Protocol::Connect | Protocol::ConnectUnary => GrpcKind::Connect(GrpcKindConnect {
+                codec: codec.unwrap_or_else(|| "proto".into()),
+                streaming: protocol == Protocol::Connect,
+            }),
+        };
+        Some(kind)
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, ProxyError> {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .ok_or(ProxyError::MissingContentType)?;
+        Self::from_content_type(content_type)
+            .ok_or_else(|| ProxyError::UnsupportedContentType(content_type.clone()))
     }
+
     pub async fn forward<B>(
-        self,
-        mut sender: http2::SendRequest<B>,
-        mut req: Request<B>,
-    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, BoxError>
+        &self,
+        sender: UpstreamSender,
+        req: Request<B>,
+        recompression: Option<&Recompression>,
+        limits: MessageLimits,
+        mut call: ClusterCall,
+        mirror: Option<Mirror>,
+    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, ProxyError>
     where
         B: hyper::body::Body<Data = Bytes> + Send + 'static,
         B::Error: Into<BoxError>,
     {
+        let mut req = req.map(|body| body.map_err(Into::into).boxed_unsync());
+        let violation = Violation::default();
         match self {
-            GrpcKind::Web(ref kind) => kind.modify_request(&mut req),
+            GrpcKind::Web(kind) => kind.modify_request(&mut req),
+            GrpcKind::WebText(kind) => kind.modify_request(&mut req),
+            GrpcKind::Connect(kind) => kind.modify_request(&mut req, limits.request, &violation),
+            GrpcKind::Json(kind) => kind.modify_request(&mut req),
             GrpcKind::Plain(_) => {}
         }
+        limits.request(&mut req, &violation);
+        if let Some(recompression) = recompression {
+            recompression.request(&mut req, limits.request, &violation);
+        }
+        // the mirror gets the call as the upstream does
+        if let Some(mirror) = mirror {
+            mirror.tee(&mut req);
+        }
+        let protocol = sender.protocol();
+        if protocol == UpstreamProtocol::GrpcWeb {
+            grpc_web::modify_request(&mut req);
+        }
 
         let res = sender
             .send_request(req)
             .await
-            .map_err(Into::<BoxError>::into)?;
+            .map_err(|err| call.fail(violation.take().unwrap_or(ProxyError::Upstream(err))))?
+            .map(BodyExt::boxed);
+        let res = match protocol {
+            UpstreamProtocol::Grpc => res,
+            UpstreamProtocol::GrpcWeb => grpc_web::modify_response(res),
+        };
+        let res = call.observe(limits.response(res, violation));
+        let res = match recompression {
+            Some(recompression) => recompression.response(res, limits.response),
+            None => res,
+        };
+
+        match self {
+            GrpcKind::Plain(kind) => Ok(kind.modify_response(res)),
+            GrpcKind::Web(kind) => Ok(kind.modify_response(res)),
+            GrpcKind::WebText(kind) => Ok(kind.modify_response(res)),
+            GrpcKind::Connect(kind) => Ok(kind.modify_response(res, limits.response).await),
+            GrpcKind::Json(kind) => Ok(kind.modify_response(res, limits.response).await),
+        }
+    }
 
+    /// a response carrying only `code`,
+    /// in the form clients of this kind read it
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
         match self {
-            GrpcKind::Plain(ref kind) => Ok(kind.modify_response(res)),
-            GrpcKind::Web(ref kind) => Ok(kind.modify_response(res)),
+            GrpcKind::Plain(kind) => kind.status_response(code, message),
+            GrpcKind::Web(kind) => kind.status_response(code, message),
+            GrpcKind::WebText(kind) => kind.status_response(code, message),
+            GrpcKind::Connect(kind) => kind.status_response(code, message),
+            GrpcKind::Json(kind) => kind.status_response(code, message),
+        }
+    }
+
+    /// last frame of a response that ends with `code`
+    /// after the upstream headers were sent,
+    /// `None` when responses of this kind are complete once sent
+    pub fn status_frame(&self, code: Code, message: &str) -> Option<Frame<Bytes>> {
+        match self {
+            GrpcKind::Plain(kind) => Some(kind.status_frame(code, message)),
+            GrpcKind::Web(kind) => Some(kind.status_frame(code, message)),
+            GrpcKind::WebText(kind) => Some(kind.status_frame(code, message)),
+            GrpcKind::Connect(kind) => kind.status_frame(code, message),
+            GrpcKind::Json(_) => None,
+        }
+    }
+
+    /// rewrite the request headers of other protocols
+    /// into their gRPC form
+    pub fn modify_headers(&self, headers: &mut HeaderMap) {
+        if let GrpcKind::Connect(kind) = self {
+            kind.modify_headers(headers);
         }
     }
 }
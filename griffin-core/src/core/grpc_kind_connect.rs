@@ -0,0 +1,536 @@
+//!
+//! Connect protocol clients, translated to gRPC upstream.
+//! Unary calls carry a bare message and get errors as JSON bodies,
+//! streaming calls use gRPC framing and end with a JSON
+//! end-of-stream message instead of trailers.
+//! <https://connectrpc.com/docs/protocol>
+//!
+use std::convert::Infallible;
+use std::time::Duration;
+
+use async_stream::stream;
+use base64::{
+    Engine, alphabet,
+    engine::{
+        DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig, general_purpose::STANDARD_NO_PAD,
+    },
+};
+use bytes::{Bytes, BytesMut};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Request, Response,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use serde_json::{Map, Value, json};
+
+use crate::compression::{GRPC_ACCEPT_ENCODING, GRPC_ENCODING};
+use crate::core::content_type::{ContentType, Protocol};
+use crate::core::grpc_kind_web::trailers_only;
+use crate::deadline::{GRPC_TIMEOUT, encode_timeout};
+use crate::error::{ProxyError, upstream_error_code};
+use crate::frame::{COMPRESSED, Deframer, encode_frame};
+use crate::message_size::Violation;
+use crate::protobuf::{Field, next_field};
+use crate::status::{self, Code};
+use crate::{ProxyResponse, UpstreamBody};
+
+const CONNECT_PROTOCOL_VERSION: &str = "connect-protocol-version";
+const CONNECT_TIMEOUT_MS: &str = "connect-timeout-ms";
+const CONNECT_CONTENT_ENCODING: &str = "connect-content-encoding";
+const CONNECT_ACCEPT_ENCODING: &str = "connect-accept-encoding";
+/// flag of the end-of-stream message
+const END_STREAM: u8 = 0x02;
+
+/// status keys, they never end up in Connect metadata
+const STATUS_KEYS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+pub struct GrpcKindConnect {
+    /// message codec, `proto` or `json`
+    pub codec: String,
+    /// `application/connect+codec`,
+    /// unary calls use `application/codec`
+    pub streaming: bool,
+}
+
+impl GrpcKindConnect {
+    /// Connect request headers in their gRPC form,
+    /// rewritten before the deadline and compression
+    /// rules look at the call
+    pub fn modify_headers(&self, headers: &mut HeaderMap) {
+        // at most 10 digits, in milliseconds
+        if let Some(timeout) = headers.remove(CONNECT_TIMEOUT_MS)
+            && timeout.len() <= 10
+            && let Some(ms) = timeout.to_str().ok().and_then(|ms| ms.parse().ok())
+        {
+            headers.insert(GRPC_TIMEOUT, encode_timeout(Duration::from_millis(ms)));
+        }
+        let (encoding, accept_encoding) = if self.streaming {
+            (
+                HeaderName::from_static(CONNECT_CONTENT_ENCODING),
+                HeaderName::from_static(CONNECT_ACCEPT_ENCODING),
+            )
+        } else {
+            (CONTENT_ENCODING, ACCEPT_ENCODING)
+        };
+        if let Some(encoding) = headers.remove(encoding) {
+            headers.insert(GRPC_ENCODING, encoding);
+        }
+        if let Some(accept_encoding) = headers.remove(accept_encoding) {
+            headers.insert(GRPC_ACCEPT_ENCODING, accept_encoding);
+        }
+        headers.remove(CONNECT_PROTOCOL_VERSION);
+    }
+
+    /// a unary body is buffered up to `limit`,
+    /// a larger one fails and is kept in the violation
+    pub(crate) fn modify_request(
+        &self,
+        req: &mut Request<UpstreamBody>,
+        limit: Option<usize>,
+        violation: &Violation,
+    ) {
+        let content_type = ContentType {
+            protocol: Protocol::Grpc,
+            codec: Some(self.codec.clone()),
+        };
+        req.headers_mut()
+            .insert(CONTENT_TYPE, content_type.to_header_value());
+        req.headers_mut().remove(CONTENT_LENGTH);
+        if self.streaming {
+            // same framing as gRPC
+            return;
+        }
+
+        // the bare message becomes a single gRPC frame
+        let flag = match req.headers().get(GRPC_ENCODING) {
+            Some(encoding) if encoding != "identity" => COMPRESSED,
+            _ => 0,
+        };
+        let found = violation.clone();
+        let mut body = std::mem::take(req.body_mut());
+        let framed = stream! {
+            let mut message = BytesMut::new();
+            let framed = loop {
+                let Some(frame) = body.frame().await else {
+                    break encode_frame(flag, &message)
+                        .map_err(|err| ProxyError::MessageTooLarge(format!("request {}", err)));
+                };
+                let Ok(data) = frame?.into_data() else {
+                    continue;
+                };
+                if let Some(limit) = limit
+                    && message.len() + data.len() > limit
+                {
+                    break Err(ProxyError::MessageTooLarge(format!(
+                        "request message is larger than the limit of {} bytes",
+                        limit
+                    )));
+                }
+                message.extend_from_slice(&data);
+            };
+            match framed {
+                Ok(framed) => yield Ok(Frame::data(framed)),
+                Err(err) => yield Err(found.fail(err)),
+            }
+        };
+        *req.body_mut() = StreamBody::new(framed).boxed_unsync();
+    }
+
+    /// unary responses keep a message of at most `limit` bytes
+    pub async fn modify_response(&self, res: ProxyResponse, limit: Option<usize>) -> ProxyResponse {
+        if self.streaming {
+            self.streaming_response(res)
+        } else {
+            self.unary_response(res, limit).await
+        }
+    }
+
+    /// The whole upstream response is read,
+    /// the HTTP status depends on the gRPC status
+    /// that only arrives at the end.
+    /// Only the first message is kept, one longer
+    /// than `limit` ends the call with RESOURCE_EXHAUSTED
+    async fn unary_response(&self, res: ProxyResponse, limit: Option<usize>) -> ProxyResponse {
+        let (parts, mut body) = res.into_parts();
+        let mut trailers = trailers_only(&parts.headers);
+        let mut message = None;
+        if trailers.is_none() {
+            let mut deframer = Deframer::with_max_len(limit);
+            while let Some(frame) = body.frame().await {
+                match frame.map(Frame::into_data) {
+                    // a unary call has one message, the rest is dropped
+                    Ok(Ok(_)) if message.is_some() => {}
+                    Ok(Ok(data)) => {
+                        deframer.push(&data);
+                        match deframer.next_frame() {
+                            Ok(frame) => message = frame,
+                            Err(err) => {
+                                let err = ProxyError::MessageTooLarge(format!("response {}", err));
+                                trailers =
+                                    Some(status::status_headers(err.code(), &err.to_string()));
+                                break;
+                            }
+                        }
+                    }
+                    Ok(Err(frame)) => trailers = frame.into_trailers().ok(),
+                    Err(err) => {
+                        let code = upstream_error_code(&err);
+                        trailers = Some(status::status_headers(code, &err.to_string()));
+                        break;
+                    }
+                }
+            }
+        }
+        let trailers = trailers.unwrap_or_default();
+        let encoding = parts.headers.get(GRPC_ENCODING).cloned();
+
+        let mut headers = leading_metadata(parts.headers);
+        for (name, value) in metadata(&trailers) {
+            if let Ok(name) = HeaderName::from_bytes(format!("trailer-{}", name).as_bytes()) {
+                headers.append(name, value.clone());
+            }
+        }
+
+        let (code, error) = trailer_status(&trailers);
+        let mut res = match error {
+            None => {
+                let (flag, message) = message.unwrap_or_default();
+                if flag & COMPRESSED != 0
+                    && let Some(encoding) = encoding
+                {
+                    headers.insert(CONTENT_ENCODING, encoding);
+                }
+                let content_type = ContentType {
+                    protocol: Protocol::ConnectUnary,
+                    codec: Some(self.codec.clone()),
+                };
+                headers.insert(CONTENT_TYPE, content_type.to_header_value());
+                Response::new(full_body(message))
+            }
+            Some(error) => {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                let mut res = Response::new(full_body(Bytes::from(error.to_string())));
+                *res.status_mut() = status::http_status(code);
+                res
+            }
+        };
+        *res.headers_mut() = headers;
+        res
+    }
+
+    /// messages pass through,
+    /// trailers become the end-of-stream message
+    fn streaming_response(&self, res: ProxyResponse) -> ProxyResponse {
+        let (mut parts, mut body) = res.into_parts();
+        let trailers = trailers_only(&parts.headers);
+        if let Some(encoding) = parts.headers.remove(GRPC_ENCODING) {
+            parts.headers.insert(CONNECT_CONTENT_ENCODING, encoding);
+        }
+        if let Some(accept_encoding) = parts.headers.remove(GRPC_ACCEPT_ENCODING) {
+            parts
+                .headers
+                .insert(CONNECT_ACCEPT_ENCODING, accept_encoding);
+        }
+        let mut headers = leading_metadata(parts.headers.clone());
+        headers.insert(CONTENT_TYPE, self.streaming_content_type());
+        parts.headers = headers;
+
+        let body = match trailers {
+            Some(trailers) => full_body(end_stream_frame(&trailers)),
+            None => {
+                let frames = stream! {
+                    while let Some(frame) = body.frame().await {
+                        match frame {
+                            Ok(frame) => match frame.into_trailers() {
+                                Ok(trailers) => {
+                                    yield Ok(Frame::data(end_stream_frame(&trailers)));
+                                }
+                                Err(frame) => yield Ok(frame),
+                            },
+                            Err(err) => {
+                                let code = upstream_error_code(&err);
+                                let trailers = status::status_headers(code, &err.to_string());
+                                yield Ok(Frame::data(end_stream_frame(&trailers)));
+                                break;
+                            }
+                        }
+                    }
+                };
+                StreamBody::new(frames).boxed()
+            }
+        };
+        Response::from_parts(parts, body)
+    }
+
+    /// a JSON error for unary calls,
+    /// an end-of-stream message for streaming calls
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
+        let trailers = status::status_headers(code, message);
+        if self.streaming {
+            let mut res = Response::new(full_body(end_stream_frame(&trailers)));
+            res.headers_mut()
+                .insert(CONTENT_TYPE, self.streaming_content_type());
+            return res;
+        }
+        let error = trailer_status(&trailers).1.unwrap_or_default();
+        let mut res = Response::new(full_body(Bytes::from(error.to_string())));
+        *res.status_mut() = status::http_status(code);
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        res
+    }
+
+    /// unary responses are complete when sent,
+    /// only streaming calls can end with a status later on
+    pub fn status_frame(&self, code: Code, message: &str) -> Option<Frame<Bytes>> {
+        self.streaming
+            .then(|| Frame::data(end_stream_frame(&status::status_headers(code, message))))
+    }
+
+    fn streaming_content_type(&self) -> HeaderValue {
+        ContentType {
+            protocol: Protocol::Connect,
+            codec: Some(self.codec.clone()),
+        }
+        .to_header_value()
+    }
+}
+
+fn full_body(data: Bytes) -> http_body_util::combinators::BoxBody<Bytes, hyper::Error> {
+    Full::new(data)
+        .map_err(|never: Infallible| match never {})
+        .boxed()
+}
+
+/// upstream response headers without
+/// the ones that only make sense in gRPC
+pub(crate) fn leading_metadata(mut headers: HeaderMap) -> HeaderMap {
+    for name in STATUS_KEYS
+        .iter()
+        .chain(&[GRPC_ENCODING, GRPC_ACCEPT_ENCODING])
+    {
+        headers.remove(*name);
+    }
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    headers
+}
+
+/// trailers without the status keys
+fn metadata(trailers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+    trailers
+        .iter()
+        .filter(|(name, _)| !STATUS_KEYS.contains(&name.as_str()))
+}
+
+/// status code and Connect error of trailers,
+/// a missing `grpc-status` is UNKNOWN
+fn trailer_status(trailers: &HeaderMap) -> (Code, Option<Value>) {
+    let (code, message) = status::status_of(trailers);
+    if code == Code::Ok {
+        return (code, None);
+    }
+
+    let mut error = Map::new();
+    error.insert("code".into(), connect_code(code).into());
+    if let Some(message) = message {
+        error.insert("message".into(), message.into());
+    }
+    let details = trailers
+        .get("grpc-status-details-bin")
+        .map(|details| error_details(details.as_bytes()))
+        .unwrap_or_default();
+    if !details.is_empty() {
+        error.insert("details".into(), details.into());
+    }
+    (code, Some(Value::Object(error)))
+}
+
+/// `google.rpc.Status.details` as Connect error details,
+/// malformed details are dropped
+fn error_details(details: &[u8]) -> Vec<Value> {
+    let engine = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
+    let Ok(status) = engine.decode(details) else {
+        return Vec::new();
+    };
+    let mut status = Bytes::from(status);
+    let mut details = Vec::new();
+    while let Ok(Some((number, field))) = next_field(&mut status) {
+        // repeated google.protobuf.Any details = 3
+        if let (3, Field::Bytes(mut any)) = (number, field) {
+            let (mut type_url, mut value) = (String::new(), Bytes::new());
+            while let Ok(Some((number, field))) = next_field(&mut any) {
+                match (number, field) {
+                    (1, Field::Bytes(bytes)) => {
+                        type_url = String::from_utf8_lossy(&bytes).into_owned()
+                    }
+                    (2, Field::Bytes(bytes)) => value = bytes,
+                    _ => {}
+                }
+            }
+            // Connect uses the bare message name
+            let name = type_url.rsplit('/').next().unwrap_or_default();
+            details.push(json!({
+                "type": name,
+                "value": STANDARD_NO_PAD.encode(&value),
+            }));
+        }
+    }
+    details
+}
+
+/// the end-of-stream message of a streaming call
+fn end_stream_frame(trailers: &HeaderMap) -> Bytes {
+    let mut metadata = Map::new();
+    for (name, value) in self::metadata(trailers) {
+        let values = metadata
+            .entry(name.as_str())
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let (Value::Array(values), Ok(value)) = (values, value.to_str()) {
+            values.push(value.into());
+        }
+    }
+    let mut message = Map::new();
+    if let (_, Some(error)) = trailer_status(trailers) {
+        message.insert("error".into(), error);
+    }
+    if !metadata.is_empty() {
+        message.insert("metadata".into(), Value::Object(metadata));
+    }
+    // made of header values, far below the frame limit
+    encode_frame(END_STREAM, Value::Object(message).to_string().as_bytes())
+        .expect("end-of-stream message fits a frame")
+}
+
+fn connect_code(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "ok",
+        Code::Cancelled => "canceled",
+        Code::Unknown => "unknown",
+        Code::InvalidArgument => "invalid_argument",
+        Code::DeadlineExceeded => "deadline_exceeded",
+        Code::NotFound => "not_found",
+        Code::AlreadyExists => "already_exists",
+        Code::PermissionDenied => "permission_denied",
+        Code::ResourceExhausted => "resource_exhausted",
+        Code::FailedPrecondition => "failed_precondition",
+        Code::Aborted => "aborted",
+        Code::OutOfRange => "out_of_range",
+        Code::Unimplemented => "unimplemented",
+        Code::Internal => "internal",
+        Code::Unavailable => "unavailable",
+        Code::Unauthenticated => "unauthenticated",
+        Code::DataLoss => "data_loss",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protobuf::put_varint;
+    use base64::engine::general_purpose::STANDARD;
+    use bytes::BufMut;
+    use http::StatusCode;
+
+    fn unary() -> GrpcKindConnect {
+        GrpcKindConnect {
+            codec: "proto".into(),
+            streaming: false,
+        }
+    }
+
+    #[test]
+    fn test_modify_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECT_TIMEOUT_MS, HeaderValue::from_static("1500"));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        headers.insert(CONNECT_PROTOCOL_VERSION, HeaderValue::from_static("1"));
+        unary().modify_headers(&mut headers);
+
+        assert_eq!(headers[GRPC_TIMEOUT], "1500000u");
+        assert_eq!(headers[GRPC_ENCODING], "gzip");
+        assert!(headers.get(CONTENT_ENCODING).is_none());
+        assert!(headers.get(CONNECT_PROTOCOL_VERSION).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unary_error_is_json() {
+        let res = unary().status_response(Code::Unavailable, "upstream not ready");
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            error,
+            json!({"code": "unavailable", "message": "upstream not ready"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unary_response_keeps_one_message() {
+        let mut messages = encode_frame(0, b"hello").unwrap().to_vec();
+        messages.extend_from_slice(&encode_frame(0, b"ignored").unwrap());
+        let upstream = || {
+            let frames = [
+                Frame::data(Bytes::from(messages.clone())),
+                Frame::trailers(status::status_headers(Code::Ok, "")),
+            ];
+            let frames = futures_util::stream::iter(frames.map(Ok::<_, hyper::Error>));
+            Response::new(StreamBody::new(frames).boxed())
+        };
+
+        let res = unary().modify_response(upstream(), Some(5)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+
+        let res = unary().modify_response(upstream(), Some(4)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_end_stream_message() {
+        let mut trailers = status::status_headers(Code::NotFound, "no 100%");
+        trailers.append("x-reason", HeaderValue::from_static("a"));
+        trailers.append("x-reason", HeaderValue::from_static("b"));
+        let frame = end_stream_frame(&trailers);
+
+        assert_eq!(frame[0], END_STREAM);
+        let message: Value = serde_json::from_slice(&frame[5..]).unwrap();
+        assert_eq!(
+            message,
+            json!({
+                "error": {"code": "not_found", "message": "no 100%"},
+                "metadata": {"x-reason": ["a", "b"]},
+            })
+        );
+
+        let ok = end_stream_frame(&status::status_headers(Code::Ok, ""));
+        assert_eq!(&ok[5..], b"{}");
+    }
+
+    #[test]
+    fn test_error_details() {
+        // google.rpc.Status { details: [Any { type_url, value }] }
+        let mut any = BytesMut::new();
+        let type_url = b"type.googleapis.com/google.rpc.RetryInfo";
+        any.put_u8(0x0a);
+        put_varint(&mut any, type_url.len() as u64);
+        any.put_slice(type_url);
+        any.put_slice(&[0x12, 0x02, 0x08, 0x01]);
+        let mut status = BytesMut::from(&[0x08, 0x0e][..]);
+        status.put_u8(0x1a);
+        put_varint(&mut status, any.len() as u64);
+        status.put(any);
+
+        let details = error_details(STANDARD.encode(&status).as_bytes());
+        assert_eq!(
+            details,
+            vec![json!({"type": "google.rpc.RetryInfo", "value": "CAE"})]
+        );
+    }
+}
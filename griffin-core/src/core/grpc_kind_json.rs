@@ -0,0 +1,221 @@
+//!
+//! HTTP/JSON clients of transcoded calls,
+//! see [`crate::transcoding`] for the request side.
+//! Messages are answered as JSON, failed calls with
+//! the HTTP status of their code and a JSON `google.rpc.Status`.
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use http::{
+    HeaderMap, HeaderValue, Response,
+    header::{CONTENT_TYPE, HeaderName},
+};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use prost_reflect::{DynamicMessage, MessageDescriptor, SerializeOptions};
+use serde_json::{Value, json};
+
+use crate::compression::{DEFAULT_MAX_DECOMPRESSED_BYTES, Encoding};
+use crate::core::grpc_kind_connect::leading_metadata;
+use crate::core::grpc_kind_web::trailers_only;
+use crate::error::{ProxyError, upstream_error_code};
+use crate::frame::{COMPRESSED, Deframer};
+use crate::status::{self, Code};
+use crate::telemetry::metrics::from_full_bytes;
+use crate::{ProxyResponse, UpstreamBody};
+
+pub struct GrpcKindJson {
+    /// output message of the method
+    pub output: MessageDescriptor,
+    /// field of the output sent as the response body,
+    /// the whole message when missing
+    pub response_body: Option<String>,
+    /// server streaming methods answer with a JSON array
+    pub streaming: bool,
+}
+
+impl GrpcKindJson {
+    /// the request was already transcoded,
+    /// its body is a single gRPC frame
+    pub fn modify_request(&self, _req: &mut http::Request<UpstreamBody>) {}
+
+    /// upstream messages are at most `limit` bytes,
+    /// compressed or decompressed, before they are converted
+    pub async fn modify_response(&self, res: ProxyResponse, limit: Option<usize>) -> ProxyResponse {
+        let (parts, mut body) = res.into_parts();
+        if let Some(trailers) = trailers_only(&parts.headers) {
+            return self.trailers_response(&trailers);
+        }
+        let encoding = Encoding::from_headers(&parts.headers);
+        let headers = leading_metadata(parts.headers);
+        let converter = Converter {
+            output: self.output.clone(),
+            response_body: self.response_body.clone(),
+            encoding: encoding.ok(),
+            limit: limit.unwrap_or(DEFAULT_MAX_DECOMPRESSED_BYTES),
+        };
+
+        if self.streaming {
+            let mut deframer = Deframer::default();
+            let elements = stream! {
+                yield Ok(Frame::data(Bytes::from_static(b"[")));
+                let mut first = true;
+                let mut separator = || if std::mem::take(&mut first) { "" } else { "," };
+                while let Some(frame) = body.frame().await {
+                    let trailers = match frame.map(Frame::into_data) {
+                        Ok(Ok(data)) => {
+                            deframer.push(&data);
+                            let mut elements = String::new();
+                            while let Ok(Some((flag, payload))) = deframer.next_frame() {
+                                let element = converter
+                                    .to_json(flag, payload)
+                                    .unwrap_or_else(|err| status_json(err.code(), &err.to_string()));
+                                elements.push_str(separator());
+                                elements.push_str(&element.to_string());
+                            }
+                            yield Ok(Frame::data(Bytes::from(elements)));
+                            continue;
+                        }
+                        Ok(Err(frame)) => frame.into_trailers().unwrap_or_default(),
+                        Err(err) => status::status_headers(upstream_error_code(&err), &err.to_string()),
+                    };
+                    // a failed stream ends with its status
+                    let (code, message) = status::status_of(&trailers);
+                    if code != Code::Ok {
+                        let element = status_json(code, message.as_deref().unwrap_or_default());
+                        yield Ok(Frame::data(Bytes::from(format!("{}{}", separator(), element))));
+                    }
+                    break;
+                }
+                yield Ok(Frame::data(Bytes::from_static(b"]")));
+            };
+            let mut res = Response::new(StreamBody::new(elements).boxed());
+            *res.headers_mut() = headers;
+            res.headers_mut().insert(CONTENT_TYPE, json_content_type());
+            return res;
+        }
+
+        // the HTTP status depends on the gRPC status
+        // that only arrives at the end, only the first
+        // message is kept and it is at most `limit` bytes
+        let mut deframer = Deframer::with_max_len(limit);
+        let mut message = None;
+        let mut trailers = HeaderMap::new();
+        while let Some(frame) = body.frame().await {
+            match frame.map(Frame::into_data) {
+                // a unary call has one message, the rest is dropped
+                Ok(Ok(_)) if message.is_some() => {}
+                Ok(Ok(data)) => {
+                    deframer.push(&data);
+                    match deframer.next_frame() {
+                        Ok(frame) => message = frame,
+                        Err(err) => {
+                            let err = ProxyError::MessageTooLarge(format!("response {}", err));
+                            trailers = status::status_headers(err.code(), &err.to_string());
+                            break;
+                        }
+                    }
+                }
+                Ok(Err(frame)) => trailers = frame.into_trailers().unwrap_or_default(),
+                Err(err) => {
+                    trailers = status::status_headers(upstream_error_code(&err), &err.to_string());
+                    break;
+                }
+            }
+        }
+        if status::status_of(&trailers).0 != Code::Ok {
+            return self.trailers_response(&trailers);
+        }
+        let (flag, payload) = message.unwrap_or_default();
+        match converter.to_json(flag, payload) {
+            Ok(json) => {
+                let mut res = from_full_bytes(Full::new(Bytes::from(json.to_string())));
+                *res.headers_mut() = headers;
+                for (name, value) in trailer_headers(&trailers) {
+                    res.headers_mut().append(name, value);
+                }
+                res.headers_mut().insert(CONTENT_TYPE, json_content_type());
+                res
+            }
+            Err(err) => self.status_response(err.code(), &err.to_string()),
+        }
+    }
+
+    fn trailers_response(&self, trailers: &HeaderMap) -> ProxyResponse {
+        let (code, message) = status::status_of(trailers);
+        self.status_response(code, message.as_deref().unwrap_or_default())
+    }
+
+    /// `google.rpc.Status` in JSON
+    /// with the HTTP status of `code`
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
+        let body = status_json(code, message).to_string();
+        let mut res = from_full_bytes(Full::new(Bytes::from(body)));
+        *res.status_mut() = status::http_status(code);
+        res.headers_mut().insert(CONTENT_TYPE, json_content_type());
+        res
+    }
+}
+
+/// gRPC messages of a method into JSON
+struct Converter {
+    output: MessageDescriptor,
+    response_body: Option<String>,
+    /// `None` when the upstream used an encoding Griffin cannot read
+    encoding: Option<Encoding>,
+    /// largest decompressed message
+    limit: usize,
+}
+
+impl Converter {
+    /// the message in JSON, a message decompressing past
+    /// the limit is RESOURCE_EXHAUSTED, other failures INTERNAL
+    fn to_json(&self, flag: u8, payload: Bytes) -> Result<Value, ProxyError> {
+        let internal = ProxyError::MalformedMessage;
+        let payload = if flag & COMPRESSED == 0 {
+            payload
+        } else {
+            let encoding = self
+                .encoding
+                .ok_or_else(|| internal("unsupported grpc-encoding".into()))?;
+            encoding.decompress(&payload, self.limit)?
+        };
+        let message = DynamicMessage::decode(self.output.clone(), payload)
+            .map_err(|err| internal(format!("cannot decode upstream message: {}", err)))?;
+        let Some(response_body) = &self.response_body else {
+            return serde_json::to_value(&message).map_err(|err| internal(err.to_string()));
+        };
+
+        // a field set to its default value is still sent
+        let options = SerializeOptions::new().skip_default_fields(false);
+        let json = message
+            .serialize_with_options(serde_json::value::Serializer, &options)
+            .map_err(|err| internal(err.to_string()))?;
+        let field = self
+            .output
+            .get_field_by_name(response_body)
+            .ok_or_else(|| internal(format!("unknown response_body field {:?}", response_body)))?;
+        Ok(json.get(field.json_name()).cloned().unwrap_or_default())
+    }
+}
+
+fn status_json(code: Code, message: &str) -> Value {
+    json!({"code": code as i32, "message": message})
+}
+
+fn json_content_type() -> HeaderValue {
+    HeaderValue::from_static("application/json")
+}
+
+/// trailers of a unary call, sent as
+/// `grpc-trailer-` prefixed headers
+fn trailer_headers(trailers: &HeaderMap) -> Vec<(HeaderName, HeaderValue)> {
+    trailers
+        .iter()
+        .filter(|(name, _)| !name.as_str().starts_with("grpc-"))
+        .filter_map(|(name, value)| {
+            let name = HeaderName::from_bytes(format!("grpc-trailer-{}", name).as_bytes()).ok()?;
+            Some((name, value.clone()))
+        })
+        .collect()
+}
@@ -1,15 +1,24 @@
 use bytes::Bytes;
-use http::Response;
-use http_body_util::{BodyExt, combinators::BoxBody};
-use hyper::body::Incoming;
+use http::HeaderValue;
+use http_body::Frame;
+
+use crate::ProxyResponse;
+use crate::status::{self, Code};
 
 pub struct GrpcKindPlain;
 
 impl GrpcKindPlain {
-    pub fn modify_response(
-        &self,
-        res: Response<Incoming>,
-    ) -> Response<BoxBody<Bytes, hyper::Error>> {
-        res.map(|body| body.boxed())
+    pub fn modify_response(&self, res: ProxyResponse) -> ProxyResponse {
+        res
+    }
+
+    /// Trailers-Only response carrying the status
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
+        status::trailers_only(&HeaderValue::from_static("application/grpc"), code, message)
+    }
+
+    /// HTTP/2 trailers carrying the status
+    pub fn status_frame(&self, code: Code, message: &str) -> Frame<Bytes> {
+        Frame::trailers(status::status_headers(code, message))
     }
 }
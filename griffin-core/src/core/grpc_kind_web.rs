@@ -1,44 +1,194 @@
+use std::convert::Infallible;
+
+use async_stream::stream;
 use bytes::Bytes;
-use http::{HeaderValue, Request, Response};
+use http::{HeaderMap, Request, Response, header};
 use http_body::Frame;
-use http_body_util::{BodyExt, combinators::BoxBody};
-use hyper::body::Incoming;
+use http_body_util::{BodyExt, Full, StreamBody, combinators::BoxBody};
 
+use crate::core::content_type::{ContentType, Protocol, web_response_content_type};
+use crate::error::upstream_error_code;
+use crate::status::{self, Code};
 use crate::trailers::Trailers;
-pub struct GrpcKindWeb;
+use crate::{ProxyResponse, UpstreamBody};
+/// gRPC-Web in binary mode (`application/grpc-web`)
+#[derive(Default)]
+pub struct GrpcKindWeb {
+    /// codec suffix of the client content type,
+    /// carried to the upstream
+    pub codec: Option<String>,
+}
 impl GrpcKindWeb {
-    pub fn modify_request<B>(&self, req: &mut Request<B>)
-    where
-        B: hyper::body::Body,
-    {
-        req.headers_mut().insert(
-            http::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/grpc"),
-        );
+    pub fn modify_request(&self, req: &mut Request<UpstreamBody>) {
+        let content_type = ContentType {
+            protocol: Protocol::Grpc,
+            codec: self.codec.clone(),
+        };
+        req.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type.to_header_value());
         req.headers_mut().remove(hyper::header::CONTENT_LENGTH);
     }
 
-    pub fn modify_response(
-        &self,
-        res: Response<Incoming>,
-    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+    pub fn modify_response(&self, res: ProxyResponse) -> ProxyResponse {
         let (parts, body) = res.into_parts();
-        let transformed = body
-            .map_frame(|frame| {
-                if let Some(trailers) = frame.trailers_ref() {
-                    let t = Trailers::new(trailers.clone());
-                    Frame::data(t.into_to_frame())
-                } else {
-                    frame
-                }
-            })
-            .boxed();
+        let transformed = match trailers_only(&parts.headers) {
+            Some(trailers) => trailer_frame_body(Trailers::new(trailers).into_frame_or_status()),
+            None => web_body(body),
+        };
 
+        let content_type =
+            web_response_content_type(Protocol::GrpcWeb, &parts.headers, self.codec.as_deref());
         let mut res = Response::from_parts(parts, transformed);
-        res.headers_mut().insert(
-            http::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/grpc-web+proto"),
-        );
+        res.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type);
         res
     }
+
+    /// body made of a trailer frame carrying the status
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
+        let mut res = Response::new(trailer_frame_body(status_trailer_frame(code, message)));
+        let content_type =
+            web_response_content_type(Protocol::GrpcWeb, &HeaderMap::new(), self.codec.as_deref());
+        res.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type);
+        res
+    }
+
+    /// trailer frame carrying the status
+    pub fn status_frame(&self, code: Code, message: &str) -> Frame<Bytes> {
+        Frame::data(status_trailer_frame(code, message))
+    }
+}
+
+pub(crate) fn status_trailer_frame(code: Code, message: &str) -> Bytes {
+    Trailers::new(status::status_headers(code, message)).into_frame_or_status()
+}
+
+/// headers that describe the HTTP response itself,
+/// they are never part of the trailing metadata
+pub(crate) const HTTP_HEADERS: [header::HeaderName; 5] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::DATE,
+    header::SERVER,
+    header::TRANSFER_ENCODING,
+];
+
+/// Detect a Trailers-Only response
+///
+/// When the upstream fails before sending any message,
+/// `grpc-status` arrives in the HEADERS frame
+/// and there is neither a body nor trailers.
+/// Returns the trailing metadata carried by the headers.
+///
+/// The headers are left in place as well,
+/// the gRPC-Web spec allows clients to read the status from either.
+/// <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md#protocol-differences-vs-grpc-over-http2>
+pub(crate) fn trailers_only(headers: &HeaderMap) -> Option<HeaderMap> {
+    if !headers.contains_key("grpc-status") {
+        return None;
+    }
+    let trailers = headers
+        .iter()
+        .filter(|(name, _)| !HTTP_HEADERS.contains(name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    Some(trailers)
+}
+
+/// Upstream body in gRPC-Web framing
+///
+/// Trailers become a trailer frame.
+/// A body error, when the upstream resets the stream
+/// or the connection drops, becomes a trailer frame
+/// with the matching status, so the client always
+/// gets a terminal status instead of a truncated body.
+pub(crate) fn web_body(mut body: BoxBody<Bytes, hyper::Error>) -> BoxBody<Bytes, hyper::Error> {
+    let frames = stream! {
+        while let Some(frame) = body.frame().await {
+            match frame {
+                Ok(frame) => match frame.into_trailers() {
+                    Ok(trailers) => {
+                        yield Ok(Frame::data(Trailers::new(trailers).into_frame_or_status()));
+                    }
+                    Err(frame) => yield Ok(frame),
+                },
+                Err(err) => {
+                    let code = upstream_error_code(&err);
+                    yield Ok(Frame::data(status_trailer_frame(code, &err.to_string())));
+                    break;
+                }
+            }
+        }
+    };
+    StreamBody::new(frames).boxed()
+}
+
+/// body made of a single trailer frame
+pub(crate) fn trailer_frame_body(frame: Bytes) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(frame)
+        .map_err(|never: Infallible| match never {})
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::service_fn;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use tokio::net::{TcpListener, TcpStream};
+    use tower::BoxError;
+
+    /// h2 upstream sending one message,
+    /// then failing its body, which resets the stream
+    async fn spawn_resetting_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = service_fn(|_req| async {
+                let frames = async_stream::stream! {
+                    yield Ok::<_, BoxError>(Frame::data(Bytes::from_static(b"\0\0\0\0\0")));
+                    // let the client read the headers first
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    yield Err("upstream crashed".into());
+                };
+                Ok::<_, Infallible>(Response::new(StreamBody::new(frames)))
+            });
+            let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), svc)
+                .await;
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_reset_stream_ends_with_trailer_frame() {
+        let address = spawn_resetting_server().await;
+        let stream = TcpStream::connect(&address).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http2::Builder::new(TokioExecutor::new())
+            .handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+
+        let req = Request::post(format!("http://{}/a.B/C", address))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        let body = GrpcKindWeb::default()
+            .modify_response(res.map(BodyExt::boxed))
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+
+        // the message, then a trailer frame
+        assert_eq!(&body[..5], b"\0\0\0\0\0");
+        assert_eq!(body[5], 0x80);
+        let trailers = String::from_utf8_lossy(&body[10..]);
+        // hyper resets a failed body with INTERNAL_ERROR
+        assert!(trailers.contains("grpc-status:13\r\n"), "{}", trailers);
+    }
 }
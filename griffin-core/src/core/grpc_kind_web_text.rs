@@ -0,0 +1,179 @@
+use async_stream::try_stream;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, Request, Response};
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody};
+use tower::BoxError;
+
+use crate::core::content_type::{ContentType, Protocol, web_response_content_type};
+use crate::core::grpc_kind_web::{
+    status_trailer_frame, trailer_frame_body, trailers_only, web_body,
+};
+use crate::status::Code;
+use crate::trailers::Trailers;
+use crate::{ProxyResponse, UpstreamBody};
+
+/// gRPC-Web in text mode (`application/grpc-web-text`):
+/// same framing as [`GrpcKindWeb`](super::grpc_kind_web::GrpcKindWeb),
+/// but both directions are base64 encoded
+#[derive(Default)]
+pub struct GrpcKindWebText {
+    /// codec suffix of the client content type,
+    /// carried to the upstream
+    pub codec: Option<String>,
+}
+impl GrpcKindWebText {
+    pub fn modify_request(&self, req: &mut Request<UpstreamBody>) {
+        let content_type = ContentType {
+            protocol: Protocol::Grpc,
+            codec: self.codec.clone(),
+        };
+        req.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type.to_header_value());
+        req.headers_mut().remove(hyper::header::CONTENT_LENGTH);
+
+        let mut body = std::mem::take(req.body_mut());
+        let decoded = try_stream! {
+            let mut decoder = Base64Decoder::default();
+            while let Some(frame) = body.frame().await {
+                match frame?.into_data() {
+                    Ok(data) => {
+                        let data = decoder.decode(&data)?;
+                        if !data.is_empty() {
+                            yield Frame::data(data);
+                        }
+                    }
+                    Err(frame) => yield frame,
+                }
+            }
+            decoder.finish()?;
+        };
+        *req.body_mut() = StreamBody::new(decoded).boxed_unsync();
+    }
+
+    pub fn modify_response(&self, res: ProxyResponse) -> ProxyResponse {
+        let (parts, body) = res.into_parts();
+        let transformed = match trailers_only(&parts.headers) {
+            Some(trailers) => {
+                trailer_frame_body(encode(&Trailers::new(trailers).into_frame_or_status()))
+            }
+            None => web_body(body)
+                .map_frame(|frame| match frame.into_data() {
+                    Ok(data) => Frame::data(encode(&data)),
+                    Err(frame) => frame,
+                })
+                .boxed(),
+        };
+
+        let content_type =
+            web_response_content_type(Protocol::GrpcWebText, &parts.headers, self.codec.as_deref());
+        let mut res = Response::from_parts(parts, transformed);
+        res.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type);
+        res
+    }
+
+    /// base64 trailer frame carrying the status
+    pub fn status_response(&self, code: Code, message: &str) -> ProxyResponse {
+        let frame = status_trailer_frame(code, message);
+        let mut res = Response::new(trailer_frame_body(encode(&frame)));
+        let content_type = web_response_content_type(
+            Protocol::GrpcWebText,
+            &HeaderMap::new(),
+            self.codec.as_deref(),
+        );
+        res.headers_mut()
+            .insert(http::header::CONTENT_TYPE, content_type);
+        res
+    }
+
+    /// base64 trailer frame carrying the status
+    pub fn status_frame(&self, code: Code, message: &str) -> Frame<Bytes> {
+        Frame::data(encode(&status_trailer_frame(code, message)))
+    }
+}
+
+/// every chunk is encoded on its own with padding,
+/// so a message is never held back waiting for
+/// the next chunk. The spec allows padded
+/// segments in the middle of a text stream
+fn encode(data: &[u8]) -> Bytes {
+    Bytes::from(STANDARD.encode(data))
+}
+
+/// Incremental base64 decoder
+///
+/// HTTP chunks do not line up with base64 quanta,
+/// so the tail of a chunk that does not fill
+/// a 4-char quantum is kept until the next chunk arrives.
+#[derive(Default)]
+struct Base64Decoder {
+    pending: BytesMut,
+}
+
+impl Base64Decoder {
+    fn decode(&mut self, chunk: &[u8]) -> Result<Bytes, base64::DecodeError> {
+        self.pending.extend_from_slice(chunk);
+        let complete = self.pending.len() - self.pending.len() % 4;
+        let input = self.pending.split_to(complete);
+
+        let mut out = Vec::with_capacity(complete / 4 * 3);
+        // a padded quantum ends a segment,
+        // each segment has to be decoded separately
+        let mut start = 0;
+        for (i, quantum) in input.chunks(4).enumerate() {
+            if quantum[3] == b'=' {
+                let end = (i + 1) * 4;
+                STANDARD.decode_vec(&input[start..end], &mut out)?;
+                start = end;
+            }
+        }
+        STANDARD.decode_vec(&input[start..], &mut out)?;
+        Ok(Bytes::from(out))
+    }
+
+    fn finish(self) -> Result<(), BoxError> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err("Truncated base64 request body".into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_across_chunk_boundaries() {
+        let encoded = STANDARD.encode(b"hello griffin");
+        let (a, b) = encoded.as_bytes().split_at(5);
+
+        let mut decoder = Base64Decoder::default();
+        let mut out = decoder.decode(a).unwrap().to_vec();
+        out.extend_from_slice(&decoder.decode(b).unwrap());
+        decoder.finish().unwrap();
+
+        assert_eq!(out, b"hello griffin");
+    }
+
+    #[test]
+    fn test_decode_padded_segments() {
+        let encoded = format!("{}{}", STANDARD.encode(b"a"), STANDARD.encode(b"bcde"));
+
+        let mut decoder = Base64Decoder::default();
+        let out = decoder.decode(encoded.as_bytes()).unwrap();
+        decoder.finish().unwrap();
+
+        assert_eq!(&out[..], b"abcde");
+    }
+
+    #[test]
+    fn test_decode_truncated_input() {
+        let mut decoder = Base64Decoder::default();
+        decoder.decode(b"aGVsbG").unwrap();
+        assert!(decoder.finish().is_err());
+    }
+}
@@ -1,3 +1,7 @@
+pub mod content_type;
 pub mod grpc_kind;
+pub mod grpc_kind_connect;
+pub mod grpc_kind_json;
 pub mod grpc_kind_plain;
 pub mod grpc_kind_web;
+pub mod grpc_kind_web_text;
@@ -0,0 +1,429 @@
+//!
+//! CORS for browser clients calling Griffin
+//! from a different origin.
+//! Preflight requests are answered by Griffin itself,
+//! they never reach the upstream.
+//!
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode, header};
+use http_body_util::Full;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::ProxyResponse;
+use crate::core::grpc_kind_web::HTTP_HEADERS;
+use crate::telemetry::metrics::from_full_bytes;
+
+/// request headers a gRPC-Web client sends,
+/// always allowed on top of [`CorsConfig::allow_headers`]
+const GRPC_WEB_ALLOW_HEADERS: [&str; 6] = [
+    "content-type",
+    "x-grpc-web",
+    "x-user-agent",
+    "grpc-timeout",
+    "x-accept-content-transfer-encoding",
+    "x-accept-response-streaming",
+];
+
+/// response headers a gRPC-Web client reads,
+/// always exposed on top of [`CorsConfig::expose_headers`]
+const GRPC_WEB_EXPOSE_HEADERS: [&str; 3] =
+    ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+/// CORS section of the listener config
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allow_origins: Vec<OriginConfig>,
+    pub allow_methods: Vec<String>,
+    /// extra request headers, gRPC-Web headers are always allowed
+    pub allow_headers: Vec<String>,
+    /// extra response headers, gRPC status headers
+    /// and response metadata are always exposed
+    pub expose_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// seconds a browser may cache a preflight response
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allow_origins: Vec::new(),
+            allow_methods: vec!["POST".into()],
+            allow_headers: Vec::new(),
+            expose_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+/// ```yaml
+/// allow_origins:
+///   - exact: "https://app.example.com"
+///   - wildcard: "https://*.example.com"
+///   - regex: "https://[a-z]+\\.example\\.org"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginConfig {
+    Exact(String),
+    /// `*` matches any sequence of characters,
+    /// a single `*` allows every origin
+    Wildcard(String),
+    /// the whole origin has to match
+    Regex(String),
+}
+
+enum OriginMatcher {
+    Exact(String),
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl OriginMatcher {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginMatcher::Exact(exact) => exact.eq_ignore_ascii_case(origin),
+            OriginMatcher::Wildcard(pattern) => wildcard_match(pattern, origin),
+            OriginMatcher::Regex(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+/// compiled [`CorsConfig`]
+pub struct CorsPolicy {
+    origins: Vec<OriginMatcher>,
+    methods: Vec<String>,
+    /// lowercase
+    headers: Vec<String>,
+    allow_methods: HeaderValue,
+    allow_headers: HeaderValue,
+    expose_headers: Vec<String>,
+    allow_credentials: bool,
+    max_age: Option<HeaderValue>,
+}
+
+impl CorsPolicy {
+    pub fn new(config: &CorsConfig) -> Result<Self, tower::BoxError> {
+        let origins = config
+            .allow_origins
+            .iter()
+            .map(|origin| {
+                Ok(match origin {
+                    OriginConfig::Exact(exact) => OriginMatcher::Exact(exact.clone()),
+                    OriginConfig::Wildcard(pattern) => OriginMatcher::Wildcard(pattern.clone()),
+                    // anchor the pattern so that a partial match
+                    // cannot allow an unexpected origin
+                    OriginConfig::Regex(regex) => {
+                        OriginMatcher::Regex(Regex::new(&format!("^(?:{})$", regex))?)
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+
+        let allow_headers = GRPC_WEB_ALLOW_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(config.allow_headers.iter().map(|name| name.to_lowercase()))
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            origins,
+            methods: config.allow_methods.clone(),
+            headers: allow_headers.clone(),
+            allow_methods: HeaderValue::from_str(&config.allow_methods.join(", "))?,
+            allow_headers: HeaderValue::from_str(&allow_headers.join(", "))?,
+            expose_headers: config
+                .expose_headers
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect(),
+            allow_credentials: config.allow_credentials,
+            max_age: config.max_age.map(HeaderValue::from),
+        })
+    }
+
+    pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(header::ORIGIN)
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// the `Origin` of the request is allowed
+    pub fn allows_origin(&self, headers: &HeaderMap) -> bool {
+        self.allowed_origin(headers).is_some()
+    }
+
+    fn allowed_origin<'a>(&self, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        let text = origin.to_str().ok()?;
+        self.origins
+            .iter()
+            .any(|matcher| matcher.matches(text))
+            .then_some(origin)
+    }
+
+    /// the method and every header the preflight asks for are allowed
+    fn allows_request(&self, headers: &HeaderMap) -> bool {
+        let method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| method.to_str().ok());
+        let Some(method) = method else {
+            return false;
+        };
+        if !self
+            .methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method.trim()))
+        {
+            return false;
+        }
+        headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .all(|value| {
+                value.to_str().is_ok_and(|names| {
+                    names
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .all(|name| {
+                            self.headers
+                                .iter()
+                                .any(|allowed| allowed.eq_ignore_ascii_case(name))
+                        })
+                })
+            })
+    }
+
+    /// answer a preflight request,
+    /// a disallowed origin, method or header
+    /// gets 403 without any CORS header
+    pub fn preflight(&self, headers: &HeaderMap) -> ProxyResponse {
+        let mut res = from_full_bytes(Full::<Bytes>::default());
+        let origin = self
+            .allowed_origin(headers)
+            .filter(|_| self.allows_request(headers));
+        let Some(origin) = origin else {
+            *res.status_mut() = StatusCode::FORBIDDEN;
+            return res;
+        };
+        *res.status_mut() = StatusCode::NO_CONTENT;
+
+        let res_headers = res.headers_mut();
+        self.insert_origin(res_headers, origin);
+        res_headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            self.allow_methods.clone(),
+        );
+        res_headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            self.allow_headers.clone(),
+        );
+        if let Some(max_age) = &self.max_age {
+            res_headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.clone());
+        }
+        res
+    }
+
+    /// add CORS headers to the response of an actual request
+    pub fn apply<B>(&self, req_headers: &HeaderMap, res: &mut Response<B>) {
+        let Some(origin) = self.allowed_origin(req_headers) else {
+            return;
+        };
+
+        // gRPC status, configured headers
+        // and every metadata key the upstream sent
+        let mut expose = GRPC_WEB_EXPOSE_HEADERS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.expose_headers.iter().cloned())
+            .collect::<Vec<_>>();
+        for name in res.headers().keys() {
+            if !is_http_header(name) && !expose.iter().any(|n| n == name.as_str()) {
+                expose.push(name.to_string());
+            }
+        }
+
+        let res_headers = res.headers_mut();
+        self.insert_origin(res_headers, origin);
+        if let Ok(expose) = HeaderValue::from_str(&expose.join(", ")) {
+            res_headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, expose);
+        }
+    }
+
+    fn insert_origin(&self, headers: &mut HeaderMap, origin: &HeaderValue) {
+        // echo the origin instead of `*`,
+        // browsers reject `*` together with credentials
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+/// response headers browsers can always read
+/// or that are not metadata
+fn is_http_header(name: &header::HeaderName) -> bool {
+    HTTP_HEADERS.contains(name)
+        || name == header::VARY
+        || name.as_str().starts_with("access-control-")
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let text = text.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // no `*` in the pattern
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(origins: Vec<OriginConfig>) -> CorsPolicy {
+        CorsPolicy::new(&CorsConfig {
+            allow_origins: origins,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn request_from(origin: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, origin.parse().unwrap());
+        headers
+    }
+
+    fn preflight_from(origin: &str, method: &str, request_headers: &str) -> HeaderMap {
+        let mut headers = request_from(origin);
+        headers.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            method.parse().unwrap(),
+        );
+        if !request_headers.is_empty() {
+            headers.insert(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                request_headers.parse().unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", "https://anything.example"));
+        assert!(wildcard_match(
+            "https://*.example.com",
+            "https://app.example.com"
+        ));
+        assert!(wildcard_match(
+            "https://*.example.com",
+            "https://A.B.example.com"
+        ));
+        assert!(!wildcard_match(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!wildcard_match(
+            "https://*.example.com",
+            "http://app.example.com"
+        ));
+        assert!(wildcard_match(
+            "https://app.example.com",
+            "https://app.example.com"
+        ));
+        assert!(!wildcard_match(
+            "https://app.example.com",
+            "https://app.example.com.evil"
+        ));
+    }
+
+    #[test]
+    fn test_origin_matchers() {
+        let policy = policy(vec![
+            OriginConfig::Exact("https://app.example.com".into()),
+            OriginConfig::Wildcard("https://*.staging.example.com".into()),
+            OriginConfig::Regex(r"https://[a-z]+\.example\.org".into()),
+        ]);
+
+        for allowed in [
+            "https://app.example.com",
+            "https://pr-1.staging.example.com",
+            "https://web.example.org",
+        ] {
+            assert!(policy.allowed_origin(&request_from(allowed)).is_some());
+        }
+        for denied in [
+            "https://other.example.com",
+            "https://web.example.org.evil.com",
+            "https://123.example.org",
+        ] {
+            assert!(policy.allowed_origin(&request_from(denied)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_preflight_denied_origin() {
+        let policy = policy(vec![OriginConfig::Exact("https://app.example.com".into())]);
+        let res = policy.preflight(&request_from("https://evil.com"));
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(
+            !res.headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[test]
+    fn test_preflight_checks_method_and_headers() {
+        let policy = CorsPolicy::new(&CorsConfig {
+            allow_origins: vec![OriginConfig::Exact("https://app.example.com".into())],
+            allow_headers: vec!["Authorization".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let status = |method, request_headers| {
+            let headers = preflight_from("https://app.example.com", method, request_headers);
+            policy.preflight(&headers).status()
+        };
+
+        assert_eq!(status("POST", ""), StatusCode::NO_CONTENT);
+        assert_eq!(
+            status("POST", "content-type, X-Grpc-Web,authorization"),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(status("PUT", ""), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status("POST", "content-type,x-api-key"),
+            StatusCode::FORBIDDEN
+        );
+
+        let res = policy.preflight(&preflight_from("https://app.example.com", "DELETE", ""));
+        assert!(
+            !res.headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//!
+//! Call deadlines from the `grpc-timeout` header.
+//! The client deadline is capped, a default one is used
+//! when the client sent none, and once it passes the call
+//! ends with DEADLINE_EXCEEDED and the upstream stream is cancelled.
+//! <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests>
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, Frame};
+use http_body_util::{BodyExt, StreamBody};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::ProxyResponse;
+
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// ```yaml
+/// deadline:
+///   default_timeout_ms: 30000
+///   max_timeout_ms: 300000
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DeadlineConfig {
+    /// deadline of calls without `grpc-timeout`
+    pub default_timeout_ms: Option<u64>,
+    /// longest deadline a client can ask for,
+    /// calls without any deadline get this one
+    pub max_timeout_ms: Option<u64>,
+}
+
+impl DeadlineConfig {
+    /// timeout of a call, `None` when it has no deadline.
+    /// An invalid `grpc-timeout` counts as missing
+    pub fn timeout(&self, headers: &HeaderMap) -> Option<Duration> {
+        let requested = headers
+            .get(GRPC_TIMEOUT)
+            .and_then(parse_timeout)
+            .or(self.default_timeout_ms.map(Duration::from_millis));
+        let max = self.max_timeout_ms.map(Duration::from_millis);
+        match (requested, max) {
+            (Some(requested), Some(max)) => Some(requested.min(max)),
+            (requested, max) => requested.or(max),
+        }
+    }
+}
+
+/// `grpc-timeout`: at most 8 digits and a unit
+pub fn parse_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.as_bytes();
+    let (&unit, digits) = value.split_last()?;
+    if digits.is_empty() || digits.len() > 8 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let amount: u64 = std::str::from_utf8(digits).ok()?.parse().ok()?;
+    let timeout = match unit {
+        b'H' => Duration::from_secs(amount * 3600),
+        b'M' => Duration::from_secs(amount * 60),
+        b'S' => Duration::from_secs(amount),
+        b'm' => Duration::from_millis(amount),
+        b'u' => Duration::from_micros(amount),
+        b'n' => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+/// `grpc-timeout` in the finest unit that fits in 8 digits,
+/// rounded up so the upstream never gets a shorter deadline
+pub fn encode_timeout(timeout: Duration) -> HeaderValue {
+    const MAX: u128 = 99_999_999;
+    let nanos = timeout.as_nanos();
+    let units: [(u128, char); 6] = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60_000_000_000, 'M'),
+        (3_600_000_000_000, 'H'),
+    ];
+    let (amount, unit) = units
+        .iter()
+        .map(|(size, unit)| (nanos.div_ceil(*size), *unit))
+        .find(|(amount, _)| *amount <= MAX)
+        .unwrap_or((MAX, 'H'));
+    HeaderValue::from_str(&format!("{}{}", amount, unit)).expect("digits and a unit")
+}
+
+/// end the response body with `status`
+/// when `deadline` passes before the upstream finished,
+/// the upstream body is dropped at that point
+pub(crate) fn expire_at(
+    res: ProxyResponse,
+    deadline: Instant,
+    status: Frame<Bytes>,
+) -> ProxyResponse {
+    // a Trailers-Only response is already complete
+    if res.body().is_end_stream() {
+        return res;
+    }
+    res.map(|mut body| {
+        let frames = stream! {
+            let expired = tokio::time::sleep_until(deadline);
+            tokio::pin!(expired);
+            loop {
+                tokio::select! {
+                    frame = body.frame() => match frame {
+                        Some(frame) => yield frame,
+                        None => break,
+                    },
+                    _ = &mut expired => {
+                        yield Ok(status);
+                        break;
+                    }
+                }
+            }
+        };
+        StreamBody::new(frames).boxed()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::metrics::from_full_bytes;
+    use http_body_util::Full;
+
+    #[test]
+    fn test_parse_timeout() {
+        let parse = |value| parse_timeout(&HeaderValue::from_static(value));
+        assert_eq!(parse("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse("3S"), Some(Duration::from_secs(3)));
+        assert_eq!(parse("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse("5u"), Some(Duration::from_micros(5)));
+        assert_eq!(parse("99999999n"), Some(Duration::from_nanos(99_999_999)));
+        assert_eq!(parse("123456789m"), None);
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("10"), None);
+        assert_eq!(parse("10s"), None);
+        assert_eq!(parse("-1S"), None);
+    }
+
+    #[test]
+    fn test_encode_timeout() {
+        let encode = |timeout| encode_timeout(timeout).to_str().unwrap().to_string();
+        assert_eq!(encode(Duration::from_nanos(500)), "500n");
+        assert_eq!(encode(Duration::from_millis(250)), "250000u");
+        assert_eq!(encode(Duration::from_secs(30)), "30000000u");
+        assert_eq!(encode(Duration::from_secs(300_000)), "300000S");
+        // rounded up
+        assert_eq!(encode(Duration::new(100_000, 1)), "100001S");
+        for timeout in [
+            Duration::from_millis(1234),
+            Duration::from_secs(86_400 * 365),
+        ] {
+            let parsed = parse_timeout(&encode_timeout(timeout)).unwrap();
+            assert!(parsed >= timeout);
+        }
+    }
+
+    #[test]
+    fn test_timeout_is_capped_and_defaulted() {
+        let config = DeadlineConfig {
+            default_timeout_ms: Some(1000),
+            max_timeout_ms: Some(5000),
+        };
+        let mut headers = HeaderMap::new();
+        assert_eq!(config.timeout(&headers), Some(Duration::from_secs(1)));
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("2S"));
+        assert_eq!(config.timeout(&headers), Some(Duration::from_secs(2)));
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("1M"));
+        assert_eq!(config.timeout(&headers), Some(Duration::from_secs(5)));
+
+        let config = DeadlineConfig {
+            default_timeout_ms: None,
+            max_timeout_ms: Some(5000),
+        };
+        assert_eq!(
+            config.timeout(&HeaderMap::new()),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(DeadlineConfig::default().timeout(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_body_ends_with_status() {
+        let pending = StreamBody::new(futures_util::stream::pending::<
+            Result<Frame<Bytes>, hyper::Error>,
+        >());
+        let res = http::Response::new(pending.boxed());
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let res = expire_at(res, deadline, Frame::data(Bytes::from_static(b"status")));
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "status");
+    }
+
+    #[tokio::test]
+    async fn test_complete_response_is_kept() {
+        let res = from_full_bytes(Full::default());
+        let res = expire_at(
+            res,
+            Instant::now(),
+            Frame::data(Bytes::from_static(b"status")),
+        );
+        assert!(res.body().is_end_stream());
+    }
+}
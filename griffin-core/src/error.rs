@@ -0,0 +1,163 @@
+//!
+//! Failures of a call before the upstream answered,
+//! each one is sent to the client as a gRPC status
+//! instead of resetting the stream.
+//!
+use http::{HeaderValue, StatusCode};
+use std::fmt;
+use tower::BoxError;
+
+use crate::ProxyResponse;
+use crate::core::grpc_kind::GrpcKind;
+use crate::status::{self, Code};
+
+#[derive(Debug)]
+pub enum ProxyError {
+    MissingContentType,
+    UnsupportedContentType(HeaderValue),
+    /// client messages compressed with an
+    /// encoding Griffin cannot recompress
+    UnsupportedEncoding(HeaderValue),
+    /// the readiness gate did not open in time
+    /// or its queue is full
+    NotReady(&'static str),
+    NoEndpoint,
+    /// the upstream uri or headers could not be built
+    InvalidRequest(BoxError),
+    /// an HTTP/JSON request that does not fit
+    /// the message of its method
+    InvalidMessage(String),
+    /// a message above the size limit of the call
+    MessageTooLarge(String),
+    /// a body that does not split into frames
+    MalformedMessage(String),
+    /// no connection to the upstream
+    Connect(BoxError),
+    /// the upstream failed before sending response headers
+    Upstream(hyper::Error),
+    DeadlineExceeded,
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::MissingContentType => write!(f, "missing content-type header"),
+            ProxyError::UnsupportedContentType(content_type) => {
+                write!(f, "unsupported content-type {:?}", content_type)
+            }
+            ProxyError::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported grpc-encoding {:?}", encoding)
+            }
+            ProxyError::NotReady(reason) => write!(f, "{}", reason),
+            ProxyError::NoEndpoint => write!(f, "no upstream endpoint available"),
+            ProxyError::InvalidRequest(err) => write!(f, "invalid upstream request: {}", err),
+            ProxyError::InvalidMessage(reason)
+            | ProxyError::MessageTooLarge(reason)
+            | ProxyError::MalformedMessage(reason) => write!(f, "{}", reason),
+            ProxyError::Connect(err) => write!(f, "upstream connect error: {}", err),
+            ProxyError::Upstream(err) => write!(f, "upstream error: {}", err),
+            ProxyError::DeadlineExceeded => write!(f, "deadline exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl ProxyError {
+    pub fn code(&self) -> Code {
+        match self {
+            ProxyError::MissingContentType
+            | ProxyError::UnsupportedContentType(_)
+            | ProxyError::UnsupportedEncoding(_) => Code::Unimplemented,
+            ProxyError::NotReady(_)
+            | ProxyError::NoEndpoint
+            | ProxyError::Connect(_)
+            | ProxyError::Upstream(_) => Code::Unavailable,
+            ProxyError::InvalidRequest(_) | ProxyError::MalformedMessage(_) => Code::Internal,
+            ProxyError::MessageTooLarge(_) => Code::ResourceExhausted,
+            ProxyError::InvalidMessage(_) => Code::InvalidArgument,
+            ProxyError::DeadlineExceeded => Code::DeadlineExceeded,
+        }
+    }
+
+    /// the status for `kind` clients,
+    /// a request that is not gRPC at all gets 415
+    /// with the status in the headers
+    pub fn into_response(self, kind: Option<&GrpcKind>) -> ProxyResponse {
+        let message = self.to_string();
+        match kind {
+            Some(kind) => kind.status_response(self.code(), &message),
+            None => {
+                let mut res = status::trailers_only(
+                    &HeaderValue::from_static("application/grpc"),
+                    self.code(),
+                    &message,
+                );
+                *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                res
+            }
+        }
+    }
+}
+
+/// Status of a call whose upstream stream failed,
+/// mapped from the RST_STREAM reason as the gRPC spec does.
+/// A dropped connection has no reason and is UNAVAILABLE
+/// <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#errors>
+pub fn upstream_error_code(err: &hyper::Error) -> Code {
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if let Some(reason) = err.downcast_ref::<h2::Error>().and_then(h2::Error::reason) {
+            return reset_code(reason);
+        }
+        source = err.source();
+    }
+    Code::Unavailable
+}
+
+fn reset_code(reason: h2::Reason) -> Code {
+    match reason {
+        h2::Reason::REFUSED_STREAM => Code::Unavailable,
+        h2::Reason::CANCEL => Code::Cancelled,
+        h2::Reason::ENHANCE_YOUR_CALM => Code::ResourceExhausted,
+        h2::Reason::INADEQUATE_SECURITY => Code::PermissionDenied,
+        _ => Code::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn test_reset_code() {
+        assert_eq!(reset_code(h2::Reason::CANCEL), Code::Cancelled);
+        assert_eq!(reset_code(h2::Reason::REFUSED_STREAM), Code::Unavailable);
+        assert_eq!(reset_code(h2::Reason::INTERNAL_ERROR), Code::Internal);
+        assert_eq!(reset_code(h2::Reason::PROTOCOL_ERROR), Code::Internal);
+    }
+
+    #[test]
+    fn test_unsupported_content_type() {
+        let res = ProxyError::UnsupportedContentType(HeaderValue::from_static("text/plain"))
+            .into_response(None);
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(res.headers()["grpc-status"], "12");
+    }
+
+    #[tokio::test]
+    async fn test_web_status_is_a_trailer_frame() {
+        let kind =
+            GrpcKind::from_content_type(&HeaderValue::from_static("application/grpc-web+proto"))
+                .unwrap();
+        let res = ProxyError::Connect("connection refused".into()).into_response(Some(&kind));
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body[0], 0x80);
+        let trailers = String::from_utf8_lossy(&body[5..]);
+        assert!(trailers.contains("grpc-status:14\r\n"));
+        assert!(trailers.contains("grpc-message:upstream connect error: connection refused"));
+    }
+}
@@ -0,0 +1,372 @@
+//!
+//! gRPC message framing: a flag byte,
+//! a 4-byte big endian length, then the message.
+//! <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests>
+//!
+//! [`FrameStream`] reads the messages and trailers of a gRPC
+//! or gRPC-Web body, [`GrpcFrame::encode`] writes them back.
+//!
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_core::Stream;
+use http::HeaderMap;
+use http_body::Body;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tower::BoxError;
+
+use crate::trailers::{Trailers, TrailersError};
+
+/// the message is compressed with the call `grpc-encoding`
+pub const COMPRESSED: u8 = 0x01;
+/// gRPC-Web trailer frame
+pub const TRAILERS: u8 = 0x80;
+const HEADER_LEN: usize = 5;
+
+/// Splits a byte stream into frames,
+/// chunks do not line up with frames so the
+/// tail of a chunk is kept until the next one arrives
+#[derive(Default)]
+pub struct Deframer {
+    buf: BytesMut,
+    /// largest payload, unlimited when missing
+    max_len: Option<usize>,
+}
+
+impl Deframer {
+    /// a frame declaring a longer payload is refused
+    /// as soon as its header arrives
+    pub fn with_max_len(max_len: Option<usize>) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            max_len,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// next complete frame, its flag and payload
+    pub fn next_frame(&mut self) -> Result<Option<(u8, Bytes)>, FrameError> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]) as usize;
+        if let Some(max) = self.max_len
+            && len > max
+        {
+            return Err(FrameError::TooLarge { len, max });
+        }
+        if self.buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let flag = self.buf.get_u8();
+        self.buf.advance(4);
+        Ok(Some((flag, self.buf.split_to(len).freeze())))
+    }
+
+    /// no partial frame is left
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+/// a frame of `payload`, whose length must fit in 32 bits
+pub fn encode_frame(flag: u8, payload: &[u8]) -> Result<Bytes, FrameError> {
+    let len = u32::try_from(payload.len()).map_err(|_| FrameError::TooLarge {
+        len: payload.len(),
+        max: u32::MAX as usize,
+    })?;
+    let mut frame = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    frame.put_u8(flag);
+    frame.put_u32(len);
+    frame.put_slice(payload);
+    Ok(frame.freeze())
+}
+
+/// An item of a gRPC or gRPC-Web body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrpcFrame {
+    /// a length-prefixed message, compressed
+    /// with the call `grpc-encoding` when flagged
+    Message { compressed: bool, payload: Bytes },
+    /// trailers of the call, from a gRPC-Web
+    /// trailer frame or the HTTP/2 trailers
+    Trailers(HeaderMap),
+}
+
+impl GrpcFrame {
+    /// the frame in gRPC-Web framing,
+    /// trailers become a trailer frame
+    pub fn encode(&self) -> Result<Bytes, FrameError> {
+        match self {
+            GrpcFrame::Message {
+                compressed,
+                payload,
+            } => encode_frame(if *compressed { COMPRESSED } else { 0 }, payload),
+            GrpcFrame::Trailers(trailers) => {
+                Ok(Trailers::new(trailers.clone()).into_frame_or_status())
+            }
+        }
+    }
+
+    fn decode(flag: u8, payload: Bytes) -> Result<Self, FrameError> {
+        if flag & !(COMPRESSED | TRAILERS) != 0 {
+            return Err(FrameError::UnknownFlags(flag));
+        }
+        if flag & TRAILERS == 0 {
+            return Ok(GrpcFrame::Message {
+                compressed: flag & COMPRESSED != 0,
+                payload,
+            });
+        }
+        let trailers = Trailers::decode(&payload).map_err(FrameError::InvalidTrailers)?;
+        Ok(GrpcFrame::Trailers(trailers.into_inner()))
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// the body failed
+    Body(BoxError),
+    /// the body ended inside a frame
+    Truncated,
+    /// a payload longer than the limit
+    TooLarge { len: usize, max: usize },
+    /// flags other than compressed and trailers
+    UnknownFlags(u8),
+    /// a trailer frame that does not decode
+    InvalidTrailers(TrailersError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Body(err) => write!(f, "body error: {}", err),
+            FrameError::Truncated => write!(f, "body ended inside a frame"),
+            FrameError::TooLarge { len, max } => write!(
+                f,
+                "frame of {} bytes is larger than the limit of {} bytes",
+                len, max
+            ),
+            FrameError::UnknownFlags(flag) => write!(f, "frame with unknown flags {:#04x}", flag),
+            FrameError::InvalidTrailers(reason) => write!(f, "invalid trailer frame: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// The frames of a body, whatever its chunks are
+///
+/// Messages come in order, the stream ends after
+/// the trailers or when the body ends without them.
+/// A body error, a partial frame or a frame
+/// over the max length is the last item.
+pub struct FrameStream<B> {
+    body: B,
+    deframer: Deframer,
+    done: bool,
+}
+
+impl<B> FrameStream<B> {
+    pub fn new(body: B) -> Self {
+        Self {
+            body,
+            deframer: Deframer::default(),
+            done: false,
+        }
+    }
+
+    /// largest payload read, the frame declaring
+    /// a longer one ends the stream with [`FrameError::TooLarge`]
+    pub fn with_max_len(mut self, max_len: Option<usize>) -> Self {
+        self.deframer.max_len = max_len;
+        self
+    }
+}
+
+impl<B> Stream for FrameStream<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Item = Result<GrpcFrame, FrameError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            if let Some(frame) = this.deframer.next_frame().transpose() {
+                let frame = frame.and_then(|(flag, payload)| GrpcFrame::decode(flag, payload));
+                // a trailer frame closes the body
+                this.done = !matches!(frame, Ok(GrpcFrame::Message { .. }));
+                return Poll::Ready(Some(frame));
+            }
+            let frame = ready!(Pin::new(&mut this.body).poll_frame(cx));
+            let item = match frame {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        this.deframer.push(&data);
+                        continue;
+                    }
+                    Err(frame) => match frame.into_trailers() {
+                        Ok(_) if !this.deframer.is_empty() => Some(Err(FrameError::Truncated)),
+                        Ok(trailers) => Some(Ok(GrpcFrame::Trailers(trailers))),
+                        Err(_) => continue,
+                    },
+                },
+                Some(Err(err)) => Some(Err(FrameError::Body(err.into()))),
+                None if !this.deframer.is_empty() => Some(Err(FrameError::Truncated)),
+                None => None,
+            };
+            this.done = true;
+            return Poll::Ready(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use http::HeaderValue;
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+
+    fn chunked_body(
+        chunks: Vec<Frame<Bytes>>,
+    ) -> StreamBody<impl futures_core::Stream<Item = Result<Frame<Bytes>, BoxError>> + Unpin> {
+        StreamBody::new(futures_util::stream::iter(chunks.into_iter().map(Ok)))
+    }
+
+    fn message(compressed: bool, payload: &'static str) -> GrpcFrame {
+        GrpcFrame::Message {
+            compressed,
+            payload: Bytes::from(payload),
+        }
+    }
+
+    #[test]
+    fn test_frames_across_chunk_boundaries() {
+        let mut stream = encode_frame(0, b"hello").unwrap().to_vec();
+        stream.extend_from_slice(&encode_frame(COMPRESSED, b"griffin").unwrap());
+        let (a, b) = stream.split_at(7);
+
+        let mut deframer = Deframer::default();
+        deframer.push(a);
+        assert_eq!(deframer.next_frame().unwrap(), None);
+        deframer.push(b);
+        assert_eq!(
+            deframer.next_frame().unwrap(),
+            Some((0, Bytes::from("hello")))
+        );
+        assert_eq!(
+            deframer.next_frame().unwrap(),
+            Some((COMPRESSED, Bytes::from("griffin")))
+        );
+        assert_eq!(deframer.next_frame().unwrap(), None);
+        assert!(deframer.is_empty());
+    }
+
+    #[test]
+    fn test_deframer_refuses_long_frames_from_their_header() {
+        let frame = encode_frame(0, b"griffin").unwrap();
+        let mut deframer = Deframer::with_max_len(Some(5));
+        // the payload has not arrived yet
+        deframer.push(&frame[..5]);
+        assert!(matches!(
+            deframer.next_frame(),
+            Err(FrameError::TooLarge { len: 7, max: 5 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream_reads_messages_and_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let mut stream = message(false, "hello").encode().unwrap().to_vec();
+        stream.extend_from_slice(&message(true, "griffin").encode().unwrap());
+        let (a, b) = stream.split_at(3);
+
+        let body = chunked_body(vec![
+            Frame::data(Bytes::copy_from_slice(a)),
+            Frame::data(Bytes::copy_from_slice(b)),
+            Frame::trailers(trailers.clone()),
+        ]);
+        let frames: Vec<_> = FrameStream::new(body)
+            .map(|frame| frame.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            frames,
+            vec![
+                message(false, "hello"),
+                message(true, "griffin"),
+                GrpcFrame::Trailers(trailers),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream_reads_web_trailer_frame() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("3"));
+        let mut stream = message(false, "hello").encode().unwrap().to_vec();
+        stream.extend_from_slice(&GrpcFrame::Trailers(trailers.clone()).encode().unwrap());
+        // nothing is read after the trailer frame
+        stream.extend_from_slice(&message(false, "ignored").encode().unwrap());
+
+        let body = chunked_body(vec![Frame::data(Bytes::from(stream))]);
+        let frames: Vec<_> = FrameStream::new(body)
+            .map(|frame| frame.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            frames,
+            vec![message(false, "hello"), GrpcFrame::Trailers(trailers)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream_reports_truncated_body() {
+        let frame = message(false, "hello").encode().unwrap();
+        let body = chunked_body(vec![Frame::data(frame.slice(..7))]);
+        let mut frames = FrameStream::new(body);
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameError::Truncated))
+        ));
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream_stops_at_long_message() {
+        let mut stream = message(false, "hello").encode().unwrap().to_vec();
+        stream.extend_from_slice(&message(false, "griffin").encode().unwrap());
+        let body = chunked_body(vec![Frame::data(Bytes::from(stream))]);
+        let mut frames = FrameStream::new(body).with_max_len(Some(5));
+        assert_eq!(
+            frames.next().await.unwrap().unwrap(),
+            message(false, "hello")
+        );
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameError::TooLarge { len: 7, max: 5 }))
+        ));
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream_refuses_unknown_flags() {
+        let body = chunked_body(vec![Frame::data(encode_frame(0x04, b"").unwrap())]);
+        let mut frames = FrameStream::new(body);
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameError::UnknownFlags(0x04)))
+        ));
+    }
+}
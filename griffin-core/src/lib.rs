@@ -1,42 +1,94 @@
 use bytes::Bytes;
-use http::{Request, Response, Uri, header::CONTENT_TYPE, uri::Authority};
-use http_body_util::combinators::BoxBody;
-use hyper::client::conn::http2;
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use http::{HeaderValue, Request, Response, Uri, request::Parts};
+use http_body_util::combinators::{BoxBody, UnsyncBoxBody};
+use http_body_util::{BodyExt, Full};
 use scopeguard::defer;
+use std::convert::Infallible;
 use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::time::Instant;
+use tokio::time::{Instant, timeout_at};
 use tower::BoxError;
 
+use crate::cancellation::{cancel_on_drop, cancellation};
+use crate::compression::Recompression;
+use crate::context::ProxyContext;
 use crate::core::grpc_kind::GrpcKind;
-use crate::telemetry::metrics::Metrics;
+use crate::core::grpc_kind_connect::GrpcKindConnect;
+use crate::cors::CorsPolicy;
+use crate::deadline::{GRPC_TIMEOUT, encode_timeout, expire_at};
+use crate::error::ProxyError;
+use crate::mirror::Mirror;
+use crate::readiness::readyz;
+use crate::telemetry::cluster_call::ClusterCall;
+use crate::transcoding::TranscodedCall;
 
+pub mod cancellation;
+pub mod compression;
+pub mod context;
 pub mod core;
+pub mod cors;
+pub mod deadline;
+pub mod error;
+pub mod frame;
+pub mod message_size;
+pub mod mirror;
+pub(crate) mod protobuf;
+pub mod readiness;
+pub mod routing;
+pub mod status;
 pub mod telemetry;
 pub mod trailers;
+pub mod transcoding;
+pub mod upstream;
+pub mod websocket;
 pub type ProxyResponse = Response<BoxBody<Bytes, hyper::Error>>;
+/// request body sent to the upstream,
+/// boxed so every [`GrpcKind`] can rewrite it
+pub type UpstreamBody = UnsyncBoxBody<Bytes, BoxError>;
+
+/// keep `guard` alive until the response body
+/// is finished or dropped by the client
+pub(crate) fn hold_until_end<G>(res: ProxyResponse, guard: G) -> ProxyResponse
+where
+    G: Send + Sync + 'static,
+{
+    res.map(|body| {
+        body.map_frame(move |frame| {
+            let _ = &guard;
+            frame
+        })
+        .boxed()
+    })
+}
+
 pub async fn proxy_request<B>(
     req: Request<B>,
-    authority: Authority,
-    metrics: Arc<Metrics>,
-) -> Result<ProxyResponse, BoxError>
+    ctx: Arc<ProxyContext>,
+) -> Result<ProxyResponse, Infallible>
 where
     B: hyper::body::Body<Data = Bytes> + Send + 'static + Unpin,
     B::Error: Into<BoxError>,
 {
-    //[START] switch endpoint
-    let (mut parts, req_body) = req.into_parts();
-    parts
-        .headers
-        .insert(hyper::header::HOST, authority.as_str().parse()?);
+    let metrics = &ctx.metrics;
 
+    // browsers send preflight before cross-origin calls,
+    // upstream does not know how to answer them
+    if let Some(cors) = &ctx.cors
+        && CorsPolicy::is_preflight(req.method(), req.headers())
+    {
+        return Ok(cors.preflight(req.headers()));
+    }
+
+    let (parts, req_body) = req.into_parts();
     let path = parts.uri.path().to_string();
 
-    // Early exit for /metrics
+    // Early exit for /metrics and /readyz
     if path == "/metrics" {
         return Ok(metrics.render());
     }
+    if path == "/readyz" {
+        let ready = ctx.readiness.as_ref().is_none_or(|gate| gate.is_ready());
+        return Ok(readyz(ready));
+    }
     let start = Instant::now();
     defer!({
         let elapsed = start.elapsed().as_secs_f64();
@@ -49,35 +101,172 @@ where
             .with_label_values(&[&"POST", &path.as_str()])
             .observe(elapsed);
     });
-    let url = format!("http://{}{}", authority.as_ref(), path);
 
-    parts.uri = url.parse::<Uri>()?;
+    // browsers stream requests over a WebSocket,
+    // the call runs once the connection is upgraded
+    if websocket::is_websocket(&parts) {
+        return Ok(websocket::upgrade(parts, ctx.clone()));
+    }
 
-    //[END] switch endpoint
+    let req_headers = ctx.cors.as_ref().map(|_| parts.headers.clone());
 
-    let stream = TcpStream::connect(authority.to_string()).await?;
-    let io = TokioIo::new(stream);
+    // failures are answered with a gRPC status,
+    // the client never sees a reset stream
+    let kind = GrpcKind::from_headers(&parts.headers);
+    // framed calls always go upstream as they are,
+    // HTTP/JSON requests look like Connect unary ones
+    let transcoded = match &kind {
+        Ok(GrpcKind::Connect(GrpcKindConnect {
+            streaming: false, ..
+        }))
+        | Err(_) => ctx
+            .transcoder
+            .as_ref()
+            .and_then(|transcoder| transcoder.route(&parts.method, &path)),
+        Ok(_) => None,
+    };
+    let mut res = match (transcoded, kind) {
+        (Some(call), _) => transcode_call(parts, req_body, call, &ctx).await,
+        (None, Ok(kind)) => forward_call(parts, req_body, &kind, &ctx)
+            .await
+            .unwrap_or_else(|err| err.into_response(Some(&kind))),
+        (None, Err(err)) => err.into_response(None),
+    };
 
-    let exec = TokioExecutor::new();
-    let (sender, conn): (
-        http2::SendRequest<_>,
-        http2::Connection<TokioIo<TcpStream>, _, TokioExecutor>,
-    ) = http2::Builder::new(exec).handshake(io).await?;
+    if let (Some(cors), Some(req_headers)) = (&ctx.cors, req_headers) {
+        cors.apply(&req_headers, &mut res);
+    }
+    Ok(res)
+}
 
-    // Spawn a task to poll the connection, driving the HTTP state
-    tokio::task::spawn(async move {
-        if let Err(err) = conn.await {
-            println!("Connection failed: {:?}", err);
-        }
-    });
-    let content_type = parts
-        .headers
-        .get(CONTENT_TYPE)
-        .ok_or("Missing Content-Type header")?
-        .clone();
-    let req = Request::from_parts(parts, req_body);
-    GrpcKind::from_content_type(&content_type)
-        .ok_or("Unsupported Content-Type header")?
-        .forward(sender, req)
+/// an HTTP/JSON call forwarded as
+/// the gRPC method its rule maps to
+async fn transcode_call<B>(
+    mut parts: Parts,
+    req_body: B,
+    call: TranscodedCall<'_>,
+    ctx: &ProxyContext,
+) -> ProxyResponse
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static + Unpin,
+    B::Error: Into<BoxError>,
+{
+    let kind = call.kind();
+    let limit = ctx.message_size.limits(&call.path()).request;
+    let res = match call.request(&mut parts, req_body, limit).await {
+        Ok(message) => forward_call(parts, Full::new(message), &kind, ctx).await,
+        Err(err) => Err(err),
+    };
+    res.unwrap_or_else(|err| err.into_response(Some(&kind)))
+}
+
+pub(crate) async fn forward_call<B>(
+    mut parts: Parts,
+    req_body: B,
+    kind: &GrpcKind,
+    ctx: &ProxyContext,
+) -> Result<ProxyResponse, ProxyError>
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static + Unpin,
+    B::Error: Into<BoxError>,
+{
+    kind.modify_headers(&mut parts.headers);
+    let deadline = ctx
+        .deadline
+        .timeout(&parts.headers)
+        .map(|timeout| Instant::now() + timeout);
+    let call = send_call(parts, req_body, deadline, kind, ctx);
+    let res = match deadline {
+        Some(deadline) => timeout_at(deadline, call)
+            .await
+            .map_err(|_| ProxyError::DeadlineExceeded)??,
+        None => call.await?,
+    };
+    Ok(res)
+}
+
+/// forward the call until the upstream response headers
+async fn send_call<B>(
+    mut parts: Parts,
+    req_body: B,
+    deadline: Option<Instant>,
+    kind: &GrpcKind,
+    ctx: &ProxyContext,
+) -> Result<ProxyResponse, ProxyError>
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static + Unpin,
+    B::Error: Into<BoxError>,
+{
+    let recompression = ctx
+        .compression
+        .iter()
+        .find(|rule| rule.matches(parts.uri.path()))
+        .map(|rule| Recompression::new(rule, &parts.headers))
+        .transpose()?;
+
+    // hold calls while the upstream is starting
+    if let Some(gate) = &ctx.readiness {
+        gate.wait().await.map_err(ProxyError::NotReady)?;
+    }
+
+    //[START] switch endpoint
+    let routed = ctx.router.route(parts.uri.path(), &parts.headers);
+    let cluster = routed.cluster.unwrap_or(&ctx.cluster);
+    let mirror = routed
+        .mirror
+        .filter(|policy| policy.sample())
+        .and_then(|policy| Mirror::new(policy, ctx));
+    let mut call = ClusterCall::new(cluster.name(), deadline, ctx.metrics.clone());
+    // picked per RPC, calls multiplexed on one
+    // downstream connection spread over all replicas
+    let endpoint = cluster
+        .pick()
+        .ok_or_else(|| call.fail(ProxyError::NoEndpoint))?;
+    let authority = endpoint.authority();
+    let host = HeaderValue::from_str(authority.as_str())
+        .map_err(|err| ProxyError::InvalidRequest(err.into()))?;
+    parts.headers.insert(hyper::header::HOST, host);
+    let url = format!("http://{}{}", authority.as_ref(), parts.uri.path());
+
+    parts.uri = url
+        .parse::<Uri>()
+        .map_err(|err| ProxyError::InvalidRequest(err.into()))?;
+
+    //[END] switch endpoint
+
+    let stream = ctx
+        .pool
+        .checkout(authority, cluster.protocol(), ctx.max_concurrent_streams)
         .await
+        .map_err(|err| call.fail(ProxyError::Connect(err)))?;
+    // the upstream gets the time left
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        parts.headers.insert(GRPC_TIMEOUT, encode_timeout(left));
+    }
+    // the upstream stream is reset when the client goes away
+    // or the deadline passes before the response is finished
+    let path = parts.uri.path().to_string();
+    let limits = ctx.message_size.limits(&path);
+    let (req_body, cancel) = cancellation(req_body, deadline, ctx.metrics.clone(), path);
+    let req = Request::from_parts(parts, req_body);
+    let mut res = kind
+        .forward(
+            stream.sender(),
+            req,
+            recompression.as_ref(),
+            limits,
+            call,
+            mirror,
+        )
+        .await?;
+    let status = kind.status_frame(
+        ProxyError::DeadlineExceeded.code(),
+        &ProxyError::DeadlineExceeded.to_string(),
+    );
+    if let (Some(deadline), Some(status)) = (deadline, status) {
+        res = expire_at(res, deadline, status);
+    }
+    let res = cancel_on_drop(res, cancel);
+    Ok(hold_until_end(res, (stream, endpoint)))
 }
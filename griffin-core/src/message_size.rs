@@ -0,0 +1,359 @@
+//!
+//! Message size limits and frame checks.
+//! Bodies are read as a [`FrameStream`] and each message is passed
+//! on once complete, a length prefix above the limit ends the call
+//! with RESOURCE_EXHAUSTED before the message is buffered, and so
+//! does a compressed message growing past it while decompressed.
+//! A body ending inside a frame or a frame with unknown flags
+//! ends it with INTERNAL.
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http::{HeaderMap, Request};
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tower::BoxError;
+
+use crate::compression::Encoding;
+use crate::error::ProxyError;
+use crate::frame::{FrameError, FrameStream, GrpcFrame};
+use crate::status;
+use crate::{ProxyResponse, UpstreamBody};
+
+/// ```yaml
+/// message_size:
+///   max_request_bytes: 4194304
+///   max_response_bytes: 16777216
+///   routes:
+///     - prefix: /helloworld.Greeter/SayHelloBiStream
+///       max_request_bytes: 65536
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MessageSizeConfig {
+    /// largest client message, unlimited when missing
+    pub max_request_bytes: Option<usize>,
+    /// largest upstream message, unlimited when missing
+    pub max_response_bytes: Option<usize>,
+    /// limits of calls whose path starts with a prefix,
+    /// the first matching route is used
+    pub routes: Vec<MessageSizeRoute>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MessageSizeRoute {
+    /// calls whose path starts with it
+    pub prefix: String,
+    /// the listener limits apply when missing
+    pub max_request_bytes: Option<usize>,
+    pub max_response_bytes: Option<usize>,
+}
+
+impl Default for MessageSizeRoute {
+    fn default() -> Self {
+        Self {
+            prefix: "/".into(),
+            max_request_bytes: None,
+            max_response_bytes: None,
+        }
+    }
+}
+
+impl MessageSizeConfig {
+    /// limits of the call to `path`
+    pub fn limits(&self, path: &str) -> MessageLimits {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| path.starts_with(&route.prefix));
+        MessageLimits {
+            request: route
+                .and_then(|route| route.max_request_bytes)
+                .or(self.max_request_bytes),
+            response: route
+                .and_then(|route| route.max_response_bytes)
+                .or(self.max_response_bytes),
+        }
+    }
+}
+
+/// Largest messages of one call,
+/// compressed messages count with their decompressed size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageLimits {
+    pub request: Option<usize>,
+    pub response: Option<usize>,
+}
+
+/// A client message broke the rules,
+/// the upstream stream is reset and the
+/// status is read back from the response side
+#[derive(Clone, Default)]
+pub(crate) struct Violation(Arc<Mutex<Option<ProxyError>>>);
+
+impl Violation {
+    pub(crate) fn set(&self, err: ProxyError) {
+        let mut found = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        found.get_or_insert(err);
+    }
+
+    /// keep `err` and fail the body with its reason
+    pub(crate) fn fail(&self, err: ProxyError) -> BoxError {
+        let reason = err.to_string();
+        self.set(err);
+        BoxError::from(reason)
+    }
+
+    pub(crate) fn take(&self) -> Option<ProxyError> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+    }
+}
+
+impl MessageLimits {
+    /// check the client messages, the first bad one
+    /// fails the request body and is kept in the violation
+    pub(crate) fn request(&self, req: &mut Request<UpstreamBody>, violation: &Violation) {
+        let found = violation.clone();
+        let limit = self.request;
+        let encoding = compressed_encoding(req.headers());
+        let body = std::mem::take(req.body_mut());
+        let mut frames = FrameStream::new(body).with_max_len(self.request);
+        let checked = stream! {
+            while let Some(frame) = frames.next().await {
+                let checked = match frame {
+                    Ok(frame) => body_frame(frame, "request", limit, encoding),
+                    Err(FrameError::Body(err)) => {
+                        yield Err(err);
+                        return;
+                    }
+                    Err(err) => Err(frame_violation(err, "request")),
+                };
+                match checked {
+                    Ok(frame) => yield Ok(frame),
+                    Err(err) => {
+                        yield Err(found.fail(err));
+                        return;
+                    }
+                }
+            }
+        };
+        *req.body_mut() = StreamBody::new(checked).boxed_unsync();
+    }
+
+    /// check the upstream messages, the first bad one
+    /// ends the response with its status in the trailers.
+    /// A stream reset because of a client message ends
+    /// with the status of that message
+    pub(crate) fn response(&self, res: ProxyResponse, violation: Violation) -> ProxyResponse {
+        let limit = self.response;
+        let encoding = compressed_encoding(res.headers());
+        let (parts, body) = res.into_parts();
+        let mut frames = FrameStream::new(body).with_max_len(limit);
+        let checked = stream! {
+            while let Some(frame) = frames.next().await {
+                let checked = match frame {
+                    Ok(frame) => body_frame(frame, "response", limit, encoding),
+                    Err(FrameError::Body(err)) => {
+                        if let Some(violation) = violation.take() {
+                            yield Ok(status_trailers(&violation));
+                            return;
+                        }
+                        // the upstream error, read back for its status
+                        match err.downcast::<hyper::Error>() {
+                            Ok(err) => yield Err(*err),
+                            Err(err) => {
+                                let err = ProxyError::MalformedMessage(err.to_string());
+                                yield Ok(status_trailers(&err));
+                            }
+                        }
+                        return;
+                    }
+                    Err(err) => Err(frame_violation(err, "response")),
+                };
+                match checked {
+                    Ok(frame) => yield Ok(frame),
+                    Err(err) => {
+                        yield Ok(status_trailers(&err));
+                        return;
+                    }
+                }
+            }
+        };
+        ProxyResponse::from_parts(parts, BodyExt::boxed(StreamBody::new(checked)))
+    }
+}
+
+/// the encoding of compressed messages,
+/// `None` when Griffin cannot read it
+fn compressed_encoding(headers: &HeaderMap) -> Option<Encoding> {
+    Encoding::from_headers(headers)
+        .ok()
+        .filter(|encoding| *encoding != Encoding::Identity)
+}
+
+/// a checked frame as it is passed on,
+/// compressed messages are decompressed up to the limit
+fn body_frame(
+    frame: GrpcFrame,
+    side: &str,
+    limit: Option<usize>,
+    encoding: Option<Encoding>,
+) -> Result<Frame<Bytes>, ProxyError> {
+    match frame {
+        GrpcFrame::Trailers(trailers) => Ok(Frame::trailers(trailers)),
+        GrpcFrame::Message {
+            compressed,
+            ref payload,
+        } => {
+            if let (true, Some(limit), Some(encoding)) = (compressed, limit, encoding) {
+                encoding
+                    .decompress(payload, limit)
+                    .map_err(|err| match err {
+                        ProxyError::MessageTooLarge(reason) => {
+                            ProxyError::MessageTooLarge(format!("{} {}", side, reason))
+                        }
+                        err => err,
+                    })?;
+            }
+            frame
+                .encode()
+                .map(Frame::data)
+                .map_err(|err| frame_violation(err, side))
+        }
+    }
+}
+
+fn frame_violation(err: FrameError, side: &str) -> ProxyError {
+    match err {
+        FrameError::TooLarge { len, max } => ProxyError::MessageTooLarge(format!(
+            "{} message of {} bytes is larger than the limit of {} bytes",
+            side, len, max
+        )),
+        FrameError::Truncated => {
+            ProxyError::MalformedMessage(format!("{} body ended inside a message", side))
+        }
+        err => ProxyError::MalformedMessage(format!("{} {}", side, err)),
+    }
+}
+
+fn status_trailers<T>(err: &ProxyError) -> Frame<T> {
+    Frame::trailers(status::status_headers(err.code(), &err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    use crate::compression::GRPC_ENCODING;
+    use crate::frame::{COMPRESSED, encode_frame};
+    use crate::status::Code;
+    use crate::telemetry::metrics::from_full_bytes;
+
+    async fn response_code(limits: MessageLimits, body: Bytes) -> Code {
+        let res = limits.response(from_full_bytes(Full::new(body)), Violation::default());
+        let collected = res.into_body().collect().await.unwrap();
+        collected
+            .trailers()
+            .map_or(Code::Ok, |trailers| status::status_of(trailers).0)
+    }
+
+    #[test]
+    fn test_route_limits_override_listener_limits() {
+        let config = MessageSizeConfig {
+            max_request_bytes: Some(100),
+            max_response_bytes: Some(200),
+            routes: vec![MessageSizeRoute {
+                prefix: "/helloworld.Greeter/SayHelloBiStream".into(),
+                max_request_bytes: Some(10),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            config.limits("/helloworld.Greeter/SayHelloBiStream"),
+            MessageLimits {
+                request: Some(10),
+                response: Some(200),
+            }
+        );
+        assert_eq!(
+            config.limits("/helloworld.Greeter/SayHello"),
+            MessageLimits {
+                request: Some(100),
+                response: Some(200),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_response_limits() {
+        let limits = MessageLimits {
+            request: None,
+            response: Some(5),
+        };
+        assert_eq!(
+            response_code(limits, encode_frame(0, b"hello").unwrap()).await,
+            Code::Ok
+        );
+        assert_eq!(
+            response_code(limits, encode_frame(0, b"griffin").unwrap()).await,
+            Code::ResourceExhausted
+        );
+        // the declared length goes past the end of the body
+        let frame = encode_frame(0, b"hello").unwrap();
+        assert_eq!(
+            response_code(limits, frame.slice(..7)).await,
+            Code::Internal
+        );
+        assert_eq!(
+            response_code(limits, encode_frame(0x04, b"").unwrap()).await,
+            Code::Internal
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_violation_is_kept() {
+        let limits = MessageLimits {
+            request: Some(5),
+            response: None,
+        };
+        let body = Full::new(encode_frame(0, b"griffin").unwrap())
+            .map_err(Into::into)
+            .boxed_unsync();
+        let mut req = Request::new(body);
+        let violation = Violation::default();
+        limits.request(&mut req, &violation);
+        assert!(req.into_body().collect().await.is_err());
+        assert_eq!(violation.take().unwrap().code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_messages_count_decompressed() {
+        let limits = MessageLimits {
+            request: Some(1024),
+            response: None,
+        };
+        let bomb = Encoding::Gzip.compress(&[0; 64 * 1024]).unwrap();
+        assert!(bomb.len() < 1024);
+        let body = Full::new(encode_frame(COMPRESSED, &bomb).unwrap())
+            .map_err(Into::into)
+            .boxed_unsync();
+        let mut req = Request::new(body);
+        req.headers_mut()
+            .insert(GRPC_ENCODING, http::HeaderValue::from_static("gzip"));
+        let violation = Violation::default();
+        limits.request(&mut req, &violation);
+        assert!(req.into_body().collect().await.is_err());
+        assert_eq!(violation.take().unwrap().code(), Code::ResourceExhausted);
+    }
+}
@@ -0,0 +1,437 @@
+//!
+//! Mirroring a share of the calls of a route to a shadow cluster,
+//! to validate a new backend against production traffic.
+//! The request body is copied while it streams to the upstream,
+//! the mirrored call starts once the client finished sending
+//! and its response is read and discarded.
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use http::{HeaderValue, Request, Uri, request::Parts};
+use http_body_util::{BodyExt, Full, StreamBody};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tower::BoxError;
+
+use crate::UpstreamBody;
+use crate::context::ProxyContext;
+use crate::error::{ProxyError, upstream_error_code};
+use crate::frame::{Deframer, encode_frame};
+use crate::status::{self, Code};
+use crate::telemetry::metrics::Metrics;
+use crate::upstream::cluster::{Cluster, UpstreamProtocol};
+use crate::upstream::connection_pool::ConnectionPool;
+use crate::upstream::grpc_web;
+
+/// ```yaml
+/// mirror:
+///   cluster: cart-v2
+///   percent: 10
+///   max_body_bytes: 65536
+///   max_in_flight: 100
+///   timeout_ms: 10000
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MirrorConfig {
+    /// name of a cluster in `clusters`
+    pub cluster: String,
+    /// share of the calls of the route that are mirrored
+    pub percent: f64,
+    /// largest request message copied for one mirrored call,
+    /// calls with a larger message are not mirrored
+    pub max_body_bytes: usize,
+    /// mirrored calls of the route copying or running at once,
+    /// calls sampled while all of them are taken are not mirrored
+    pub max_in_flight: usize,
+    /// mirrored calls still running after it are cancelled
+    pub timeout_ms: u64,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            cluster: String::new(),
+            percent: 100.0,
+            max_body_bytes: 64 * 1024,
+            max_in_flight: 100,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+/// compiled [`MirrorConfig`]
+pub struct MirrorPolicy {
+    cluster: Arc<Cluster>,
+    percent: f64,
+    max_body_bytes: usize,
+    in_flight: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl MirrorPolicy {
+    pub fn new(config: &MirrorConfig, cluster: Arc<Cluster>) -> Result<Self, BoxError> {
+        if !(0.0..=100.0).contains(&config.percent) {
+            return Err(format!("Mirror percent {} is not in 0..=100", config.percent).into());
+        }
+        if config.max_in_flight == 0 {
+            return Err("Mirror max_in_flight must be at least 1".into());
+        }
+        Ok(Self {
+            cluster,
+            percent: config.percent,
+            max_body_bytes: config.max_body_bytes,
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight)),
+            timeout: Duration::from_millis(config.timeout_ms),
+        })
+    }
+
+    /// whether the next call is mirrored
+    pub fn sample(&self) -> bool {
+        fastrand::f64() * 100.0 < self.percent
+    }
+
+    pub fn cluster(&self) -> &Cluster {
+        &self.cluster
+    }
+}
+
+/// Why a sampled call was not mirrored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Skipped {
+    BodyTooLarge,
+    /// more than one request message,
+    /// client and bidirectional streams are not mirrored
+    Streaming,
+    /// the request body failed or ended
+    /// inside a message, the call is not complete
+    Incomplete,
+    /// `max_in_flight` mirrored calls are running
+    Overloaded,
+}
+
+impl Skipped {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Skipped::BodyTooLarge => "body_too_large",
+            Skipped::Streaming => "streaming",
+            Skipped::Incomplete => "incomplete",
+            Skipped::Overloaded => "overloaded",
+        }
+    }
+}
+
+/// A call picked for mirroring
+pub struct Mirror {
+    cluster: Arc<Cluster>,
+    max_body_bytes: usize,
+    timeout: Duration,
+    pool: Arc<ConnectionPool>,
+    max_streams: usize,
+    metrics: Arc<Metrics>,
+    /// slot of the call in `max_in_flight`,
+    /// released once the mirrored call ends
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Mirror {
+    /// `None` when `max_in_flight` calls are mirrored already,
+    /// the call is counted as skipped without waiting for a slot
+    pub(crate) fn new(policy: &MirrorPolicy, ctx: &ProxyContext) -> Option<Self> {
+        let Ok(permit) = policy.in_flight.clone().try_acquire_owned() else {
+            ctx.metrics
+                .mirror_skipped()
+                .with_label_values(&[policy.cluster.name(), Skipped::Overloaded.as_str()])
+                .inc();
+            return None;
+        };
+        Some(Self {
+            cluster: policy.cluster.clone(),
+            max_body_bytes: policy.max_body_bytes,
+            timeout: policy.timeout,
+            pool: ctx.pool.clone(),
+            max_streams: ctx.max_concurrent_streams,
+            metrics: ctx.metrics.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// copy the request body as it streams to the upstream,
+    /// the frames are passed on right away and the mirrored
+    /// call is spawned once the body is complete
+    pub(crate) fn tee(self, req: &mut Request<UpstreamBody>) {
+        let head = request_head(req);
+        let mut body = std::mem::take(req.body_mut());
+        let teed = stream! {
+            let mut copy = Some(Deframer::with_max_len(Some(self.max_body_bytes)));
+            // the one message of a mirrored call
+            let mut message = None;
+            while let Some(frame) = body.frame().await {
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        if copy.is_some() {
+                            self.skip(Skipped::Incomplete);
+                        }
+                        yield Err(err);
+                        return;
+                    }
+                };
+                if let (Some(deframer), Some(data)) = (&mut copy, frame.data_ref()) {
+                    deframer.push(data);
+                    let skipped = loop {
+                        match deframer.next_frame() {
+                            Ok(Some(frame)) if message.is_none() => message = Some(frame),
+                            Ok(Some(_)) => break Some(Skipped::Streaming),
+                            Ok(None) => break None,
+                            Err(_) => break Some(Skipped::BodyTooLarge),
+                        }
+                    };
+                    if let Some(skipped) = skipped {
+                        // the copy is freed right away
+                        copy = None;
+                        self.skip(skipped);
+                    }
+                }
+                yield Ok(frame);
+            }
+            let Some(deframer) = copy else {
+                return;
+            };
+            let body = match message {
+                Some((flag, payload)) => encode_frame(flag, &payload),
+                None => Ok(Bytes::new()),
+            };
+            match body {
+                Ok(body) if deframer.is_empty() => {
+                    tokio::spawn(self.send(head, body));
+                }
+                Ok(_) => self.skip(Skipped::Incomplete),
+                Err(_) => self.skip(Skipped::BodyTooLarge),
+            }
+        };
+        *req.body_mut() = StreamBody::new(teed).boxed_unsync();
+    }
+
+    fn skip(&self, skipped: Skipped) {
+        self.metrics
+            .mirror_skipped()
+            .with_label_values(&[self.cluster.name(), skipped.as_str()])
+            .inc();
+    }
+
+    async fn send(self, head: Parts, body: Bytes) {
+        let code = timeout(self.timeout, self.call(head, body))
+            .await
+            .unwrap_or(Code::DeadlineExceeded);
+        self.metrics
+            .mirror_rpcs()
+            .with_label_values(&[self.cluster.name(), code.as_str()])
+            .inc();
+    }
+
+    /// the status of the mirrored call,
+    /// its messages are discarded
+    async fn call(&self, mut head: Parts, body: Bytes) -> Code {
+        let Some(endpoint) = self.cluster.pick() else {
+            return ProxyError::NoEndpoint.code();
+        };
+        let authority = endpoint.authority();
+        let url = format!("http://{}{}", authority, head.uri.path());
+        let (Ok(uri), Ok(host)) = (
+            url.parse::<Uri>(),
+            HeaderValue::from_str(authority.as_str()),
+        ) else {
+            return Code::Internal;
+        };
+        head.uri = uri;
+        head.headers.insert(http::header::HOST, host);
+
+        let protocol = self.cluster.protocol();
+        let Ok(stream) = self
+            .pool
+            .checkout(authority, protocol, self.max_streams)
+            .await
+        else {
+            return Code::Unavailable;
+        };
+        let body = Full::new(body).map_err(Into::into).boxed_unsync();
+        let mut req = Request::from_parts(head, body);
+        if protocol == UpstreamProtocol::GrpcWeb {
+            grpc_web::modify_request(&mut req);
+        }
+        let res = match stream.sender().send_request(req).await {
+            Ok(res) => res.map(BodyExt::boxed),
+            Err(err) => return upstream_error_code(&err),
+        };
+        let res = match protocol {
+            UpstreamProtocol::Grpc => res,
+            UpstreamProtocol::GrpcWeb => grpc_web::modify_response(res),
+        };
+        // a Trailers-Only response carries the status in its headers
+        if res.headers().contains_key("grpc-status") {
+            return status::status_of(res.headers()).0;
+        }
+        // only the trailers are kept, the messages
+        // of a server stream are dropped as they arrive
+        let mut body = res.into_body();
+        while let Some(frame) = body.frame().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => return upstream_error_code(&err),
+            };
+            if let Ok(trailers) = frame.into_trailers() {
+                return status::status_of(&trailers).0;
+            }
+        }
+        Code::Internal
+    }
+}
+
+/// method, uri and headers of the request sent upstream
+fn request_head<B>(req: &Request<B>) -> Parts {
+    let (mut head, ()) = Request::new(()).into_parts();
+    head.method = req.method().clone();
+    head.uri = req.uri().clone();
+    head.version = req.version();
+    head.headers = req.headers().clone();
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Frame;
+
+    use crate::frame::encode_frame;
+    use crate::upstream::cluster::ClusterConfig;
+
+    fn cluster(name: &str) -> Arc<Cluster> {
+        let cluster = Cluster::new(&ClusterConfig {
+            name: name.into(),
+            // nothing listens there, the mirrored call fails
+            endpoints: vec!["127.0.0.1:1".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        Arc::new(cluster)
+    }
+
+    fn mirror(name: &str, max_body_bytes: usize) -> Mirror {
+        let config = MirrorConfig {
+            max_body_bytes,
+            ..Default::default()
+        };
+        let policy = MirrorPolicy::new(&config, cluster(name)).unwrap();
+        Mirror::new(&policy, &context(name)).unwrap()
+    }
+
+    fn context(name: &str) -> ProxyContext {
+        let primary = Cluster::new(&ClusterConfig {
+            name: format!("{name}-primary"),
+            endpoints: vec!["127.0.0.1:1".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        ProxyContext::new(primary, Arc::new(Metrics::new()))
+    }
+
+    fn skipped(cluster: &str, skipped: Skipped) -> f64 {
+        Metrics::new()
+            .mirror_skipped()
+            .with_label_values(&[cluster, skipped.as_str()])
+            .get()
+    }
+
+    fn request(frames: Vec<Bytes>) -> Request<UpstreamBody> {
+        let frames = frames
+            .into_iter()
+            .map(|data| Ok::<_, BoxError>(Frame::data(data)));
+        Request::post("http://127.0.0.1:3000/helloworld.Greeter/SayHello")
+            .body(StreamBody::new(futures_util::stream::iter(frames)).boxed_unsync())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_policy_checks_percent() {
+        let cluster = cluster("test-percent");
+        let config = |percent| MirrorConfig {
+            percent,
+            ..Default::default()
+        };
+        assert!(MirrorPolicy::new(&config(120.0), cluster.clone()).is_err());
+        assert!(
+            !MirrorPolicy::new(&config(0.0), cluster.clone())
+                .unwrap()
+                .sample()
+        );
+        assert!(MirrorPolicy::new(&config(100.0), cluster).unwrap().sample());
+    }
+
+    #[test]
+    fn test_mirrored_calls_are_limited() {
+        let config = MirrorConfig {
+            max_in_flight: 1,
+            ..Default::default()
+        };
+        let policy = MirrorPolicy::new(&config, cluster("test-overloaded")).unwrap();
+        let ctx = context("test-overloaded");
+
+        let first = Mirror::new(&policy, &ctx);
+        assert!(first.is_some());
+        assert!(Mirror::new(&policy, &ctx).is_none());
+        assert_eq!(skipped("test-overloaded", Skipped::Overloaded), 1.0);
+
+        // the slot is free once the first call ends
+        drop(first);
+        assert!(Mirror::new(&policy, &ctx).is_some());
+
+        let config = MirrorConfig {
+            max_in_flight: 0,
+            ..Default::default()
+        };
+        assert!(MirrorPolicy::new(&config, cluster("test-overloaded")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_primary_body_is_unchanged() {
+        let frames = vec![
+            encode_frame(0, b"hello").unwrap(),
+            encode_frame(0, b"world").unwrap(),
+        ];
+        let mut req = request(frames.clone());
+        mirror("test-streaming", 1024).tee(&mut req);
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, [frames[0].clone(), frames[1].clone()].concat());
+        assert_eq!(skipped("test-streaming", Skipped::Streaming), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_large_body_is_not_mirrored() {
+        let mut req = request(vec![encode_frame(0, &[0; 64]).unwrap()]);
+        mirror("test-large", 16).tee(&mut req);
+        req.into_body().collect().await.unwrap();
+        assert_eq!(skipped("test-large", Skipped::BodyTooLarge), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_mirror_is_counted() {
+        let mut req = request(vec![encode_frame(0, b"hello").unwrap()]);
+        mirror("test-failed", 1024).tee(&mut req);
+        req.into_body().collect().await.unwrap();
+        let metrics = Metrics::new();
+        let failed = metrics
+            .mirror_rpcs()
+            .with_label_values(&["test-failed", Code::Unavailable.as_str()]);
+        for _ in 0..50 {
+            if failed.get() > 0.0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(failed.get(), 1.0);
+    }
+}
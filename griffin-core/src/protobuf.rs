@@ -0,0 +1,69 @@
+//!
+//! Just enough of the protobuf wire format
+//! for the few messages Griffin reads and writes itself.
+//! <https://protobuf.dev/programming-guides/encoding/>
+//!
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tower::BoxError;
+
+/// value of a field, fixed size values are skipped
+pub(crate) enum Field {
+    Varint(u64),
+    Bytes(Bytes),
+    Fixed,
+}
+
+/// next field number and value of a message
+pub(crate) fn next_field(buf: &mut Bytes) -> Result<Option<(u64, Field)>, BoxError> {
+    if !buf.has_remaining() {
+        return Ok(None);
+    }
+    let key = get_varint(buf)?;
+    let field = match key & 0x7 {
+        0 => Field::Varint(get_varint(buf)?),
+        1 => {
+            take(buf, 8)?;
+            Field::Fixed
+        }
+        2 => {
+            let len = get_varint(buf)? as usize;
+            Field::Bytes(take(buf, len)?)
+        }
+        5 => {
+            take(buf, 4)?;
+            Field::Fixed
+        }
+        _ => return Err("Unsupported protobuf wire type".into()),
+    };
+    Ok(Some((key >> 3, field)))
+}
+
+pub(crate) fn put_varint(buf: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+fn get_varint(buf: &mut Bytes) -> Result<u64, BoxError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        if !buf.has_remaining() {
+            break;
+        }
+        let byte = buf.get_u8();
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err("Malformed protobuf varint".into())
+}
+
+fn take(buf: &mut Bytes, len: usize) -> Result<Bytes, BoxError> {
+    if buf.remaining() < len {
+        return Err("Truncated protobuf field".into());
+    }
+    Ok(buf.split_to(len))
+}
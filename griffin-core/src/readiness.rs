@@ -0,0 +1,138 @@
+//!
+//! Startup gate for the upstream cold start.
+//! Until an upstream endpoint passes a health probe
+//! `/readyz` reports not ready and calls wait in a bounded queue
+//! instead of failing on a refused connection.
+//!
+use bytes::Bytes;
+use http::StatusCode;
+use http_body_util::Full;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::{Semaphore, watch};
+
+use crate::ProxyResponse;
+use crate::telemetry::metrics::from_full_bytes;
+
+/// ```yaml
+/// readiness:
+///   max_waiting: 1000
+///   wait_timeout_ms: 10000
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// calls waiting for the upstream at the same time,
+    /// calls above that fail right away
+    pub max_waiting: usize,
+    /// how long a call waits before it fails with UNAVAILABLE
+    pub wait_timeout_ms: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            max_waiting: 1000,
+            wait_timeout_ms: 10000,
+        }
+    }
+}
+
+/// Opened by the first passed health probe,
+/// it stays open afterwards
+pub struct ReadinessGate {
+    ready: watch::Sender<bool>,
+    waiting: Semaphore,
+    wait_timeout: Duration,
+}
+
+impl ReadinessGate {
+    pub fn new(config: &ReadinessConfig) -> Self {
+        Self {
+            ready: watch::Sender::new(false),
+            waiting: Semaphore::new(config.max_waiting),
+            wait_timeout: Duration::from_millis(config.wait_timeout_ms),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    pub fn mark_ready(&self) {
+        self.ready
+            .send_if_modified(|ready| !std::mem::replace(ready, true));
+    }
+
+    /// wait until the gate is open,
+    /// the error is the reason given to the client
+    pub async fn wait(&self) -> Result<(), &'static str> {
+        if self.is_ready() {
+            return Ok(());
+        }
+        let _permit = self
+            .waiting
+            .try_acquire()
+            .map_err(|_| "too many calls waiting for the upstream")?;
+        let mut ready = self.ready.subscribe();
+        match tokio::time::timeout(self.wait_timeout, ready.wait_for(|ready| *ready)).await {
+            Ok(Ok(_)) => Ok(()),
+            _ => Err("upstream not ready"),
+        }
+    }
+}
+
+/// answer `/readyz`
+pub fn readyz(ready: bool) -> ProxyResponse {
+    let (status, text) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    };
+    let mut res = from_full_bytes(Full::new(Bytes::from_static(text.as_bytes())));
+    *res.status_mut() = status;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn gate(max_waiting: usize, wait_timeout_ms: u64) -> Arc<ReadinessGate> {
+        Arc::new(ReadinessGate::new(&ReadinessConfig {
+            max_waiting,
+            wait_timeout_ms,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_waiting_call_resumes_when_ready() {
+        let gate = gate(1, 5000);
+        let waiting = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.wait().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!gate.is_ready());
+
+        gate.mark_ready();
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+        assert!(gate.is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_wait_times_out() {
+        let gate = gate(1, 10);
+        assert_eq!(gate.wait().await, Err("upstream not ready"));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_fails_right_away() {
+        let gate = gate(0, 5000);
+        assert_eq!(
+            gate.wait().await,
+            Err("too many calls waiting for the upstream")
+        );
+    }
+}
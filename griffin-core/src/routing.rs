@@ -0,0 +1,641 @@
+//!
+//! Routes sending calls to named upstream clusters
+//! by their gRPC path `/package.Service/Method`
+//! and their metadata.
+//! The first matching route wins, calls matching
+//! no route go to the listener `upstream`.
+//! A route can split its calls between clusters by weight,
+//! the weights change in place on reload.
+//! A route can also mirror a share of its calls to a shadow cluster.
+//!
+use http::{HeaderMap, HeaderName};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tower::BoxError;
+
+use crate::mirror::{MirrorConfig, MirrorPolicy};
+use crate::upstream::cluster::Cluster;
+
+/// ```yaml
+/// routes:
+///   - path: /helloworld.Greeter/SayHello
+///     cluster: greeter
+///   - prefix: /shop.orders.
+///     cluster: orders
+///   - regex: /shop\.billing\.v[0-9]+\..*
+///     cluster: billing
+///   - prefix: /
+///     headers:
+///       - name: x-canary
+///         exact: "true"
+///     cluster: canary
+///   - prefix: /shop.cart.
+///     weighted_clusters:
+///       - name: cart
+///         weight: 95
+///       - name: cart-canary
+///         weight: 5
+///   - prefix: /shop.search.
+///     cluster: search
+///     mirror:
+///       cluster: search-v2
+///       percent: 10
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RouteConfig {
+    #[serde(flatten)]
+    pub matcher: PathMatch,
+    /// metadata the call has to carry on top
+    /// of the path, every matcher has to match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<HeaderMatchConfig>,
+    #[serde(flatten)]
+    pub target: RouteTarget,
+    /// shadow cluster receiving a copy of the calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorConfig>,
+}
+
+/// Clusters are named in `clusters`,
+/// the listener `upstream` can be named as well
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteTarget {
+    Cluster(String),
+    /// every call goes to one of them,
+    /// in proportion to their weights
+    WeightedClusters(Vec<WeightedCluster>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WeightedCluster {
+    pub name: String,
+    /// `0` sends no call to the cluster
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathMatch {
+    /// one method, `/package.Service/Method`
+    Path(String),
+    /// a service, `/package.Service/`,
+    /// or every service of a package, `/package.`
+    Prefix(String),
+    /// matched against the whole path
+    Regex(String),
+}
+
+/// ```yaml
+/// headers:
+///   - name: x-tenant
+///     exact: acme
+///   - name: user-agent
+///     prefix: "shop-ios/"
+///   - name: x-canary
+///     present: true
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HeaderMatchConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub value: ValueMatch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueMatch {
+    Exact(String),
+    Prefix(String),
+    /// matched against the whole value
+    Regex(String),
+    /// `false` matches calls without the header
+    Present(bool),
+}
+
+/// matches a path or a header value
+enum Matcher {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// anchored so that a partial match
+    /// cannot send a call to the wrong cluster
+    fn regex(regex: &str) -> Result<Self, BoxError> {
+        Ok(Matcher::Regex(Regex::new(&format!("^(?:{})$", regex))?))
+    }
+
+    fn path(config: &PathMatch) -> Result<Self, BoxError> {
+        match config {
+            PathMatch::Path(path) => Ok(Matcher::Exact(path.clone())),
+            PathMatch::Prefix(prefix) => Ok(Matcher::Prefix(prefix.clone())),
+            PathMatch::Regex(regex) => Matcher::regex(regex),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Matcher::Exact(expected) => text == expected,
+            Matcher::Prefix(prefix) => text.starts_with(prefix),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+struct HeaderMatcher {
+    name: HeaderName,
+    /// `None` checks the presence only
+    value: Option<Matcher>,
+    present: bool,
+}
+
+impl HeaderMatcher {
+    fn new(config: &HeaderMatchConfig) -> Result<Self, BoxError> {
+        let name = HeaderName::from_bytes(config.name.as_bytes())?;
+        let (value, present) = match &config.value {
+            ValueMatch::Exact(exact) => (Some(Matcher::Exact(exact.clone())), true),
+            ValueMatch::Prefix(prefix) => (Some(Matcher::Prefix(prefix.clone())), true),
+            ValueMatch::Regex(regex) => (Some(Matcher::regex(regex)?), true),
+            ValueMatch::Present(present) => (None, *present),
+        };
+        Ok(Self {
+            name,
+            value,
+            present,
+        })
+    }
+
+    /// a repeated header matches when one of its values does
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        let mut values = headers.get_all(&self.name).iter();
+        match &self.value {
+            Some(matcher) => {
+                values.any(|value| value.to_str().is_ok_and(|value| matcher.matches(value)))
+            }
+            None => values.next().is_some() == self.present,
+        }
+    }
+}
+
+/// `None` is the listener `upstream`
+type Destination = Option<Arc<Cluster>>;
+
+enum Target {
+    Cluster(Destination),
+    Weighted(Vec<(AtomicU32, Destination)>),
+}
+
+impl Target {
+    fn pick(&self) -> Option<&Cluster> {
+        match self {
+            Target::Cluster(cluster) => cluster.as_deref(),
+            Target::Weighted(clusters) => {
+                // one pass over the weights, every cluster takes the pick
+                // with its share of the weights seen so far
+                let mut total = 0;
+                let mut picked = None;
+                for (weight, cluster) in clusters {
+                    let weight = weight.load(Ordering::Relaxed);
+                    total += weight;
+                    if weight > 0 && fastrand::u32(..total) < weight {
+                        picked = cluster.as_deref();
+                    }
+                }
+                picked
+            }
+        }
+    }
+}
+
+struct Route {
+    path: Matcher,
+    headers: Vec<HeaderMatcher>,
+    target: Target,
+    mirror: Option<MirrorPolicy>,
+}
+
+/// Where a call goes
+#[derive(Default)]
+pub struct Routed<'a> {
+    /// `None` for the listener `upstream`
+    pub cluster: Option<&'a Cluster>,
+    pub mirror: Option<&'a MirrorPolicy>,
+}
+
+/// Routes of a listener and
+/// the clusters they send calls to
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    clusters: Vec<Arc<Cluster>>,
+}
+
+impl Router {
+    /// `default` is the name of the listener `upstream`,
+    /// a route naming an unknown cluster is an error.
+    /// Mirrors go to named clusters only
+    pub fn new(
+        routes: &[RouteConfig],
+        clusters: Vec<Cluster>,
+        default: &str,
+    ) -> Result<Self, BoxError> {
+        let mut by_name = HashMap::new();
+        for cluster in clusters {
+            let name = cluster.name().to_string();
+            if name == default || by_name.contains_key(&name) {
+                return Err(format!("Cluster {} is defined twice", name).into());
+            }
+            by_name.insert(name, Arc::new(cluster));
+        }
+        let destination = |name: &String| match by_name.get(name) {
+            Some(cluster) => Ok(Some(cluster.clone())),
+            None if name == default => Ok(None),
+            None => Err(BoxError::from(format!("Route to unknown cluster {}", name))),
+        };
+        let routes = routes
+            .iter()
+            .map(|route| {
+                let target = match &route.target {
+                    RouteTarget::Cluster(name) => Target::Cluster(destination(name)?),
+                    RouteTarget::WeightedClusters(clusters) => {
+                        check_weights(clusters)?;
+                        Target::Weighted(
+                            clusters
+                                .iter()
+                                .map(|cluster| {
+                                    Ok((
+                                        AtomicU32::new(cluster.weight),
+                                        destination(&cluster.name)?,
+                                    ))
+                                })
+                                .collect::<Result<_, BoxError>>()?,
+                        )
+                    }
+                };
+                let mirror = route
+                    .mirror
+                    .as_ref()
+                    .map(|mirror| match by_name.get(&mirror.cluster) {
+                        Some(cluster) => MirrorPolicy::new(mirror, cluster.clone()),
+                        None => Err(format!("Mirror to unknown cluster {}", mirror.cluster).into()),
+                    })
+                    .transpose()?;
+                Ok(Route {
+                    path: Matcher::path(&route.matcher)?,
+                    headers: route
+                        .headers
+                        .iter()
+                        .map(HeaderMatcher::new)
+                        .collect::<Result<_, _>>()?,
+                    target,
+                    mirror,
+                })
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        Ok(Self {
+            routes,
+            clusters: by_name.into_values().collect(),
+        })
+    }
+
+    /// the cluster of the call to `path`
+    /// and the mirror of its route
+    pub fn route(&self, path: &str, headers: &HeaderMap) -> Routed<'_> {
+        self.routes
+            .iter()
+            .find(|route| {
+                route.path.matches(path)
+                    && route.headers.iter().all(|header| header.matches(headers))
+            })
+            .map_or_else(Routed::default, |route| Routed {
+                cluster: route.target.pick(),
+                mirror: route.mirror.as_ref(),
+            })
+    }
+
+    /// take the weights of `routes`, the same routes
+    /// as the router was built with but for their weights.
+    /// Calls in flight keep the cluster they were sent to
+    pub fn set_weights(&self, routes: &[RouteConfig]) -> Result<(), BoxError> {
+        if routes.len() != self.routes.len() {
+            return Err("routes changed besides their weights".into());
+        }
+        let mut updates = Vec::new();
+        for (route, config) in self.routes.iter().zip(routes) {
+            match (&route.target, &config.target) {
+                (Target::Weighted(current), RouteTarget::WeightedClusters(clusters))
+                    if current.len() == clusters.len() =>
+                {
+                    check_weights(clusters)?;
+                    updates.extend(
+                        current
+                            .iter()
+                            .zip(clusters)
+                            .map(|((weight, _), cluster)| (weight, cluster.weight)),
+                    );
+                }
+                (Target::Cluster(_), RouteTarget::Cluster(_)) => {}
+                _ => return Err("routes changed besides their weights".into()),
+            }
+        }
+        // nothing changes unless every route is valid
+        for (weight, value) in updates {
+            weight.store(value, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// named clusters, the listener `upstream` excluded
+    pub fn clusters(&self) -> &[Arc<Cluster>] {
+        &self.clusters
+    }
+}
+
+/// a weighted route sends calls somewhere
+fn check_weights(clusters: &[WeightedCluster]) -> Result<(), BoxError> {
+    let total = clusters
+        .iter()
+        .try_fold(0u32, |total, cluster| total.checked_add(cluster.weight));
+    match total {
+        Some(0) => Err("Weighted clusters without weight".into()),
+        Some(_) => Ok(()),
+        None => Err("Weights of weighted clusters overflow".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::cluster::ClusterConfig;
+
+    fn cluster(name: &str) -> Cluster {
+        Cluster::new(&ClusterConfig {
+            name: name.into(),
+            endpoints: vec!["127.0.0.1:3000".into()],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn route(matcher: PathMatch, cluster: &str) -> RouteConfig {
+        RouteConfig {
+            matcher,
+            headers: Vec::new(),
+            target: RouteTarget::Cluster(cluster.into()),
+            mirror: None,
+        }
+    }
+
+    #[test]
+    fn test_first_matching_route_wins() {
+        let router = Router::new(
+            &[
+                route(
+                    PathMatch::Path("/shop.orders.v1.Orders/Get".into()),
+                    "default",
+                ),
+                route(PathMatch::Prefix("/shop.orders.".into()), "orders"),
+                route(
+                    PathMatch::Regex(r"/shop\.billing\.v[0-9]+\..*".into()),
+                    "billing",
+                ),
+            ],
+            vec![cluster("orders"), cluster("billing")],
+            "default",
+        )
+        .unwrap();
+        let name = |path| {
+            router
+                .route(path, &HeaderMap::new())
+                .cluster
+                .map(Cluster::name)
+        };
+
+        assert_eq!(name("/shop.orders.v1.Orders/Get"), None);
+        assert_eq!(name("/shop.orders.v1.Orders/List"), Some("orders"));
+        assert_eq!(name("/shop.billing.v2.Invoices/Get"), Some("billing"));
+        // the regex matches the whole path
+        assert_eq!(name("/x/shop.billing.v2.Invoices/Get"), None);
+        assert_eq!(name("/helloworld.Greeter/SayHello"), None);
+    }
+
+    #[test]
+    fn test_header_matchers() {
+        let header = |name: &str, value| HeaderMatchConfig {
+            name: name.into(),
+            value,
+        };
+        let routes = [
+            RouteConfig {
+                headers: vec![
+                    header("x-tenant", ValueMatch::Exact("acme".into())),
+                    header("x-canary", ValueMatch::Present(false)),
+                ],
+                ..route(PathMatch::Prefix("/".into()), "acme")
+            },
+            RouteConfig {
+                headers: vec![header(
+                    "user-agent",
+                    ValueMatch::Regex("shop-ios/[0-9.]+".into()),
+                )],
+                ..route(PathMatch::Prefix("/shop.".into()), "mobile")
+            },
+            RouteConfig {
+                headers: vec![header("x-canary", ValueMatch::Prefix("t".into()))],
+                ..route(PathMatch::Prefix("/".into()), "canary")
+            },
+        ];
+        let router = Router::new(
+            &routes,
+            vec![cluster("acme"), cluster("mobile"), cluster("canary")],
+            "default",
+        )
+        .unwrap();
+        let name = |path, headers: &[(&'static str, &'static str)]| {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (HeaderName::from_static(name), value.parse().unwrap()))
+                .collect();
+            router.route(path, &headers).cluster.map(Cluster::name)
+        };
+
+        assert_eq!(
+            name("/shop.Cart/Get", &[("x-tenant", "acme")]),
+            Some("acme")
+        );
+        assert_eq!(
+            name(
+                "/shop.Cart/Get",
+                &[("x-tenant", "acme"), ("x-canary", "true")]
+            ),
+            Some("canary")
+        );
+        assert_eq!(
+            name("/shop.Cart/Get", &[("user-agent", "shop-ios/2.1")]),
+            Some("mobile")
+        );
+        // the path has to match as well
+        assert_eq!(
+            name("/other.Cart/Get", &[("user-agent", "shop-ios/2.1")]),
+            None
+        );
+        // the whole value has to match the regex
+        assert_eq!(
+            name("/shop.Cart/Get", &[("user-agent", "shop-ios/2.1 beta")]),
+            None
+        );
+        assert_eq!(name("/shop.Cart/Get", &[]), None);
+    }
+
+    #[test]
+    fn test_invalid_routes() {
+        let unknown = Router::new(
+            &[route(PathMatch::Prefix("/".into()), "missing")],
+            vec![cluster("orders")],
+            "default",
+        );
+        assert!(unknown.is_err());
+
+        let twice = Router::new(&[], vec![cluster("orders"), cluster("orders")], "default");
+        assert!(twice.is_err());
+
+        let regex = Router::new(
+            &[route(PathMatch::Regex("(".into()), "orders")],
+            vec![cluster("orders")],
+            "default",
+        );
+        assert!(regex.is_err());
+
+        let header = Router::new(
+            &[RouteConfig {
+                headers: vec![HeaderMatchConfig {
+                    name: "not a header".into(),
+                    value: ValueMatch::Present(true),
+                }],
+                ..route(PathMatch::Prefix("/".into()), "orders")
+            }],
+            vec![cluster("orders")],
+            "default",
+        );
+        assert!(header.is_err());
+    }
+
+    #[test]
+    fn test_route_config_parses() {
+        let routes: Vec<RouteConfig> = serde_json::from_str(
+            r#"[{"prefix": "/shop.orders.", "cluster": "orders"},
+                {"regex": ".*", "cluster": "default",
+                 "headers": [{"name": "x-canary", "present": true}]}]"#,
+        )
+        .unwrap();
+        assert_eq!(routes[0].matcher, PathMatch::Prefix("/shop.orders.".into()));
+        assert!(routes[0].headers.is_empty());
+        assert_eq!(routes[1].matcher, PathMatch::Regex(".*".into()));
+        assert_eq!(routes[1].headers[0].value, ValueMatch::Present(true));
+
+        let weighted: RouteConfig = serde_json::from_str(
+            r#"{"prefix": "/", "weighted_clusters": [{"name": "canary", "weight": 5}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            weighted.target,
+            RouteTarget::WeightedClusters(vec![WeightedCluster {
+                name: "canary".into(),
+                weight: 5,
+            }])
+        );
+    }
+
+    fn weighted(stable: u32, canary: u32) -> Vec<RouteConfig> {
+        vec![RouteConfig {
+            target: RouteTarget::WeightedClusters(vec![
+                WeightedCluster {
+                    name: "default".into(),
+                    weight: stable,
+                },
+                WeightedCluster {
+                    name: "canary".into(),
+                    weight: canary,
+                },
+            ]),
+            ..route(PathMatch::Prefix("/".into()), "")
+        }]
+    }
+
+    #[test]
+    fn test_weighted_clusters() {
+        let router = Router::new(&weighted(3, 1), vec![cluster("canary")], "default").unwrap();
+        let canary = (0..4000)
+            .filter(|_| router.route("/a.B/C", &HeaderMap::new()).cluster.is_some())
+            .count();
+        assert!((800..1200).contains(&canary), "{} calls to canary", canary);
+
+        // every call moves to the canary
+        router.set_weights(&weighted(0, 1)).unwrap();
+        for _ in 0..100 {
+            let cluster = router.route("/a.B/C", &HeaderMap::new()).cluster;
+            assert_eq!(cluster.map(Cluster::name), Some("canary"));
+        }
+
+        // the weights stay as they are
+        assert!(router.set_weights(&weighted(0, 0)).is_err());
+        assert!(router.set_weights(&[]).is_err());
+        assert!(
+            router
+                .set_weights(&[route(PathMatch::Prefix("/".into()), "canary")])
+                .is_err()
+        );
+        assert_eq!(
+            router
+                .route("/a.B/C", &HeaderMap::new())
+                .cluster
+                .map(Cluster::name),
+            Some("canary")
+        );
+
+        assert!(Router::new(&weighted(0, 0), vec![cluster("canary")], "default").is_err());
+    }
+
+    #[test]
+    fn test_mirror_of_the_route() {
+        let mirror = |cluster: &str| MirrorConfig {
+            cluster: cluster.into(),
+            ..Default::default()
+        };
+        let routes = [
+            RouteConfig {
+                mirror: Some(mirror("search-v2")),
+                ..route(PathMatch::Prefix("/shop.search.".into()), "search")
+            },
+            route(PathMatch::Prefix("/".into()), "default"),
+        ];
+        let clusters = || vec![cluster("search"), cluster("search-v2")];
+        let router = Router::new(&routes, clusters(), "default").unwrap();
+
+        let routed = router.route("/shop.search.Search/Find", &HeaderMap::new());
+        assert_eq!(routed.cluster.map(Cluster::name), Some("search"));
+        assert_eq!(routed.mirror.map(|m| m.cluster().name()), Some("search-v2"));
+        let routed = router.route("/shop.cart.Cart/Get", &HeaderMap::new());
+        assert!(routed.mirror.is_none());
+
+        // the listener upstream is not a mirror
+        let routes = [RouteConfig {
+            mirror: Some(mirror("default")),
+            ..route(PathMatch::Prefix("/".into()), "search")
+        }];
+        assert!(Router::new(&routes, clusters(), "default").is_err());
+        let routes = [RouteConfig {
+            mirror: Some(MirrorConfig {
+                percent: 101.0,
+                ..mirror("search-v2")
+            }),
+            ..route(PathMatch::Prefix("/".into()), "search")
+        }];
+        assert!(Router::new(&routes, clusters(), "default").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//!
+//! gRPC statuses produced by Griffin itself,
+//! when a call cannot reach the upstream.
+//!
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use http_body_util::Full;
+
+use crate::ProxyResponse;
+use crate::telemetry::metrics::from_full_bytes;
+
+/// gRPC status codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 15,
+    DataLoss = 16,
+}
+
+impl Code {
+    /// unknown values are UNKNOWN, as gRPC clients read them
+    pub fn from_i32(value: i32) -> Code {
+        match value {
+            0 => Code::Ok,
+            1 => Code::Cancelled,
+            3 => Code::InvalidArgument,
+            4 => Code::DeadlineExceeded,
+            5 => Code::NotFound,
+            6 => Code::AlreadyExists,
+            7 => Code::PermissionDenied,
+            8 => Code::ResourceExhausted,
+            9 => Code::FailedPrecondition,
+            10 => Code::Aborted,
+            11 => Code::OutOfRange,
+            12 => Code::Unimplemented,
+            13 => Code::Internal,
+            14 => Code::Unavailable,
+            15 => Code::Unauthenticated,
+            16 => Code::DataLoss,
+            _ => Code::Unknown,
+        }
+    }
+
+    /// the name the gRPC spec gives the code
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::Ok => "OK",
+            Code::Cancelled => "CANCELLED",
+            Code::Unknown => "UNKNOWN",
+            Code::InvalidArgument => "INVALID_ARGUMENT",
+            Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            Code::NotFound => "NOT_FOUND",
+            Code::AlreadyExists => "ALREADY_EXISTS",
+            Code::PermissionDenied => "PERMISSION_DENIED",
+            Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            Code::FailedPrecondition => "FAILED_PRECONDITION",
+            Code::Aborted => "ABORTED",
+            Code::OutOfRange => "OUT_OF_RANGE",
+            Code::Unimplemented => "UNIMPLEMENTED",
+            Code::Internal => "INTERNAL",
+            Code::Unavailable => "UNAVAILABLE",
+            Code::Unauthenticated => "UNAUTHENTICATED",
+            Code::DataLoss => "DATA_LOSS",
+        }
+    }
+}
+
+/// A Trailers-Only response: HTTP 200 without body,
+/// `grpc-status` and `grpc-message` in the headers.
+/// gRPC and gRPC-Web clients both read it
+pub fn trailers_only(content_type: &HeaderValue, code: Code, message: &str) -> ProxyResponse {
+    let mut res = from_full_bytes(Full::<Bytes>::default());
+    *res.headers_mut() = status_headers(code, message);
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, content_type.clone());
+    res
+}
+
+/// `grpc-status` and `grpc-message`
+pub fn status_headers(code: Code, message: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(code as u16));
+    if let Ok(message) = HeaderValue::from_str(&percent_encode(message)) {
+        headers.insert("grpc-message", message);
+    }
+    headers
+}
+
+/// code and decoded message of `grpc-status` and `grpc-message`,
+/// a missing status is UNKNOWN
+pub fn status_of(headers: &HeaderMap) -> (Code, Option<String>) {
+    let code = headers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .map_or(Code::Unknown, Code::from_i32);
+    let message = headers
+        .get("grpc-message")
+        .map(|message| percent_decode(message.as_bytes()));
+    (code, message)
+}
+
+/// `grpc-message` is percent encoded,
+/// printable ASCII except `%` is sent as is
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// decode `grpc-message`, an invalid escape is kept as is
+pub fn percent_decode(message: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(message.len());
+    let mut i = 0;
+    while i < message.len() {
+        let escaped = message
+            .get(i + 1..i + 3)
+            .filter(|_| message[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(message[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// HTTP status of a failed call for clients
+/// that do not read gRPC statuses, as Connect
+/// and the HTTP/JSON mapping of Google APIs do
+/// <https://connectrpc.com/docs/protocol#error-codes>
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).expect("valid status"),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("not ready"), "not ready");
+        assert_eq!(percent_encode("100%\n"), "100%25%0A");
+        assert_eq!(percent_encode("ü"), "%C3%BC");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode(b"100%25%0A"), "100%\n");
+        assert_eq!(percent_decode(b"%C3%BC"), "ü");
+        assert_eq!(percent_decode(b"50%"), "50%");
+        assert_eq!(percent_decode(b"%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_trailers_only() {
+        let res = trailers_only(
+            &HeaderValue::from_static("application/grpc"),
+            Code::Unavailable,
+            "upstream not ready",
+        );
+        assert_eq!(res.headers()["grpc-status"], "14");
+        assert_eq!(res.headers()["grpc-message"], "upstream not ready");
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/grpc");
+    }
+}
@@ -0,0 +1,204 @@
+//!
+//! Status and duration of every RPC sent to an upstream cluster,
+//! so the error rate of a canary can be compared
+//! with the one of the stable cluster.
+//!
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tokio::time::Instant;
+
+use crate::ProxyResponse;
+use crate::error::{ProxyError, upstream_error_code};
+use crate::status::{self, Code};
+use crate::telemetry::metrics::Metrics;
+
+/// An RPC sent to one cluster, it is counted once
+/// with the status the upstream ended it with.
+/// Dropped before a status, it counts as
+/// DEADLINE_EXCEEDED once the deadline passed
+/// and as CANCELLED otherwise
+pub struct ClusterCall {
+    cluster: String,
+    start: Instant,
+    deadline: Option<Instant>,
+    metrics: Arc<Metrics>,
+    finished: bool,
+}
+
+impl ClusterCall {
+    pub(crate) fn new(cluster: &str, deadline: Option<Instant>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            cluster: cluster.to_string(),
+            start: Instant::now(),
+            deadline,
+            metrics,
+            finished: false,
+        }
+    }
+
+    fn finish(&mut self, code: Code) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        self.metrics
+            .upstream_rpcs()
+            .with_label_values(&[self.cluster.as_str(), code.as_str()])
+            .inc();
+        self.metrics
+            .upstream_rpc_duration()
+            .with_label_values(&[self.cluster.as_str()])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+
+    /// the call failed before the upstream answered
+    pub(crate) fn fail(&mut self, err: ProxyError) -> ProxyError {
+        self.finish(err.code());
+        err
+    }
+
+    /// count the call once the upstream response
+    /// carries its status, in the headers of a
+    /// Trailers-Only response or in its trailers
+    pub(crate) fn observe(mut self, res: ProxyResponse) -> ProxyResponse {
+        if res.headers().contains_key("grpc-status") {
+            self.finish(status::status_of(res.headers()).0);
+            return res;
+        }
+        res.map(|inner| ObservedBody { inner, call: self }.boxed())
+    }
+}
+
+impl Drop for ClusterCall {
+    fn drop(&mut self) {
+        let expired = self
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now());
+        self.finish(if expired {
+            Code::DeadlineExceeded
+        } else {
+            Code::Cancelled
+        });
+    }
+}
+
+/// Upstream response body, its trailers end the call
+struct ObservedBody<B: Body> {
+    inner: B,
+    call: ClusterCall,
+}
+
+impl<B> Body for ObservedBody<B>
+where
+    B: Body<Data = Bytes, Error = hyper::Error> + Unpin,
+{
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    self.call.finish(status::status_of(trailers).0);
+                }
+            }
+            Some(Err(err)) => {
+                let code = upstream_error_code(err);
+                self.call.finish(code);
+            }
+            // a gRPC response always ends with a status
+            None => self.call.finish(Code::Internal),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B: Body> Drop for ObservedBody<B> {
+    fn drop(&mut self) {
+        // hyper never polls a body that is already at its end
+        if self.inner.is_end_stream() {
+            self.call.finish(Code::Internal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{Full, StreamBody};
+
+    use crate::telemetry::metrics::from_full_bytes;
+
+    fn count(cluster: &str, code: Code) -> f64 {
+        Metrics::new()
+            .upstream_rpcs()
+            .with_label_values(&[cluster, code.as_str()])
+            .get()
+    }
+
+    fn call(cluster: &str, deadline: Option<Instant>) -> ClusterCall {
+        ClusterCall::new(cluster, deadline, Arc::new(Metrics::new()))
+    }
+
+    #[tokio::test]
+    async fn test_status_of_trailers_is_counted() {
+        let cluster = "test-trailers";
+        let trailers = status::status_headers(Code::NotFound, "missing");
+        let frames = futures_util::stream::iter([
+            Ok::<_, hyper::Error>(Frame::data(Bytes::from_static(b"message"))),
+            Ok(Frame::trailers(trailers)),
+        ]);
+        let res = ProxyResponse::new(StreamBody::new(frames).boxed());
+        let res = call(cluster, None).observe(res);
+        assert_eq!(count(cluster, Code::NotFound), 0.0);
+        res.into_body().collect().await.unwrap();
+        assert_eq!(count(cluster, Code::NotFound), 1.0);
+    }
+
+    #[test]
+    fn test_trailers_only_and_failures_are_counted() {
+        let cluster = "test-trailers-only";
+        let mut res = from_full_bytes(Full::default());
+        *res.headers_mut() = status::status_headers(Code::Unavailable, "down");
+        drop(call(cluster, None).observe(res));
+        assert_eq!(count(cluster, Code::Unavailable), 1.0);
+
+        let mut failed = call(cluster, None);
+        failed.fail(ProxyError::NoEndpoint);
+        drop(failed);
+        assert_eq!(count(cluster, Code::Unavailable), 2.0);
+        assert_eq!(count(cluster, Code::Cancelled), 0.0);
+    }
+
+    #[test]
+    fn test_dropped_call_is_cancelled_or_expired() {
+        let cluster = "test-dropped";
+        drop(call(cluster, None));
+        assert_eq!(count(cluster, Code::Cancelled), 1.0);
+
+        drop(call(cluster, Some(Instant::now())));
+        assert_eq!(count(cluster, Code::DeadlineExceeded), 1.0);
+
+        // a response dropped before its trailers
+        let pending = futures_util::stream::pending::<Result<Frame<Bytes>, hyper::Error>>();
+        let res = ProxyResponse::new(StreamBody::new(pending).boxed());
+        drop(call(cluster, None).observe(res));
+        assert_eq!(count(cluster, Code::Cancelled), 2.0);
+    }
+}
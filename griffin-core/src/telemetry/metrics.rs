@@ -1,13 +1,12 @@
 use std::convert::Infallible;
 
-use async_stream::try_stream;
 use bytes::Bytes;
 use http::Response;
-use http_body::Body;
-use http_body_util::{BodyExt, Full, StreamBody, combinators::BoxBody};
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use once_cell::sync::Lazy;
 use prometheus::{
-    CounterVec, Encoder, HistogramVec, TextEncoder, register_counter_vec, register_histogram_vec,
+    CounterVec, Encoder, HistogramVec, IntGaugeVec, TextEncoder, register_counter_vec,
+    register_histogram_vec, register_int_gauge_vec,
 };
 
 // use crate::core::stream_response::StreamResponse;
@@ -31,6 +30,60 @@ pub static REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
     )
     .expect("metric already registered")
 });
+
+pub static CANCELLED_RPCS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "grpc_cancelled_total",
+        "RPCs cancelled by the client before the response was finished",
+        &["path"]
+    )
+    .expect("metric already registered")
+});
+
+pub static UPSTREAM_ENDPOINT_HEALTHY: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "upstream_endpoint_healthy",
+        "1 when the last health checks passed, 0 otherwise",
+        &["cluster", "endpoint"]
+    )
+    .expect("metric already registered")
+});
+
+pub static UPSTREAM_RPCS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "upstream_rpcs_total",
+        "RPCs sent to an upstream cluster, by their final gRPC status",
+        &["cluster", "code"]
+    )
+    .expect("metric already registered")
+});
+
+pub static UPSTREAM_RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "upstream_rpc_duration_seconds",
+        "Time from sending an RPC to an upstream cluster until its status",
+        &["cluster"]
+    )
+    .expect("metric already registered")
+});
+
+pub static MIRROR_RPCS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "mirror_rpcs_total",
+        "RPCs mirrored to a shadow cluster, by their final gRPC status",
+        &["cluster", "code"]
+    )
+    .expect("metric already registered")
+});
+
+pub static MIRROR_SKIPPED: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "mirror_skipped_total",
+        "RPCs sampled for mirroring but not mirrored, by reason",
+        &["cluster", "reason"]
+    )
+    .expect("metric already registered")
+});
 #[derive(Clone)]
 pub struct Metrics;
 
@@ -47,6 +100,30 @@ impl Metrics {
         &REQUEST_DURATION
     }
 
+    pub fn cancelled_rpcs(&self) -> &CounterVec {
+        &CANCELLED_RPCS
+    }
+
+    pub fn upstream_endpoint_healthy(&self) -> &IntGaugeVec {
+        &UPSTREAM_ENDPOINT_HEALTHY
+    }
+
+    pub fn upstream_rpcs(&self) -> &CounterVec {
+        &UPSTREAM_RPCS
+    }
+
+    pub fn upstream_rpc_duration(&self) -> &HistogramVec {
+        &UPSTREAM_RPC_DURATION
+    }
+
+    pub fn mirror_rpcs(&self) -> &CounterVec {
+        &MIRROR_RPCS
+    }
+
+    pub fn mirror_skipped(&self) -> &CounterVec {
+        &MIRROR_SKIPPED
+    }
+
     pub fn render(&self) -> Response<BoxBody<Bytes, hyper::Error>> {
         let encoder = TextEncoder::new();
         let metric_families = prometheus::gather();
@@ -1 +1,2 @@
+pub mod cluster_call;
 pub mod metrics;
@@ -1,35 +1,303 @@
+//!
+//! The gRPC-Web trailer frame: flag `0x80`, a 4-byte big endian
+//! length, then the trailers as an HTTP/1 header block.
+//! <https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md#protocol-differences-vs-grpc-over-http2>
+//!
+//! - names are lowercase, one line per name, values of
+//!   a repeated name are merged with `, ` as HTTP/1 does
+//! - `-bin` values are base64, several of them are
+//!   merged with `,`, padded and unpadded values are read
+//!   and unpadded ones are written, as gRPC asks for
+//!
+use base64::Engine;
+use base64::engine::DecodePaddingMode;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD_NO_PAD};
 use bytes::{BufMut, Bytes, BytesMut};
-use http::HeaderMap;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+
+use crate::status::{self, Code};
 
 const FRAME_HEADER_SIZE: usize = 5;
 const GRPC_WEB_TRAILERS_BIT: u8 = 0b10000000;
+const BINARY_SUFFIX: &str = "-bin";
+
+/// base64 with or without padding
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrailersError {
+    /// the block does not fit the 32-bit frame length
+    TooLarge(usize),
+    /// a `-bin` value that is not base64
+    InvalidBinary(String),
+    /// a line of the block without a colon
+    InvalidLine(String),
+    InvalidName(String),
+    InvalidValue(String),
+}
+
+impl fmt::Display for TrailersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrailersError::TooLarge(len) => write!(f, "trailer block of {} bytes", len),
+            TrailersError::InvalidBinary(name) => write!(f, "{} is not base64", name),
+            TrailersError::InvalidLine(line) => write!(f, "line without a colon: {:?}", line),
+            TrailersError::InvalidName(name) => write!(f, "invalid name {:?}", name),
+            TrailersError::InvalidValue(name) => write!(f, "invalid value of {}", name),
+        }
+    }
+}
+
+impl std::error::Error for TrailersError {}
+
 pub struct Trailers {
     inner: HeaderMap,
 }
+
 impl Trailers {
     pub fn new(inner: HeaderMap) -> Self {
         Self { inner }
     }
 
-    fn encode(self) -> Vec<u8> {
-        self.inner.iter().fold(Vec::new(), |mut acc, (key, value)| {
-            acc.put_slice(key.as_ref());
-            acc.push(b':');
-            acc.put_slice(value.as_bytes());
-            acc.put_slice(b"\r\n");
-            acc
-        })
+    pub fn into_inner(self) -> HeaderMap {
+        self.inner
+    }
+
+    /// trailers from the payload of a trailer frame,
+    /// names are lowercased and `-bin` values checked
+    pub fn decode(payload: &[u8]) -> Result<Self, TrailersError> {
+        let mut inner = HeaderMap::new();
+        for line in payload.split(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let lossy = || String::from_utf8_lossy(line).into_owned();
+            let colon = line
+                .iter()
+                .position(|byte| *byte == b':')
+                .ok_or_else(|| TrailersError::InvalidLine(lossy()))?;
+            let name = line[..colon].trim_ascii().to_ascii_lowercase();
+            let name =
+                HeaderName::from_bytes(&name).map_err(|_| TrailersError::InvalidName(lossy()))?;
+            let value = line[colon + 1..].trim_ascii();
+            if is_binary(&name) {
+                decode_binary(&name, value)?;
+            }
+            let value = HeaderValue::from_bytes(value)
+                .map_err(|_| TrailersError::InvalidValue(name.to_string()))?;
+            inner.append(name, value);
+        }
+        Ok(Self { inner })
+    }
+
+    /// the header block, one `name:value` line per name
+    pub fn encode(&self) -> Result<Vec<u8>, TrailersError> {
+        let mut block = Vec::new();
+        for name in self.inner.keys() {
+            let values = self.inner.get_all(name);
+            block.put_slice(name.as_str().as_bytes());
+            block.push(b':');
+            if is_binary(name) {
+                let mut pieces = Vec::new();
+                for value in values {
+                    for piece in decode_binary(name, value.as_bytes())? {
+                        pieces.push(STANDARD_NO_PAD.encode(piece));
+                    }
+                }
+                block.put_slice(pieces.join(",").as_bytes());
+            } else {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        block.put_slice(b", ");
+                    }
+                    block.put_slice(value.as_bytes());
+                }
+            }
+            block.put_slice(b"\r\n");
+        }
+        Ok(block)
     }
-    pub fn into_to_frame(self) -> Bytes {
-        let trailers = self.encode();
-        let len = trailers.len();
-        assert!(len <= u32::MAX as usize);
 
-        let mut frame = BytesMut::with_capacity(len + FRAME_HEADER_SIZE);
+    pub fn into_to_frame(self) -> Result<Bytes, TrailersError> {
+        let trailers = self.encode()?;
+        let len =
+            u32::try_from(trailers.len()).map_err(|_| TrailersError::TooLarge(trailers.len()))?;
+
+        let mut frame = BytesMut::with_capacity(trailers.len() + FRAME_HEADER_SIZE);
         frame.put_u8(GRPC_WEB_TRAILERS_BIT);
-        frame.put_u32(len as u32);
+        frame.put_u32(len);
         frame.put_slice(&trailers);
 
-        frame.freeze()
+        Ok(frame.freeze())
+    }
+
+    /// the trailer frame, or one ending the call
+    /// with INTERNAL when these trailers cannot be sent
+    pub fn into_frame_or_status(self) -> Bytes {
+        self.into_to_frame().unwrap_or_else(|err| {
+            let message = format!("invalid trailers: {}", err);
+            // status trailers are plain ASCII
+            Trailers::new(status::status_headers(Code::Internal, &message))
+                .into_to_frame()
+                .unwrap_or_default()
+        })
+    }
+}
+
+fn is_binary(name: &HeaderName) -> bool {
+    name.as_str().ends_with(BINARY_SUFFIX)
+}
+
+/// the bytes of each comma separated piece
+fn decode_binary(name: &HeaderName, value: &[u8]) -> Result<Vec<Vec<u8>>, TrailersError> {
+    value
+        .split(|byte| *byte == b',')
+        .map(|piece| {
+            LENIENT_BASE64
+                .decode(piece.trim_ascii())
+                .map_err(|_| TrailersError::InvalidBinary(name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn frame_payload(trailers: HeaderMap) -> Bytes {
+        Trailers::new(trailers)
+            .into_to_frame()
+            .unwrap()
+            .slice(FRAME_HEADER_SIZE..)
+    }
+
+    #[test]
+    fn test_decode_reads_encoded_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("3"));
+        trailers.insert("grpc-message", HeaderValue::from_static("bad%20name"));
+        let decoded = Trailers::decode(&frame_payload(trailers.clone())).unwrap();
+        assert_eq!(decoded.into_inner(), trailers);
+    }
+
+    #[test]
+    fn test_decode_is_lenient_about_case_and_spaces() {
+        let decoded = Trailers::decode(b"Grpc-Status: 0\r\nx-reason:  done \r\n")
+            .unwrap()
+            .into_inner();
+        assert_eq!(decoded["grpc-status"], "0");
+        assert_eq!(decoded["x-reason"], "done");
+        assert!(Trailers::decode(b"grpc-status 0\r\n").is_err());
+        assert!(Trailers::decode(b"x-data-bin: not base64!\r\n").is_err());
+    }
+
+    #[test]
+    fn test_repeated_names_are_merged() {
+        let mut trailers = HeaderMap::new();
+        trailers.append("x-tag", HeaderValue::from_static("a"));
+        trailers.append("x-tag", HeaderValue::from_static("b"));
+        trailers.append("x-data-bin", HeaderValue::from_static("AAE="));
+        trailers.append("x-data-bin", HeaderValue::from_static("AgM"));
+        assert_eq!(
+            frame_payload(trailers),
+            Bytes::from("x-tag:a, b\r\nx-data-bin:AAE,AgM\r\n")
+        );
+    }
+
+    #[test]
+    fn test_invalid_binary_value_is_an_error() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-data-bin", HeaderValue::from_static("***"));
+        assert_eq!(
+            Trailers::new(trailers.clone()).into_to_frame(),
+            Err(TrailersError::InvalidBinary("x-data-bin".into()))
+        );
+
+        // the call ends with INTERNAL instead
+        let frame = Trailers::new(trailers).into_frame_or_status();
+        let status = Trailers::decode(&frame[FRAME_HEADER_SIZE..])
+            .unwrap()
+            .into_inner();
+        assert_eq!(status["grpc-status"], "13");
+    }
+
+    fn metadata_name() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9_.-]{0,12}".prop_filter("binary names have their own strategy", |name| {
+            !name.ends_with(BINARY_SUFFIX)
+        })
+    }
+
+    /// printable ASCII without surrounding spaces,
+    /// which HTTP/1 does not keep
+    fn metadata_value() -> impl Strategy<Value = String> {
+        "[!-~]([ -~]{0,30}[!-~])?"
+    }
+
+    proptest! {
+        #[test]
+        fn prop_round_trip(
+            text in proptest::collection::vec((metadata_name(), metadata_value()), 0..8),
+            binary in proptest::collection::vec(
+                ("[a-z]{1,8}", proptest::collection::vec(any::<u8>(), 0..24), any::<bool>()),
+                0..4,
+            ),
+        ) {
+            let mut trailers = HeaderMap::new();
+            for (name, value) in &text {
+                trailers.append(
+                    HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    HeaderValue::from_str(value).unwrap(),
+                );
+            }
+            for (name, bytes, padded) in &binary {
+                let value = match padded {
+                    true => base64::engine::general_purpose::STANDARD.encode(bytes),
+                    false => STANDARD_NO_PAD.encode(bytes),
+                };
+                trailers.append(
+                    HeaderName::from_bytes(format!("{}-bin", name).as_bytes()).unwrap(),
+                    HeaderValue::from_str(&value).unwrap(),
+                );
+            }
+
+            let frame = Trailers::new(trailers.clone()).into_to_frame().unwrap();
+            prop_assert_eq!(frame[0], GRPC_WEB_TRAILERS_BIT);
+            let len = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+            prop_assert_eq!(len, frame.len() - FRAME_HEADER_SIZE);
+
+            let decoded = Trailers::decode(&frame[FRAME_HEADER_SIZE..]).unwrap().into_inner();
+            prop_assert_eq!(decoded.keys_len(), trailers.keys_len());
+            for name in trailers.keys() {
+                let decoded = decoded.get(name).unwrap().as_bytes();
+                if is_binary(name) {
+                    let expected: Vec<Vec<u8>> = trailers
+                        .get_all(name)
+                        .iter()
+                        .flat_map(|value| decode_binary(name, value.as_bytes()).unwrap())
+                        .collect();
+                    prop_assert_eq!(decode_binary(name, decoded).unwrap(), expected);
+                } else {
+                    let expected: Vec<&[u8]> =
+                        trailers.get_all(name).iter().map(HeaderValue::as_bytes).collect();
+                    let expected = expected.join(&b", "[..]);
+                    prop_assert_eq!(decoded, expected.as_slice());
+                }
+            }
+
+            // encoding what was decoded changes nothing
+            let again = Trailers::new(decoded).into_to_frame().unwrap();
+            prop_assert_eq!(again, frame);
+        }
+
+        #[test]
+        fn prop_decode_never_panics(payload in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let _ = Trailers::decode(&payload);
+        }
     }
 }
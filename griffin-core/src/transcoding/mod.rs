@@ -0,0 +1,380 @@
+//!
+//! HTTP/JSON to gRPC transcoding.
+//! Methods annotated with `google.api.http` rules are exposed
+//! as REST endpoints, the request is built from the path,
+//! the query and the JSON body, then forwarded as `application/grpc`.
+//! <https://cloud.google.com/endpoints/docs/grpc/transcoding>
+//!
+pub mod path_template;
+
+use bytes::{Buf, Bytes};
+use http::{HeaderValue, Method, Uri, header, request::Parts};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, Kind, MessageDescriptor, MethodDescriptor};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+use tower::BoxError;
+
+use crate::core::grpc_kind::GrpcKind;
+use crate::core::grpc_kind_json::GrpcKindJson;
+use crate::error::ProxyError;
+use crate::frame::encode_frame;
+use crate::status::percent_decode;
+use path_template::PathTemplate;
+
+const HTTP_RULE: &str = "google.api.http";
+
+/// ```yaml
+/// transcoding:
+///   descriptor_set: /etc/griffin/greeter.pb
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TranscodingConfig {
+    /// `FileDescriptorSet` of the upstream services and their imports,
+    /// `protoc --include_imports --descriptor_set_out=greeter.pb`
+    pub descriptor_set: PathBuf,
+}
+
+/// The REST endpoints of a descriptor set
+pub struct Transcoder {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    verb: Method,
+    template: PathTemplate,
+    method: MethodDescriptor,
+    /// `*` or the request field set from the body,
+    /// the body is ignored when missing
+    body: Option<String>,
+    response_body: Option<String>,
+}
+
+impl Transcoder {
+    pub fn from_config(config: &TranscodingConfig) -> Result<Self, BoxError> {
+        let descriptor_set = std::fs::read(&config.descriptor_set)
+            .map_err(|err| format!("cannot read {:?}: {}", config.descriptor_set, err))?;
+        Self::new(&DescriptorPool::decode(descriptor_set.as_slice())?)
+    }
+
+    /// a route for every `google.api.http` rule,
+    /// additional bindings included
+    pub fn new(pool: &DescriptorPool) -> Result<Self, BoxError> {
+        let extension = pool.get_extension_by_name(HTTP_RULE).ok_or(
+            "descriptor set without google/api/annotations.proto, build it with --include_imports",
+        )?;
+        let mut routes = Vec::new();
+        for service in pool.services() {
+            for method in service.methods() {
+                let options = method.options();
+                if !options.has_extension(&extension) {
+                    continue;
+                }
+                let rule = options.get_extension(&extension);
+                let Some(rule) = rule.as_message() else {
+                    continue;
+                };
+                routes.push(Route::new(&method, rule)?);
+                let bindings = rule.get_field_by_name("additional_bindings");
+                for binding in bindings
+                    .iter()
+                    .filter_map(|value| value.as_list())
+                    .flatten()
+                {
+                    if let Some(binding) = binding.as_message() {
+                        routes.push(Route::new(&method, binding)?);
+                    }
+                }
+            }
+        }
+        Ok(Self { routes })
+    }
+
+    /// the first rule matching the call
+    pub(crate) fn route(&self, verb: &Method, path: &str) -> Option<TranscodedCall<'_>> {
+        self.routes
+            .iter()
+            .filter(|route| route.verb == verb)
+            .find_map(|route| {
+                let bindings = route.template.matches(path)?;
+                Some(TranscodedCall { route, bindings })
+            })
+    }
+}
+
+impl Route {
+    fn new(method: &MethodDescriptor, rule: &DynamicMessage) -> Result<Self, BoxError> {
+        let string = |name: &str| {
+            rule.get_field_by_name(name)
+                .and_then(|value| value.as_str().map(str::to_string))
+                .filter(|value| !value.is_empty())
+        };
+        let patterns = [
+            ("get", Method::GET),
+            ("put", Method::PUT),
+            ("post", Method::POST),
+            ("delete", Method::DELETE),
+            ("patch", Method::PATCH),
+        ];
+        let pattern = patterns
+            .into_iter()
+            .find_map(|(name, verb)| Some((verb, string(name)?)));
+        let (verb, path) = match pattern {
+            Some(pattern) => pattern,
+            None => {
+                let custom = rule
+                    .get_field_by_name("custom")
+                    .and_then(|custom| custom.as_message().cloned())
+                    .ok_or_else(|| format!("http rule of {} without a path", method.full_name()))?;
+                let field = |name| {
+                    custom
+                        .get_field_by_name(name)
+                        .and_then(|value| value.as_str().map(str::to_string))
+                        .unwrap_or_default()
+                };
+                (Method::from_bytes(field("kind").as_bytes())?, field("path"))
+            }
+        };
+
+        let body = string("body");
+        if let Some(field) = body.as_deref().filter(|body| *body != "*") {
+            method.input().get_field_by_name(field).ok_or_else(|| {
+                format!("unknown body field {:?} of {}", field, method.full_name())
+            })?;
+        }
+        let response_body = string("response_body");
+        if let Some(field) = &response_body {
+            method.output().get_field_by_name(field).ok_or_else(|| {
+                format!(
+                    "unknown response_body field {:?} of {}",
+                    field,
+                    method.full_name()
+                )
+            })?;
+        }
+        Ok(Self {
+            verb,
+            template: PathTemplate::parse(&path)?,
+            method: method.clone(),
+            body,
+            response_body,
+        })
+    }
+
+    /// the request message from the path bindings,
+    /// the query parameters and the JSON body
+    fn message(
+        &self,
+        bindings: &[(String, String)],
+        query: &str,
+        body: &[u8],
+    ) -> Result<DynamicMessage, String> {
+        let input = self.method.input();
+        let mut message = match self.body.as_deref() {
+            None => DynamicMessage::new(input.clone()),
+            Some(body_field) => {
+                let body: Value = match body.is_empty() {
+                    true => Value::Object(Map::new()),
+                    false => serde_json::from_slice(body)
+                        .map_err(|err| format!("invalid JSON body: {}", err))?,
+                };
+                let body = match body_field {
+                    "*" => body,
+                    field => Value::Object(Map::from_iter([(field.to_string(), body)])),
+                };
+                DynamicMessage::deserialize(input.clone(), body)
+                    .map_err(|err| format!("invalid JSON body: {}", err))?
+            }
+        };
+
+        let mut fields = Map::new();
+        for (field_path, value) in bindings {
+            bind(&input, &mut fields, field_path, value.clone())?;
+        }
+        // fields of the body or the path
+        // are not set from the query
+        if self.body.as_deref() != Some("*") {
+            for (key, value) in query_pairs(query) {
+                let bound = |field: &str| {
+                    key == field
+                        || key
+                            .strip_prefix(field)
+                            .is_some_and(|key| key.starts_with('.'))
+                };
+                if self.body.as_deref().is_some_and(bound) || self.template.field_paths().any(bound)
+                {
+                    continue;
+                }
+                bind(&input, &mut fields, &key, value)?;
+            }
+        }
+        let bound = DynamicMessage::deserialize(input, Value::Object(fields))
+            .map_err(|err| format!("invalid parameter: {}", err))?;
+        message
+            .merge(bound.encode_to_vec().as_slice())
+            .map_err(|err| err.to_string())?;
+        Ok(message)
+    }
+}
+
+/// set `field_path` of the JSON form of a `message`,
+/// values are read as the JSON mapping reads strings
+fn bind(
+    message: &MessageDescriptor,
+    fields: &mut Map<String, Value>,
+    field_path: &str,
+    value: String,
+) -> Result<(), String> {
+    let unknown = || format!("unknown field {:?}", field_path);
+    let mut message = message.clone();
+    let mut fields = fields;
+    let mut names = field_path.split('.').peekable();
+    while let Some(name) = names.next() {
+        let field = message
+            .get_field_by_name(name)
+            .or_else(|| message.get_field_by_json_name(name))
+            .ok_or_else(unknown)?;
+        let key = field.name().to_string();
+
+        if names.peek().is_none() {
+            if field.is_map() {
+                return Err(format!("map field {:?} cannot be bound", field_path));
+            }
+            let value = match field.kind() {
+                Kind::Bool => Value::Bool(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid bool {:?} for {:?}", value, field_path))?,
+                ),
+                _ => Value::String(value),
+            };
+            if field.is_list() {
+                let values = fields
+                    .entry(key)
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                if let Value::Array(values) = values {
+                    values.push(value);
+                }
+            } else {
+                fields.insert(key, value);
+            }
+            return Ok(());
+        }
+
+        let Kind::Message(next) = field.kind() else {
+            return Err(unknown());
+        };
+        if field.is_list() {
+            return Err(format!("repeated field {:?} cannot be bound", field_path));
+        }
+        let entry = fields
+            .entry(key)
+            .or_insert_with(|| Value::Object(Map::new()));
+        fields = entry.as_object_mut().ok_or_else(unknown)?;
+        message = next;
+    }
+    Err(unknown())
+}
+
+/// `application/x-www-form-urlencoded` pairs
+fn query_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |part: &str| percent_decode(part.replace('+', " ").as_bytes());
+            (decode(key), decode(value))
+        })
+}
+
+/// a call matched by a rule
+pub(crate) struct TranscodedCall<'a> {
+    route: &'a Route,
+    bindings: Vec<(String, String)>,
+}
+
+impl TranscodedCall<'_> {
+    pub(crate) fn kind(&self) -> GrpcKind {
+        let method = &self.route.method;
+        GrpcKind::Json(GrpcKindJson {
+            output: method.output(),
+            response_body: self.route.response_body.clone(),
+            streaming: method.is_server_streaming(),
+        })
+    }
+
+    /// path of the gRPC method
+    pub(crate) fn path(&self) -> String {
+        let method = &self.route.method;
+        format!("/{}/{}", method.parent_service().full_name(), method.name())
+    }
+
+    /// turn `parts` into the gRPC request of the method,
+    /// the returned frame is its body.
+    /// The JSON body is read up to `limit` bytes
+    pub(crate) async fn request<B>(
+        &self,
+        parts: &mut Parts,
+        body: B,
+        limit: Option<usize>,
+    ) -> Result<Bytes, ProxyError>
+    where
+        B: hyper::body::Body<Data = Bytes>,
+        B::Error: Into<BoxError>,
+    {
+        let mut body = Limited::new(body, limit.unwrap_or(usize::MAX))
+            .collect()
+            .await
+            .map_err(
+                |err| match (err.downcast_ref::<LengthLimitError>(), limit) {
+                    (Some(_), Some(limit)) => ProxyError::MessageTooLarge(format!(
+                        "request body is larger than the limit of {} bytes",
+                        limit
+                    )),
+                    _ => ProxyError::InvalidMessage(err.to_string()),
+                },
+            )?
+            .aggregate();
+        let body = body.copy_to_bytes(body.remaining());
+        let query = parts.uri.query().unwrap_or_default();
+        let message = self
+            .route
+            .message(&self.bindings, query, &body)
+            .map_err(ProxyError::InvalidMessage)?;
+
+        parts.uri = self
+            .path()
+            .parse::<Uri>()
+            .map_err(|err| ProxyError::InvalidRequest(err.into()))?;
+        parts.method = Method::POST;
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        parts.headers.remove(header::CONTENT_LENGTH);
+        encode_frame(0, &message.encode_to_vec())
+            .map_err(|err| ProxyError::MessageTooLarge(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_pairs() {
+        let pairs: Vec<_> = query_pairs("name=Bob+Smith&tags=a%26b&&flag").collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("name".to_string(), "Bob Smith".to_string()),
+                ("tags".to_string(), "a&b".to_string()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//!
+//! Path templates of `google.api.http` rules.
+//! <https://github.com/googleapis/googleapis/blob/master/google/api/http.proto>
+//!
+//! ```text
+//! Template = "/" Segments [ Verb ] ;
+//! Segments = Segment { "/" Segment } ;
+//! Segment  = "*" | "**" | LITERAL | Variable ;
+//! Variable = "{" FieldPath [ "=" Segments ] "}" ;
+//! FieldPath = IDENT { "." IDENT } ;
+//! Verb     = ":" LITERAL ;
+//! ```
+//!
+use crate::status::percent_decode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// `*`, exactly one segment
+    Single,
+    /// `**`, the remaining segments, possibly none
+    Multi,
+}
+
+/// field bound to the segments `start..end`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Variable {
+    field_path: String,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+    variables: Vec<Variable>,
+    verb: Option<String>,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid path template {:?}: {}", template, reason);
+        let rest = template
+            .strip_prefix('/')
+            .ok_or_else(|| invalid("must start with /"))?;
+
+        // the verb follows the last segment,
+        // a colon inside a variable is not one
+        let (rest, verb) = match rest.rfind(':') {
+            Some(colon) if !rest[colon..].contains('}') => {
+                (&rest[..colon], Some(rest[colon + 1..].to_string()))
+            }
+            _ => (rest, None),
+        };
+
+        let mut segments = Vec::new();
+        let mut variables = Vec::new();
+        let mut variable: Option<Variable> = None;
+        for part in rest.split('/') {
+            let part = match part.strip_prefix('{') {
+                Some(inner) => {
+                    if variable.is_some() {
+                        return Err(invalid("nested variable"));
+                    }
+                    // `{name}` is `{name=*}`
+                    let (field_path, first) = match inner.split_once('=') {
+                        Some((field_path, first)) => (field_path, first),
+                        None => {
+                            let field_path = inner
+                                .strip_suffix('}')
+                                .ok_or_else(|| invalid("unclosed variable"))?;
+                            (field_path, "*}")
+                        }
+                    };
+                    if field_path.is_empty() || field_path.contains(['{', '}']) {
+                        return Err(invalid("variable without a field"));
+                    }
+                    variable = Some(Variable {
+                        field_path: field_path.to_string(),
+                        start: segments.len(),
+                        end: segments.len(),
+                    });
+                    first
+                }
+                None => part,
+            };
+            let (part, closes) = match part.strip_suffix('}') {
+                Some(part) => (part, true),
+                None => (part, false),
+            };
+            segments.push(match part {
+                "*" => Segment::Single,
+                "**" => Segment::Multi,
+                "" => return Err(invalid("empty segment")),
+                literal if literal.contains(['{', '}', '=']) => {
+                    return Err(invalid("unexpected character"));
+                }
+                literal => Segment::Literal(literal.to_string()),
+            });
+            if closes {
+                let mut closed = variable.take().ok_or_else(|| invalid("unmatched }"))?;
+                closed.end = segments.len();
+                variables.push(closed);
+            }
+        }
+        if variable.is_some() {
+            return Err(invalid("unclosed variable"));
+        }
+        let multi = segments
+            .iter()
+            .position(|segment| *segment == Segment::Multi);
+        if multi.is_some_and(|multi| multi != segments.len() - 1) {
+            return Err(invalid("** must be the last segment"));
+        }
+        Ok(Self {
+            segments,
+            variables,
+            verb,
+        })
+    }
+
+    /// the field paths and their values when `path` matches,
+    /// values are percent decoded
+    pub fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let rest = path.strip_prefix('/')?;
+        let rest = match &self.verb {
+            Some(verb) => rest.strip_suffix(verb.as_str())?.strip_suffix(':')?,
+            None => rest,
+        };
+        let parts: Vec<&str> = rest.split('/').collect();
+
+        // `**` takes what the segments before it left
+        let ends_with_multi = self.segments.last() == Some(&Segment::Multi);
+        let fixed = self.segments.len() - usize::from(ends_with_multi);
+        if parts.len() < fixed || (!ends_with_multi && parts.len() != fixed) {
+            return None;
+        }
+        for (segment, part) in self.segments.iter().zip(&parts) {
+            match segment {
+                Segment::Literal(literal) if literal != part => return None,
+                Segment::Single if part.is_empty() => return None,
+                _ => {}
+            }
+        }
+
+        let bindings = self
+            .variables
+            .iter()
+            .map(|variable| {
+                let end = if variable.end == self.segments.len() {
+                    parts.len()
+                } else {
+                    variable.end
+                };
+                let value = parts[variable.start..end]
+                    .iter()
+                    .map(|part| percent_decode(part.as_bytes()))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (variable.field_path.clone(), value)
+            })
+            .collect();
+        Some(bindings)
+    }
+
+    /// field paths bound by the template
+    pub fn field_paths(&self) -> impl Iterator<Item = &str> {
+        self.variables
+            .iter()
+            .map(|variable| variable.field_path.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(template: &str, path: &str) -> Option<Vec<(String, String)>> {
+        PathTemplate::parse(template).unwrap().matches(path)
+    }
+
+    fn binding(field_path: &str, value: &str) -> (String, String) {
+        (field_path.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_literal_and_variables() {
+        assert_eq!(bindings("/v1/shelves", "/v1/shelves"), Some(vec![]));
+        assert_eq!(bindings("/v1/shelves", "/v1/books"), None);
+        assert_eq!(
+            bindings(
+                "/v1/shelves/{shelf}/books/{book.id}",
+                "/v1/shelves/1/books/a%20b"
+            ),
+            Some(vec![binding("shelf", "1"), binding("book.id", "a b")])
+        );
+        assert_eq!(bindings("/v1/shelves/{shelf}", "/v1/shelves/"), None);
+        assert_eq!(bindings("/v1/shelves/{shelf}", "/v1/shelves/1/books"), None);
+    }
+
+    #[test]
+    fn test_variable_segments() {
+        assert_eq!(
+            bindings("/v1/{name=shelves/*/books/*}", "/v1/shelves/1/books/2"),
+            Some(vec![binding("name", "shelves/1/books/2")])
+        );
+        assert_eq!(
+            bindings("/v1/{name=shelves/*/books/*}", "/v1/shelves/1/notes/2"),
+            None
+        );
+        assert_eq!(
+            bindings("/v1/files/{path=**}", "/v1/files/a/b/c.txt"),
+            Some(vec![binding("path", "a/b/c.txt")])
+        );
+        assert_eq!(bindings("/v1/files/**", "/v1/files"), Some(vec![]));
+    }
+
+    #[test]
+    fn test_verb() {
+        assert_eq!(
+            bindings("/v1/{name=operations/*}:cancel", "/v1/operations/42:cancel"),
+            Some(vec![binding("name", "operations/42")])
+        );
+        assert_eq!(bindings("/v1/{name}:cancel", "/v1/42:delete"), None);
+        assert_eq!(bindings("/v1/{name}:cancel", "/v1/42"), None);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in [
+            "v1/shelves",
+            "/v1//shelves",
+            "/v1/{shelf",
+            "/v1/shelf}",
+            "/v1/{a={b}}",
+            "/v1/**/books",
+            "/v1/{}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+}
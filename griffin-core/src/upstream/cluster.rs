@@ -0,0 +1,299 @@
+use http::uri::Authority;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tower::BoxError;
+
+use crate::readiness::ReadinessGate;
+use crate::telemetry::metrics::Metrics;
+use crate::upstream::connection_pool::ConnectionPool;
+use crate::upstream::health_check::{self, HealthCheckConfig};
+use crate::upstream::load_balancer::{LbPolicy, LoadBalancer};
+
+/// What the endpoints of a cluster speak
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamProtocol {
+    /// gRPC over HTTP/2
+    #[default]
+    Grpc,
+    /// gRPC-Web over HTTP/1.1, for backends
+    /// behind gateways that only speak gRPC-Web
+    GrpcWeb,
+}
+
+/// ```yaml
+/// upstream:
+///   lb_policy: power_of_two_choices
+///   protocol: grpc
+///   endpoints:
+///     - "10.0.0.1:3000"
+///     - "10.0.0.2:3000"
+///   health_check:
+///     service: "helloworld.Greeter"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub name: String,
+    /// `host:port` of every replica
+    pub endpoints: Vec<String>,
+    pub lb_policy: LbPolicy,
+    pub protocol: UpstreamProtocol,
+    /// probe endpoints with `grpc.health.v1`,
+    /// every endpoint is considered healthy when missing
+    pub health_check: Option<HealthCheckConfig>,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            name: "default".into(),
+            endpoints: Vec::new(),
+            lb_policy: LbPolicy::default(),
+            protocol: UpstreamProtocol::default(),
+            health_check: None,
+        }
+    }
+}
+
+/// A single upstream replica
+pub struct Endpoint {
+    authority: Authority,
+    /// RPCs sent to the endpoint
+    /// whose response has not finished yet
+    outstanding: AtomicUsize,
+    /// endpoints start healthy, so a reload
+    /// does not stop traffic until the first probes
+    healthy: AtomicBool,
+}
+
+impl Endpoint {
+    pub fn new(authority: Authority) -> Self {
+        Self {
+            authority,
+            outstanding: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Acquire)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Release);
+    }
+}
+
+/// An endpoint picked for one RPC,
+/// it counts as outstanding until dropped
+pub struct EndpointGuard {
+    endpoint: Arc<Endpoint>,
+}
+
+impl EndpointGuard {
+    fn new(endpoint: Arc<Endpoint>) -> Self {
+        endpoint.outstanding.fetch_add(1, Ordering::AcqRel);
+        Self { endpoint }
+    }
+
+    pub fn authority(&self) -> &Authority {
+        self.endpoint.authority()
+    }
+}
+
+impl Drop for EndpointGuard {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Replicas of one upstream service
+/// and the policy spreading RPCs over them
+pub struct Cluster {
+    name: String,
+    endpoints: Vec<Arc<Endpoint>>,
+    balancer: LoadBalancer,
+    protocol: UpstreamProtocol,
+    health_check: Option<HealthCheckConfig>,
+}
+
+impl Cluster {
+    pub fn new(config: &ClusterConfig) -> Result<Self, BoxError> {
+        if config.endpoints.is_empty() {
+            return Err(format!("Cluster {} has no endpoints", config.name).into());
+        }
+        if let Some(health_check) = &config.health_check {
+            health_check.validate()?;
+        }
+        let endpoints = config
+            .endpoints
+            .iter()
+            .map(|endpoint| Ok(Arc::new(Endpoint::new(Authority::from_str(endpoint)?))))
+            .collect::<Result<Vec<_>, http::uri::InvalidUri>>()?;
+        Ok(Self {
+            name: config.name.clone(),
+            endpoints,
+            balancer: LoadBalancer::new(config.lb_policy),
+            protocol: config.protocol,
+            health_check: config.health_check.clone(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        &self.endpoints
+    }
+
+    pub fn protocol(&self) -> UpstreamProtocol {
+        self.protocol
+    }
+
+    /// choose the endpoint for the next RPC
+    /// among the healthy ones.
+    /// When no endpoint is healthy every endpoint is used,
+    /// failing some calls beats failing all of them
+    pub fn pick(&self) -> Option<EndpointGuard> {
+        let healthy = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_healthy());
+        let count = healthy.clone().count();
+        // a probe can mark the picked endpoint unhealthy
+        // after counting, every endpoint is used then
+        (count > 0)
+            .then(|| self.balancer.pick(healthy, count))
+            .flatten()
+            .or_else(|| {
+                self.balancer
+                    .pick(self.endpoints.iter(), self.endpoints.len())
+            })
+            .map(|endpoint| EndpointGuard::new(endpoint.clone()))
+    }
+
+    /// start probing every endpoint if health checks are configured,
+    /// the probes stop once the cluster is dropped.
+    /// The first passed probe opens `readiness`
+    pub fn start_health_checks(
+        &self,
+        pool: Arc<ConnectionPool>,
+        max_streams: usize,
+        metrics: Arc<Metrics>,
+        readiness: Option<Arc<ReadinessGate>>,
+    ) {
+        let Some(config) = &self.health_check else {
+            return;
+        };
+        for endpoint in &self.endpoints {
+            health_check::spawn(
+                self.name.clone(),
+                Arc::downgrade(endpoint),
+                self.protocol,
+                config.clone(),
+                pool.clone(),
+                max_streams,
+                metrics.clone(),
+                readiness.clone(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_requires_endpoints() {
+        assert!(Cluster::new(&ClusterConfig::default()).is_err());
+        assert!(
+            Cluster::new(&ClusterConfig {
+                endpoints: vec!["not a host:port".into()],
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_cluster_rejects_zero_health_check_settings() {
+        let cluster = |health_check| {
+            Cluster::new(&ClusterConfig {
+                endpoints: vec!["127.0.0.1:3000".into()],
+                health_check: Some(health_check),
+                ..Default::default()
+            })
+        };
+        assert!(cluster(HealthCheckConfig::default()).is_ok());
+        for zero in [
+            HealthCheckConfig {
+                interval_ms: 0,
+                ..Default::default()
+            },
+            HealthCheckConfig {
+                timeout_ms: 0,
+                ..Default::default()
+            },
+            HealthCheckConfig {
+                healthy_threshold: 0,
+                ..Default::default()
+            },
+            HealthCheckConfig {
+                unhealthy_threshold: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(cluster(zero).is_err());
+        }
+    }
+
+    #[test]
+    fn test_guard_tracks_outstanding_requests() {
+        let cluster = Cluster::new(&ClusterConfig {
+            endpoints: vec!["127.0.0.1:3000".into()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let first = cluster.pick().unwrap();
+        let second = cluster.pick().unwrap();
+        assert_eq!(cluster.endpoints()[0].outstanding(), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(cluster.endpoints()[0].outstanding(), 0);
+    }
+
+    #[test]
+    fn test_pick_skips_unhealthy_endpoints() {
+        let cluster = Cluster::new(&ClusterConfig {
+            endpoints: vec!["127.0.0.1:3000".into(), "127.0.0.1:3001".into()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        cluster.endpoints()[0].set_healthy(false);
+        for _ in 0..4 {
+            assert_eq!(
+                cluster.pick().unwrap().authority().as_str(),
+                "127.0.0.1:3001"
+            );
+        }
+
+        // all unhealthy, fall back to every endpoint
+        cluster.endpoints()[1].set_healthy(false);
+        assert!(cluster.pick().is_some());
+    }
+}
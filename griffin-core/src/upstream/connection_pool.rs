@@ -0,0 +1,594 @@
+use http::uri::Authority;
+use http::{Request, Response, Uri, Version};
+use hyper::body::Incoming;
+use hyper::client::conn::{http1, http2};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::collections::HashMap;
+use std::future::{Future, poll_fn};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tower::BoxError;
+
+use crate::UpstreamBody;
+use crate::upstream::cluster::UpstreamProtocol;
+
+/// Streams a single upstream connection carries
+/// before the pool opens another one.
+///
+/// A connection whose upstream announced a lower
+/// SETTINGS_MAX_CONCURRENT_STREAMS carries at most that many,
+/// hyper would queue the streams above it until one closes.
+/// 100 is the lowest value RFC 9113 recommends for that setting.
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// Multiplexed HTTP/2 connections to upstreams,
+/// keyed by authority
+///
+/// gRPC-Web upstreams get HTTP/1.1 connections instead,
+/// each carrying a single call at a time.
+///
+/// The pool is owned by the supervisor and shared
+/// by every listener it starts, so connections
+/// survive config reloads that keep the same target.
+#[derive(Default)]
+pub struct ConnectionPool {
+    connections: Mutex<HashMap<Authority, Vec<Arc<PooledConnection>>>>,
+}
+
+/// Sends requests on one upstream connection
+#[derive(Clone)]
+pub enum UpstreamSender {
+    Grpc(http2::SendRequest<UpstreamBody>),
+    /// HTTP/1.1 senders cannot be cloned,
+    /// the stream guard keeps other calls away
+    GrpcWeb(Arc<Mutex<http1::SendRequest<UpstreamBody>>>),
+}
+
+impl UpstreamSender {
+    pub fn protocol(&self) -> UpstreamProtocol {
+        match self {
+            UpstreamSender::Grpc(_) => UpstreamProtocol::Grpc,
+            UpstreamSender::GrpcWeb(_) => UpstreamProtocol::GrpcWeb,
+        }
+    }
+
+    pub fn send_request(
+        &self,
+        mut req: Request<UpstreamBody>,
+    ) -> impl Future<Output = Result<Response<Incoming>, hyper::Error>> + Send + use<> {
+        let sender = self.clone();
+        async move {
+            match sender {
+                UpstreamSender::Grpc(mut sender) => sender.send_request(req).await,
+                UpstreamSender::GrpcWeb(sender) => {
+                    // HTTP/1.1 requests carry the origin form,
+                    // the authority is in the host header
+                    if let Some(path) = req.uri().path_and_query() {
+                        *req.uri_mut() = Uri::from(path.clone());
+                    }
+                    *req.version_mut() = Version::HTTP_11;
+                    // the previous call may still be finishing
+                    poll_fn(|cx| lock(&sender).poll_ready(cx)).await?;
+                    let res = lock(&sender).send_request(req);
+                    res.await
+                }
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            UpstreamSender::Grpc(sender) => sender.is_closed(),
+            UpstreamSender::GrpcWeb(sender) => lock(sender).is_closed(),
+        }
+    }
+}
+
+struct PooledConnection {
+    sender: UpstreamSender,
+    active_streams: AtomicUsize,
+    /// SETTINGS_MAX_CONCURRENT_STREAMS of the upstream,
+    /// `usize::MAX` until it announces one
+    peer_max_streams: watch::Receiver<usize>,
+}
+
+impl PooledConnection {
+    /// reserve a stream if the connection is still open
+    /// and below `max_streams` and the upstream limit
+    fn try_acquire(self: &Arc<Self>, max_streams: usize) -> Option<StreamGuard> {
+        if self.sender.is_closed() {
+            return None;
+        }
+        // a connection always carries at least one stream
+        let max_streams = max_streams.min(*self.peer_max_streams.borrow()).max(1);
+        self.active_streams
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < max_streams).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| StreamGuard { conn: self.clone() })
+    }
+}
+
+/// A reserved stream on a pooled connection,
+/// the stream slot is given back on drop
+pub struct StreamGuard {
+    conn: Arc<PooledConnection>,
+}
+
+impl StreamGuard {
+    pub fn sender(&self) -> UpstreamSender {
+        self.conn.sender.clone()
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.conn.active_streams.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ConnectionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve a stream to `authority`,
+    /// reusing an open connection with a free stream slot
+    /// or opening a new connection when all of them are saturated
+    pub async fn checkout(
+        &self,
+        authority: &Authority,
+        protocol: UpstreamProtocol,
+        max_streams: usize,
+    ) -> Result<StreamGuard, BoxError> {
+        let max_streams = match protocol {
+            // a connection always carries at least one stream
+            UpstreamProtocol::Grpc => max_streams.max(1),
+            UpstreamProtocol::GrpcWeb => 1,
+        };
+        if let Some(guard) = self.try_checkout(authority, protocol, max_streams) {
+            return Ok(guard);
+        }
+
+        // connect without holding the lock,
+        // concurrent callers may open a connection each
+        let (sender, peer_max_streams) = match protocol {
+            UpstreamProtocol::Grpc => {
+                let (sender, peer_max_streams) = connect(authority).await?;
+                (UpstreamSender::Grpc(sender), peer_max_streams)
+            }
+            UpstreamProtocol::GrpcWeb => {
+                let sender = connect_http1(authority).await?;
+                let (_, peer_max_streams) = watch::channel(usize::MAX);
+                (
+                    UpstreamSender::GrpcWeb(Arc::new(Mutex::new(sender))),
+                    peer_max_streams,
+                )
+            }
+        };
+        let conn = Arc::new(PooledConnection {
+            sender,
+            active_streams: AtomicUsize::new(0),
+            peer_max_streams,
+        });
+        let guard = conn
+            .try_acquire(max_streams)
+            .ok_or("Upstream connection closed")?;
+        self.lock().entry(authority.clone()).or_default().push(conn);
+        Ok(guard)
+    }
+
+    fn try_checkout(
+        &self,
+        authority: &Authority,
+        protocol: UpstreamProtocol,
+        max_streams: usize,
+    ) -> Option<StreamGuard> {
+        let mut connections = self.lock();
+        let conns = connections.get_mut(authority)?;
+        // forget connections closed by the upstream
+        conns.retain(|conn| !conn.sender.is_closed());
+        conns
+            .iter()
+            .filter(|conn| conn.sender.protocol() == protocol)
+            .find_map(|conn| conn.try_acquire(max_streams))
+    }
+
+    /// Drop connections to authorities that
+    /// are no longer configured.
+    /// Streams in flight on those connections
+    /// run until they finish
+    pub fn retain(&self, keep: impl Fn(&Authority) -> bool) {
+        self.lock().retain(|authority, _| keep(authority));
+    }
+
+    /// number of open connections to `authority`
+    pub fn connection_count(&self, authority: &Authority) -> usize {
+        self.lock().get(authority).map_or(0, |conns| {
+            conns.iter().filter(|conn| !conn.sender.is_closed()).count()
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Authority, Vec<Arc<PooledConnection>>>> {
+        lock(&self.connections)
+    }
+}
+
+/// Open an HTTP/2 connection and wait for the upstream's
+/// first SETTINGS frame, the receiver follows its
+/// SETTINGS_MAX_CONCURRENT_STREAMS
+async fn connect(
+    authority: &Authority,
+) -> Result<(http2::SendRequest<UpstreamBody>, watch::Receiver<usize>), BoxError> {
+    let stream = TcpStream::connect(authority.as_str()).await?;
+    let (settings, mut peer_max_streams) = SettingsReader::new(stream);
+    let io = TokioIo::new(settings);
+
+    let exec = TokioExecutor::new();
+    let (sender, conn): (
+        http2::SendRequest<UpstreamBody>,
+        http2::Connection<TokioIo<SettingsReader<TcpStream>>, _, TokioExecutor>,
+    ) = http2::Builder::new(exec).handshake(io).await?;
+
+    // Spawn a task to poll the connection, driving the HTTP state
+    tokio::task::spawn(async move {
+        if let Err(err) = conn.await {
+            println!("Connection failed: {:?}", err);
+        }
+    });
+
+    // the server preface is a SETTINGS frame, it fails
+    // with the connection if the upstream closes first
+    peer_max_streams
+        .changed()
+        .await
+        .map_err(|_| "Upstream connection closed")?;
+    Ok((sender, peer_max_streams))
+}
+
+/// SETTINGS frame type and its ACK flag, RFC 9113 section 6.5
+const SETTINGS: u8 = 0x4;
+const SETTINGS_ACK: u8 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+
+/// Reads the frames an upstream sends on an HTTP/2 connection
+/// to follow its SETTINGS_MAX_CONCURRENT_STREAMS,
+/// the bytes are passed on to hyper unchanged
+struct SettingsReader<T> {
+    io: T,
+    /// header of the frame being read
+    header: [u8; 9],
+    header_len: usize,
+    /// payload bytes left in the frame being read
+    remaining: usize,
+    /// the frame being read is a SETTINGS frame
+    settings: bool,
+    /// SETTINGS parameter being read
+    param: [u8; 6],
+    param_len: usize,
+    max_streams: usize,
+    peer_max_streams: watch::Sender<usize>,
+}
+
+impl<T> SettingsReader<T> {
+    fn new(io: T) -> (Self, watch::Receiver<usize>) {
+        let (peer_max_streams, rx) = watch::channel(usize::MAX);
+        let reader = Self {
+            io,
+            header: [0; 9],
+            header_len: 0,
+            remaining: 0,
+            settings: false,
+            param: [0; 6],
+            param_len: 0,
+            max_streams: usize::MAX,
+            peer_max_streams,
+        };
+        (reader, rx)
+    }
+
+    fn read_frames(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.remaining == 0 {
+                let n = (9 - self.header_len).min(bytes.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&bytes[..n]);
+                self.header_len += n;
+                bytes = &bytes[n..];
+                if self.header_len == 9 {
+                    let [l0, l1, l2, kind, flags, ..] = self.header;
+                    self.header_len = 0;
+                    self.remaining = u32::from_be_bytes([0, l0, l1, l2]) as usize;
+                    self.settings = kind == SETTINGS && flags & SETTINGS_ACK == 0;
+                    self.param_len = 0;
+                    if self.remaining == 0 {
+                        self.end_frame();
+                    }
+                }
+                continue;
+            }
+
+            let n = self.remaining.min(bytes.len());
+            if self.settings {
+                for &byte in &bytes[..n] {
+                    self.param[self.param_len] = byte;
+                    self.param_len += 1;
+                    if self.param_len == 6 {
+                        self.param_len = 0;
+                        let [i0, i1, v0, v1, v2, v3] = self.param;
+                        if u16::from_be_bytes([i0, i1]) == SETTINGS_MAX_CONCURRENT_STREAMS {
+                            self.max_streams = u32::from_be_bytes([v0, v1, v2, v3]) as usize;
+                        }
+                    }
+                }
+            }
+            self.remaining -= n;
+            bytes = &bytes[n..];
+            if self.remaining == 0 {
+                self.end_frame();
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        if self.settings {
+            self.settings = false;
+            self.peer_max_streams.send_replace(self.max_streams);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for SettingsReader<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.read_frames(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for SettingsReader<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
+
+async fn connect_http1(
+    authority: &Authority,
+) -> Result<http1::SendRequest<UpstreamBody>, BoxError> {
+    let stream = TcpStream::connect(authority.as_str()).await?;
+    let (sender, conn) = http1::handshake(TokioIo::new(stream)).await?;
+    tokio::task::spawn(async move {
+        if let Err(err) = conn.await {
+            println!("Connection failed: {:?}", err);
+        }
+    });
+    Ok(sender)
+}
+
+/// the guarded state stays consistent even if
+/// a holder panicked, keep using it
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::{Request, Response};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::service::service_fn;
+    use std::convert::Infallible;
+    use std::str::FromStr;
+    use tokio::net::TcpListener;
+    use tokio::sync::Notify;
+
+    /// h2 server counting accepted connections,
+    /// every response waits for `release`
+    async fn spawn_server(release: Arc<Notify>) -> (Authority, Arc<AtomicUsize>) {
+        spawn_limited_server(release, None).await
+    }
+
+    /// h2 server announcing `max_streams`
+    /// as its SETTINGS_MAX_CONCURRENT_STREAMS
+    async fn spawn_limited_server(
+        release: Arc<Notify>,
+        max_streams: Option<u32>,
+    ) -> (Authority, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let authority = Authority::from_str(&listener.local_addr().unwrap().to_string()).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let release = release.clone();
+                tokio::spawn(async move {
+                    let svc = service_fn(move |_req| {
+                        let release = release.clone();
+                        async move {
+                            release.notified().await;
+                            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("ok"))))
+                        }
+                    });
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .max_concurrent_streams(max_streams)
+                        .serve_connection(TokioIo::new(stream), svc)
+                        .await;
+                });
+            }
+        });
+        (authority, accepted)
+    }
+
+    fn request(authority: &Authority) -> Request<UpstreamBody> {
+        Request::post(format!("http://{}/", authority))
+            .body(Empty::new().map_err(Into::into).boxed_unsync())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sequential_requests_reuse_connection() {
+        let release = Arc::new(Notify::new());
+        let (authority, accepted) = spawn_server(release.clone()).await;
+        let pool = ConnectionPool::new();
+
+        for _ in 0..3 {
+            let guard = pool
+                .checkout(&authority, UpstreamProtocol::Grpc, 10)
+                .await
+                .unwrap();
+            let res = guard.sender().send_request(request(&authority));
+            release.notify_one();
+            res.await.unwrap();
+        }
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.connection_count(&authority), 1);
+    }
+
+    #[tokio::test]
+    async fn test_saturated_connection_opens_another() {
+        let release = Arc::new(Notify::new());
+        let (authority, accepted) = spawn_server(release.clone()).await;
+        let pool = ConnectionPool::new();
+
+        // 2 streams per connection, 3 streams in flight
+        let guards = [
+            pool.checkout(&authority, UpstreamProtocol::Grpc, 2)
+                .await
+                .unwrap(),
+            pool.checkout(&authority, UpstreamProtocol::Grpc, 2)
+                .await
+                .unwrap(),
+            pool.checkout(&authority, UpstreamProtocol::Grpc, 2)
+                .await
+                .unwrap(),
+        ];
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        // a finished stream frees its slot
+        drop(guards);
+        let _guard = pool
+            .checkout(&authority, UpstreamProtocol::Grpc, 2)
+            .await
+            .unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_stream_limit_opens_another_connection() {
+        let release = Arc::new(Notify::new());
+        let (authority, accepted) = spawn_limited_server(release, Some(1)).await;
+        let pool = ConnectionPool::new();
+
+        // 10 streams configured, the upstream allows 1
+        let first = pool
+            .checkout(&authority, UpstreamProtocol::Grpc, 10)
+            .await
+            .unwrap();
+        let _second = pool
+            .checkout(&authority, UpstreamProtocol::Grpc, 10)
+            .await
+            .unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.connection_count(&authority), 2);
+
+        // a finished stream frees the slot of its connection
+        drop(first);
+        let _third = pool
+            .checkout(&authority, UpstreamProtocol::Grpc, 10)
+            .await
+            .unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_settings_reader_follows_max_concurrent_streams() {
+        let (mut reader, peer_max_streams) = SettingsReader::new(());
+        // SETTINGS with INITIAL_WINDOW_SIZE and MAX_CONCURRENT_STREAMS,
+        // a DATA frame, a SETTINGS ACK, then an update split in two reads
+        let mut bytes = vec![0, 0, 12, SETTINGS, 0, 0, 0, 0, 0];
+        bytes.extend([0, 4, 0, 0, 0xff, 0xff, 0, 3, 0, 0, 0, 10]);
+        bytes.extend([0, 0, 2, 0, 0, 0, 0, 0, 1, 7, 7]);
+        bytes.extend([0, 0, 0, SETTINGS, SETTINGS_ACK, 0, 0, 0, 0]);
+        reader.read_frames(&bytes);
+        assert_eq!(*peer_max_streams.borrow(), 10);
+
+        let update = [0, 0, 6, SETTINGS, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 2];
+        reader.read_frames(&update[..7]);
+        assert_eq!(*peer_max_streams.borrow(), 10);
+        reader.read_frames(&update[7..]);
+        assert_eq!(*peer_max_streams.borrow(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_zero_max_streams_reuses_connection() {
+        let release = Arc::new(Notify::new());
+        let (authority, _) = spawn_server(release).await;
+        let pool = ConnectionPool::new();
+
+        for _ in 0..3 {
+            drop(
+                pool.checkout(&authority, UpstreamProtocol::Grpc, 0)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(pool.connection_count(&authority), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retain_drops_unused_authorities() {
+        let release = Arc::new(Notify::new());
+        let (authority, _) = spawn_server(release).await;
+        let pool = ConnectionPool::new();
+        drop(
+            pool.checkout(&authority, UpstreamProtocol::Grpc, 2)
+                .await
+                .unwrap(),
+        );
+
+        pool.retain(|_| false);
+        assert_eq!(pool.connection_count(&authority), 0);
+    }
+}
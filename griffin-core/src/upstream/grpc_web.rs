@@ -0,0 +1,156 @@
+//!
+//! gRPC-Web upstreams, for backends behind gateways
+//! that only speak gRPC-Web over HTTP/1.1.
+//! The reverse of [`crate::core::grpc_kind_web`]: calls are sent
+//! as `application/grpc-web` and the trailer frame closing the
+//! response body is turned back into HTTP/2 trailers.
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use http::{HeaderValue, Request, Response, header};
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody, combinators::BoxBody};
+
+use crate::core::content_type::{ContentType, Protocol};
+use crate::frame::{Deframer, TRAILERS, encode_frame};
+use crate::status::{self, Code};
+use crate::trailers::Trailers;
+use crate::{ProxyResponse, UpstreamBody};
+
+/// a gRPC request into its gRPC-Web form
+pub(crate) fn modify_request(req: &mut Request<UpstreamBody>) {
+    let headers = req.headers_mut();
+    let codec = ContentType::from_headers(headers).and_then(|content_type| content_type.codec);
+    let content_type = ContentType {
+        protocol: Protocol::GrpcWeb,
+        codec,
+    };
+    headers.insert(header::CONTENT_TYPE, content_type.to_header_value());
+    headers.insert("x-grpc-web", HeaderValue::from_static("1"));
+    // HTTP/1.1 has no trailers to ask for
+    headers.remove(header::TE);
+}
+
+/// a gRPC-Web response into its gRPC form
+pub(crate) fn modify_response(res: ProxyResponse) -> ProxyResponse {
+    let (mut parts, body) = res.into_parts();
+    let content_type = ContentType::from_headers(&parts.headers)
+        .filter(|content_type| content_type.protocol == Protocol::GrpcWeb);
+    if let Some(content_type) = content_type {
+        let content_type = ContentType {
+            protocol: Protocol::Grpc,
+            codec: content_type.codec,
+        };
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, content_type.to_header_value());
+    }
+    // a Trailers-Only response has
+    // the status in its headers
+    let trailers_only = parts.headers.contains_key("grpc-status");
+    Response::from_parts(parts, grpc_body(body, trailers_only))
+}
+
+/// messages pass through, the trailer frame becomes trailers
+fn grpc_body(
+    mut body: BoxBody<Bytes, hyper::Error>,
+    trailers_only: bool,
+) -> BoxBody<Bytes, hyper::Error> {
+    let frames = stream! {
+        let mut deframer = Deframer::default();
+        while let Some(frame) = body.frame().await {
+            let data = match frame.map(Frame::into_data) {
+                Ok(Ok(data)) => data,
+                Ok(Err(frame)) => {
+                    yield Ok(frame);
+                    continue;
+                }
+                Err(err) => {
+                    yield Err(err);
+                    return;
+                }
+            };
+            deframer.push(&data);
+            loop {
+                let frame = match deframer.next_frame() {
+                    Ok(Some((flag, payload))) if flag & TRAILERS == 0 => {
+                        encode_frame(flag, &payload)
+                    }
+                    Ok(Some((_, payload))) => {
+                        let trailers = match Trailers::decode(&payload) {
+                            Ok(trailers) => trailers.into_inner(),
+                            Err(err) => status::status_headers(
+                                Code::Internal,
+                                &format!("invalid upstream trailers: {}", err),
+                            ),
+                        };
+                        yield Ok(Frame::trailers(trailers));
+                        return;
+                    }
+                    Ok(None) => break,
+                    Err(err) => Err(err),
+                };
+                match frame {
+                    Ok(frame) => yield Ok(Frame::data(frame)),
+                    Err(err) => {
+                        yield Ok(Frame::trailers(status::status_headers(
+                            Code::Internal,
+                            &format!("invalid upstream frame: {}", err),
+                        )));
+                        return;
+                    }
+                }
+            }
+        }
+        if !trailers_only {
+            yield Ok(Frame::trailers(status::status_headers(
+                Code::Internal,
+                "upstream response ended without trailers",
+            )));
+        }
+    };
+    StreamBody::new(frames).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderMap;
+    use http_body_util::Full;
+
+    use crate::telemetry::metrics::from_full_bytes;
+
+    #[tokio::test]
+    async fn test_trailer_frame_becomes_trailers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        trailers.insert("x-reason", HeaderValue::from_static("done"));
+        let mut body = encode_frame(0, b"hello").unwrap().to_vec();
+        body.extend_from_slice(&Trailers::new(trailers.clone()).into_to_frame().unwrap());
+
+        let mut res = from_full_bytes(Full::new(Bytes::from(body)));
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc-web+proto"),
+        );
+        let res = modify_response(res);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/grpc+proto"
+        );
+
+        let collected = res.into_body().collect().await.unwrap();
+        assert_eq!(collected.trailers(), Some(&trailers));
+        assert_eq!(collected.to_bytes(), encode_frame(0, b"hello").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_missing_trailers_fail_the_call() {
+        let res = modify_response(from_full_bytes(Full::new(
+            encode_frame(0, b"hello").unwrap(),
+        )));
+        let collected = res.into_body().collect().await.unwrap();
+        let (code, _) = status::status_of(collected.trailers().unwrap());
+        assert_eq!(code, Code::Internal);
+    }
+}
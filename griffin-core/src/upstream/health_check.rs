@@ -0,0 +1,371 @@
+//!
+//! Active health checks with `grpc.health.v1.Health/Check`.
+//! Every endpoint of a cluster is probed on its own task,
+//! the result is used by the load balancer
+//! and exported as the `upstream_endpoint_healthy` gauge.
+//!
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{HeaderMap, HeaderValue, Request, header};
+use http_body_util::{BodyExt, Full};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tower::BoxError;
+
+use crate::protobuf::{Field, next_field, put_varint};
+use crate::readiness::ReadinessGate;
+use crate::telemetry::metrics::Metrics;
+use crate::upstream::cluster::{Endpoint, UpstreamProtocol};
+use crate::upstream::connection_pool::ConnectionPool;
+use crate::upstream::grpc_web;
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// `HealthCheckResponse.ServingStatus.SERVING`
+const SERVING: u64 = 1;
+
+/// ```yaml
+/// health_check:
+///   service: "helloworld.Greeter"
+///   interval_ms: 5000
+///   timeout_ms: 1000
+///   healthy_threshold: 2
+///   unhealthy_threshold: 3
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// service to ask about,
+    /// empty for the whole server
+    pub service: String,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    /// consecutive passed probes before
+    /// an unhealthy endpoint gets traffic again
+    pub healthy_threshold: u32,
+    /// consecutive failed probes before
+    /// an endpoint stops getting traffic
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            service: String::new(),
+            interval_ms: 5000,
+            timeout_ms: 1000,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    /// a zero interval would make the probe task panic,
+    /// zero timeouts and thresholds make no sense
+    pub fn validate(&self) -> Result<(), BoxError> {
+        let zero = [
+            ("interval_ms", self.interval_ms == 0),
+            ("timeout_ms", self.timeout_ms == 0),
+            ("healthy_threshold", self.healthy_threshold == 0),
+            ("unhealthy_threshold", self.unhealthy_threshold == 0),
+        ];
+        match zero.iter().find(|(_, zero)| *zero) {
+            Some((name, _)) => Err(format!("Health check {} must be at least 1", name).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Consecutive probe results of one endpoint
+#[derive(Default)]
+struct Thresholds {
+    passed: u32,
+    failed: u32,
+}
+
+impl Thresholds {
+    /// the new health of the endpoint,
+    /// `None` while a threshold is not reached
+    fn record(&mut self, config: &HealthCheckConfig, passed: bool) -> Option<bool> {
+        if passed {
+            self.failed = 0;
+            self.passed = self.passed.saturating_add(1);
+            (self.passed >= config.healthy_threshold).then_some(true)
+        } else {
+            self.passed = 0;
+            self.failed = self.failed.saturating_add(1);
+            (self.failed >= config.unhealthy_threshold).then_some(false)
+        }
+    }
+}
+
+/// Probe tasks exporting each `upstream_endpoint_healthy` series,
+/// keyed by cluster and endpoint.
+/// A reload rebuilds the endpoints and starts new tasks
+/// before the old ones stop, the series stays until
+/// the last task exporting it is gone
+static GAUGE_OWNERS: Lazy<Mutex<HashMap<(String, String), usize>>> = Lazy::new(Default::default);
+
+/// One probe task's share of its gauge series
+struct GaugeOwner {
+    labels: (String, String),
+    metrics: Arc<Metrics>,
+}
+
+impl GaugeOwner {
+    fn new(cluster: String, authority: String, metrics: Arc<Metrics>) -> Self {
+        let labels = (cluster, authority);
+        *lock_owners().entry(labels.clone()).or_default() += 1;
+        Self { labels, metrics }
+    }
+
+    fn set(&self, healthy: bool) {
+        self.metrics
+            .upstream_endpoint_healthy()
+            .with_label_values(&[self.labels.0.as_str(), self.labels.1.as_str()])
+            .set(healthy as i64);
+    }
+}
+
+impl Drop for GaugeOwner {
+    fn drop(&mut self) {
+        let mut owners = lock_owners();
+        let Some(count) = owners.get_mut(&self.labels) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        owners.remove(&self.labels);
+        // no configured endpoint has this authority anymore
+        let _ = self
+            .metrics
+            .upstream_endpoint_healthy()
+            .remove_label_values(&[self.labels.0.as_str(), self.labels.1.as_str()]);
+    }
+}
+
+fn lock_owners() -> std::sync::MutexGuard<'static, HashMap<(String, String), usize>> {
+    GAUGE_OWNERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Probe `endpoint` every interval until it is dropped,
+/// which happens once the listener it belongs to is gone
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn spawn(
+    cluster: String,
+    endpoint: Weak<Endpoint>,
+    protocol: UpstreamProtocol,
+    config: HealthCheckConfig,
+    pool: Arc<ConnectionPool>,
+    max_streams: usize,
+    metrics: Arc<Metrics>,
+    readiness: Option<Arc<ReadinessGate>>,
+) {
+    let Some(authority) = endpoint
+        .upgrade()
+        .map(|endpoint| endpoint.authority().to_string())
+    else {
+        return;
+    };
+    // taken before the task starts, so the task
+    // of a replaced endpoint never removes the series
+    let gauge = GaugeOwner::new(cluster, authority, metrics);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms));
+        let mut thresholds = Thresholds::default();
+        loop {
+            interval.tick().await;
+            let Some(endpoint) = endpoint.upgrade() else {
+                break;
+            };
+
+            let passed = tokio::time::timeout(
+                Duration::from_millis(config.timeout_ms),
+                probe(&endpoint, protocol, &config.service, &pool, max_streams),
+            )
+            .await
+            .is_ok_and(|serving| serving.unwrap_or(false));
+
+            if passed && let Some(readiness) = &readiness {
+                readiness.mark_ready();
+            }
+            if let Some(healthy) = thresholds.record(&config, passed) {
+                endpoint.set_healthy(healthy);
+            }
+            gauge.set(endpoint.is_healthy());
+        }
+        // the endpoint is no longer configured,
+        // dropping `gauge` removes its series with the last owner
+        drop(gauge);
+    });
+}
+
+/// whether the endpoint answered `SERVING`
+async fn probe(
+    endpoint: &Endpoint,
+    protocol: UpstreamProtocol,
+    service: &str,
+    pool: &ConnectionPool,
+    max_streams: usize,
+) -> Result<bool, BoxError> {
+    let authority = endpoint.authority();
+    let stream = pool.checkout(authority, protocol, max_streams).await?;
+    let mut req = Request::post(format!("http://{}{}", authority, HEALTH_CHECK_PATH))
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .header(header::TE, "trailers")
+        .body(
+            Full::new(encode_request(service))
+                .map_err(Into::into)
+                .boxed_unsync(),
+        )?;
+    if protocol == UpstreamProtocol::GrpcWeb {
+        grpc_web::modify_request(&mut req);
+    }
+    let res = stream.sender().send_request(req).await?.map(BodyExt::boxed);
+    let res = match protocol {
+        UpstreamProtocol::Grpc => res,
+        UpstreamProtocol::GrpcWeb => grpc_web::modify_response(res),
+    };
+    if !res.status().is_success() {
+        return Ok(false);
+    }
+
+    // a Trailers-Only response carries
+    // grpc-status in the headers
+    let headers = res.headers().clone();
+    let collected = res.into_body().collect().await?;
+    let trailers = collected.trailers().cloned().unwrap_or_default();
+    if grpc_status(&trailers).or(grpc_status(&headers)) != Some(0) {
+        return Ok(false);
+    }
+    Ok(decode_serving_status(collected.to_bytes())? == Some(SERVING))
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get("grpc-status")
+        .and_then(|value: &HeaderValue| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// length-prefixed `HealthCheckRequest { string service = 1; }`
+fn encode_request(service: &str) -> Bytes {
+    let mut message = BytesMut::new();
+    if !service.is_empty() {
+        message.put_u8(0x0a);
+        put_varint(&mut message, service.len() as u64);
+        message.put_slice(service.as_bytes());
+    }
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put(message);
+    frame.freeze()
+}
+
+/// `HealthCheckResponse.status` from a length-prefixed message,
+/// `None` when the field is missing (UNKNOWN)
+fn decode_serving_status(mut body: Bytes) -> Result<Option<u64>, BoxError> {
+    if body.len() < 5 {
+        return Err("Truncated health check response".into());
+    }
+    if body.get_u8() != 0 {
+        return Err("Compressed health check response".into());
+    }
+    let len = body.get_u32() as usize;
+    if body.len() < len {
+        return Err("Truncated health check response".into());
+    }
+    let mut message = body.split_to(len);
+
+    let mut status = None;
+    // unknown fields are skipped
+    while let Some((number, field)) = next_field(&mut message)? {
+        if let (1, Field::Varint(value)) = (number, field) {
+            status = Some(value);
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_request() {
+        assert_eq!(&encode_request("")[..], &[0, 0, 0, 0, 0]);
+        assert_eq!(
+            &encode_request("a.B")[..],
+            &[0, 0, 0, 0, 5, 0x0a, 3, b'a', b'.', b'B']
+        );
+    }
+
+    #[test]
+    fn test_decode_serving_status() {
+        // status = SERVING
+        let serving = Bytes::from_static(&[0, 0, 0, 0, 2, 0x08, 1]);
+        assert_eq!(decode_serving_status(serving).unwrap(), Some(SERVING));
+        // unknown string field 2 before status = NOT_SERVING
+        let not_serving = Bytes::from_static(&[0, 0, 0, 0, 5, 0x12, 1, b'x', 0x08, 2]);
+        assert_eq!(decode_serving_status(not_serving).unwrap(), Some(2));
+        // empty message, UNKNOWN
+        let unknown = Bytes::from_static(&[0, 0, 0, 0, 0]);
+        assert_eq!(decode_serving_status(unknown).unwrap(), None);
+        // length larger than body
+        let truncated = Bytes::from_static(&[0, 0, 0, 0, 9, 0x08]);
+        assert!(decode_serving_status(truncated).is_err());
+    }
+
+    #[test]
+    fn test_gauge_is_removed_with_its_last_owner() {
+        use prometheus::core::Collector;
+
+        let exported = |authority: &str| {
+            Metrics::new().upstream_endpoint_healthy().collect()[0]
+                .get_metric()
+                .iter()
+                .any(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.value() == authority)
+                })
+        };
+        let owner = || GaugeOwner::new("c".into(), "owned:1".into(), Arc::new(Metrics::new()));
+
+        let old = owner();
+        old.set(true);
+        // a reload starts the new owner before the old one stops
+        let new = owner();
+        drop(old);
+        assert!(exported("owned:1"));
+        drop(new);
+        assert!(!exported("owned:1"));
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let config = HealthCheckConfig {
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+            ..Default::default()
+        };
+        let mut thresholds = Thresholds::default();
+        assert_eq!(thresholds.record(&config, false), None);
+        assert_eq!(thresholds.record(&config, false), None);
+        assert_eq!(thresholds.record(&config, false), Some(false));
+        assert_eq!(thresholds.record(&config, true), None);
+        assert_eq!(thresholds.record(&config, true), Some(true));
+        // a failure resets the passed count
+        assert_eq!(thresholds.record(&config, false), None);
+        assert_eq!(thresholds.record(&config, true), None);
+    }
+}
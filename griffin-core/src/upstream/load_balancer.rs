@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::upstream::cluster::Endpoint;
+
+/// How a cluster picks the endpoint of an RPC
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LbPolicy {
+    #[default]
+    RoundRobin,
+    /// endpoint with the fewest outstanding RPCs
+    LeastRequest,
+    /// the less loaded of two random endpoints
+    PowerOfTwoChoices,
+    Random,
+}
+
+pub(crate) struct LoadBalancer {
+    policy: LbPolicy,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    pub(crate) fn new(policy: LbPolicy) -> Self {
+        Self {
+            policy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// pick one of the `len` endpoints `candidates` yields,
+    /// the candidates are walked instead of collected
+    pub(crate) fn pick<'a>(
+        &self,
+        mut candidates: impl Iterator<Item = &'a Arc<Endpoint>> + Clone,
+        len: usize,
+    ) -> Option<&'a Arc<Endpoint>> {
+        if len <= 1 {
+            return candidates.next();
+        }
+        match self.policy {
+            LbPolicy::RoundRobin => candidates.nth(self.next.fetch_add(1, Ordering::Relaxed) % len),
+            LbPolicy::LeastRequest => {
+                // ties go to the first endpoint at or after
+                // a rotating offset, not always to the first one
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % len;
+                candidates
+                    .enumerate()
+                    .min_by_key(|(i, endpoint)| (endpoint.outstanding(), (i + len - start) % len))
+                    .map(|(_, endpoint)| endpoint)
+            }
+            LbPolicy::PowerOfTwoChoices => {
+                let first = fastrand::usize(..len);
+                // second choice is never the first one
+                let mut second = fastrand::usize(..len - 1);
+                if second >= first {
+                    second += 1;
+                }
+                let first = candidates.clone().nth(first);
+                let second = candidates.nth(second);
+                match (first, second) {
+                    (Some(first), Some(second)) if second.outstanding() < first.outstanding() => {
+                        Some(second)
+                    }
+                    (first, second) => first.or(second),
+                }
+            }
+            LbPolicy::Random => candidates.nth(fastrand::usize(..len)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream::cluster::{Cluster, ClusterConfig};
+
+    fn cluster(policy: LbPolicy, size: usize) -> Cluster {
+        Cluster::new(&ClusterConfig {
+            endpoints: (0..size)
+                .map(|i| format!("127.0.0.1:{}", 3000 + i))
+                .collect(),
+            lb_policy: policy,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn port(guard: &crate::upstream::cluster::EndpointGuard) -> u16 {
+        guard.authority().port_u16().unwrap()
+    }
+
+    #[test]
+    fn test_round_robin_cycles_endpoints() {
+        let cluster = cluster(LbPolicy::RoundRobin, 3);
+        let ports = (0..6)
+            .map(|_| port(&cluster.pick().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(ports, [3000, 3001, 3002, 3000, 3001, 3002]);
+    }
+
+    #[test]
+    fn test_least_request_avoids_busy_endpoints() {
+        let cluster = cluster(LbPolicy::LeastRequest, 3);
+        let busy = [cluster.pick().unwrap(), cluster.pick().unwrap()];
+        let busy_ports = busy.iter().map(port).collect::<Vec<_>>();
+
+        for _ in 0..5 {
+            let guard = cluster.pick().unwrap();
+            assert!(!busy_ports.contains(&port(&guard)));
+        }
+    }
+
+    #[test]
+    fn test_power_of_two_choices_prefers_idle_endpoint() {
+        let cluster = cluster(LbPolicy::PowerOfTwoChoices, 2);
+        // with two endpoints both are always compared
+        let busy = cluster.pick().unwrap();
+        let busy_port = port(&busy);
+        for _ in 0..10 {
+            assert_ne!(port(&cluster.pick().unwrap()), busy_port);
+        }
+    }
+
+    #[test]
+    fn test_random_stays_in_range() {
+        let cluster = cluster(LbPolicy::Random, 4);
+        for _ in 0..50 {
+            assert!((3000..3004).contains(&port(&cluster.pick().unwrap())));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod cluster;
+pub mod connection_pool;
+pub(crate) mod grpc_web;
+pub mod health_check;
+pub mod load_balancer;
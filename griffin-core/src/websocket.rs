@@ -0,0 +1,251 @@
+//!
+//! gRPC-Web over WebSocket, the transport of improbable-eng grpc-web
+//! that gives browsers client and bidirectional streaming.
+//! <https://github.com/improbable-eng/grpc-web/tree/master/client/grpc-web/src/transports/websocket>
+//!
+//! The client opens a WebSocket on the method path with the
+//! `grpc-websockets` subprotocol, over an HTTP/1.1 upgrade or
+//! an HTTP/2 extended CONNECT (RFC 8441).
+//!
+//! - its first message carries the call metadata, `name: value\r\n` lines
+//! - then every message starts with a byte, `0` followed by request bytes
+//!   in gRPC framing, or `1` alone once the client finished sending
+//!
+//! Griffin answers with the response headers in a trailer-flagged frame,
+//! then the response body in gRPC-Web framing, and closes the WebSocket.
+//!
+use async_stream::stream;
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+    header::{
+        CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL,
+        UPGRADE,
+    },
+    request::Parts,
+};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{Message, handshake::derive_accept_key, protocol::Role};
+use tower::BoxError;
+
+use crate::context::ProxyContext;
+use crate::core::content_type::ContentType;
+use crate::core::grpc_kind::GrpcKind;
+use crate::core::grpc_kind_web::GrpcKindWeb;
+use crate::telemetry::metrics::from_full_bytes;
+use crate::trailers::Trailers;
+use crate::{ProxyResponse, forward_call};
+
+pub const GRPC_WEBSOCKETS: &str = "grpc-websockets";
+/// request bytes follow
+const DATA: u8 = 0;
+/// the client finished sending
+const END_OF_STREAM: u8 = 1;
+
+/// a WebSocket asking for the `grpc-websockets` subprotocol,
+/// other WebSockets are not gRPC calls
+pub(crate) fn is_websocket(parts: &Parts) -> bool {
+    let upgrade = parts.method == Method::GET
+        && parts
+            .headers
+            .get(UPGRADE)
+            .is_some_and(|upgrade| upgrade.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    let connect = parts.method == Method::CONNECT
+        && parts
+            .extensions
+            .get::<hyper::ext::Protocol>()
+            .is_some_and(|protocol| protocol.as_str() == "websocket");
+    let grpc = parts
+        .headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == GRPC_WEBSOCKETS);
+    (upgrade || connect) && grpc
+}
+
+/// accept the WebSocket, the call is bridged
+/// to the upstream once the connection is upgraded.
+/// With CORS configured, a browser origin that is
+/// not allowed is refused with 403
+pub(crate) fn upgrade(mut parts: Parts, ctx: Arc<ProxyContext>) -> ProxyResponse {
+    // browsers apply no CORS to WebSockets, any page could
+    // open one with the cookies of the user otherwise
+    if let Some(cors) = &ctx.cors
+        && parts.headers.contains_key(ORIGIN)
+        && !cors.allows_origin(&parts.headers)
+    {
+        return status_response(StatusCode::FORBIDDEN);
+    }
+    let Some(on_upgrade) = parts.extensions.remove::<OnUpgrade>() else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let mut res = from_full_bytes(Full::default());
+    // HTTP/2 accepts with a plain 200
+    if parts.method == Method::GET {
+        let Some(key) = parts.headers.get(SEC_WEBSOCKET_KEY) else {
+            return status_response(StatusCode::BAD_REQUEST);
+        };
+        let accept = derive_accept_key(key.as_bytes());
+        *res.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = res.headers_mut();
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(
+            SEC_WEBSOCKET_ACCEPT,
+            HeaderValue::from_str(&accept).expect("base64 key"),
+        );
+    }
+    res.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(GRPC_WEBSOCKETS),
+    );
+
+    let path = parts.uri.path().to_string();
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                let io = TokioIo::new(upgraded);
+                let socket = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+                bridge(socket, path, &ctx).await;
+            }
+            Err(err) => eprintln!("websocket upgrade error: {:?}", err),
+        }
+    });
+    res
+}
+
+fn status_response(status: StatusCode) -> ProxyResponse {
+    let mut res = from_full_bytes(Full::default());
+    *res.status_mut() = status;
+    res
+}
+
+/// forward one call, the WebSocket messages
+/// are the request body and the response body
+/// is sent back as WebSocket messages
+async fn bridge<S>(socket: WebSocketStream<S>, path: String, ctx: &ProxyContext)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sink, mut messages) = socket.split();
+    let headers = match messages.next().await {
+        Some(Ok(Message::Binary(data))) => parse_headers(&data),
+        Some(Ok(Message::Text(text))) => parse_headers(text.as_bytes()),
+        _ => return,
+    };
+    let codec = ContentType::from_headers(&headers).and_then(|content_type| content_type.codec);
+    let kind = GrpcKind::Web(GrpcKindWeb { codec });
+
+    let body = stream! {
+        while let Some(message) = messages.next().await {
+            let data = match message {
+                Ok(Message::Binary(data)) => data,
+                Ok(Message::Text(text)) => Bytes::from(text),
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => continue,
+                Ok(Message::Close(_)) | Err(_) => break,
+            };
+            match data.first() {
+                Some(&DATA) => yield Ok(Frame::data(data.slice(1..))),
+                Some(&END_OF_STREAM) => return,
+                _ => {
+                    yield Err(BoxError::from("malformed websocket message"));
+                    return;
+                }
+            }
+        }
+        // closed before the client finished sending,
+        // the upstream stream is cancelled
+        yield Err(BoxError::from("websocket closed"));
+    };
+
+    let (mut parts, ()) = Request::post(path)
+        .body(())
+        .expect("path of a uri")
+        .into_parts();
+    parts.headers = headers;
+    let res = forward_call(parts, StreamBody::new(Box::pin(body)), &kind, ctx)
+        .await
+        .unwrap_or_else(|err| err.into_response(Some(&kind)));
+
+    let (parts, mut body) = res.into_parts();
+    let header_frame = Trailers::new(parts.headers).into_frame_or_status();
+    if sink.send(Message::Binary(header_frame)).await.is_err() {
+        return;
+    }
+    while let Some(frame) = body.frame().await {
+        let Ok(Ok(data)) = frame.map(Frame::into_data) else {
+            continue;
+        };
+        // the client went away, dropping the
+        // response cancels the upstream stream
+        if sink.send(Message::Binary(data)).await.is_err() {
+            return;
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// call metadata of the first message
+fn parse_headers(data: &[u8]) -> HeaderMap {
+    String::from_utf8_lossy(data)
+        .split("\r\n")
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let name = HeaderName::from_bytes(name.trim().to_ascii_lowercase().as_bytes()).ok()?;
+            let value = HeaderValue::from_str(value.trim()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers(
+            b"Content-Type: application/grpc-web+proto\r\nx-grpc-web: 1\r\nx-user:a\r\nbroken\r\n",
+        );
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers["content-type"], "application/grpc-web+proto");
+        assert_eq!(headers["x-grpc-web"], "1");
+        assert_eq!(headers["x-user"], "a");
+    }
+
+    #[test]
+    fn test_is_websocket() {
+        let parts = |method: Method, headers: &[(&'static str, &'static str)]| {
+            let mut req = Request::builder()
+                .method(method)
+                .uri("/helloworld.Greeter/SayHelloBiStream");
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+            req.body(()).unwrap().into_parts().0
+        };
+        let upgrade = [
+            ("upgrade", "websocket"),
+            ("sec-websocket-protocol", "grpc-websockets"),
+        ];
+        assert!(is_websocket(&parts(Method::GET, &upgrade)));
+        assert!(!is_websocket(&parts(Method::GET, &upgrade[..1])));
+        assert!(!is_websocket(&parts(Method::POST, &upgrade)));
+
+        let mut connect = parts(Method::CONNECT, &upgrade[1..]);
+        assert!(!is_websocket(&connect));
+        connect
+            .extensions
+            .insert(hyper::ext::Protocol::from_static("websocket"));
+        assert!(is_websocket(&connect));
+    }
+}
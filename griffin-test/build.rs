@@ -1,6 +1,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_prost_build::configure()
-        //.file_descriptor_set_path("helloworld_descriptor.bin")
+        // loaded by the transcoding tests
+        .file_descriptor_set_path(
+            std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("helloworld_descriptor.bin"),
+        )
         .build_client(true)
         .build_server(true)
         .compile_protos(
@@ -1,19 +1,27 @@
 use futures_util::StreamExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::metadata::MetadataMap;
 use tonic::{Code, Streaming};
-use tonic::{Request, Response, Status, transport::Server};
+use tonic::{Request, Response, Status};
 // use tonic_reflection::server::Builder as ReflectionBuilder;
 
-use hello_world::greeter_server::{Greeter, GreeterServer};
+use hello_world::greeter_server::Greeter;
 use hello_world::{HelloReply, HelloRequest};
-// pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("helloworld_descriptor");
+/// descriptor set of the greeter, with its `google.api.http` rules
+pub const DESCRIPTOR_SET_PATH: &str = concat!(env!("OUT_DIR"), "/helloworld_descriptor.bin");
 pub mod hello_world {
     tonic::include_proto!("helloworld");
 }
 
-#[derive(Debug, Default)]
-pub struct MyGreeter {}
+#[derive(Debug, Default, Clone)]
+pub struct MyGreeter {
+    /// unary calls served, shared by clones
+    pub calls: Arc<AtomicUsize>,
+    /// streams dropped by the client, shared by clones
+    pub cancelled: Arc<AtomicUsize>,
+}
 
 #[tonic::async_trait]
 impl Greeter for MyGreeter {
@@ -22,6 +30,13 @@ impl Greeter for MyGreeter {
         request: Request<HelloRequest>,
     ) -> Result<Response<HelloReply>, Status> {
         println!("Got a request: {:?}", request);
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        // fail before sending any message,
+        // tonic answers with a Trailers-Only response
+        if request.get_ref().name == "fail fast" {
+            return Err(Status::invalid_argument("name is not allowed"));
+        }
 
         request
             .metadata()
@@ -60,6 +75,22 @@ impl Greeter for MyGreeter {
         // Create a channel to send streaming replies
         let (tx, rx) = tokio::sync::mpsc::channel(4);
 
+        // send one message and keep the stream open
+        // until the client goes away
+        if name == "hold open" {
+            let cancelled = self.cancelled.clone();
+            tokio::spawn(async move {
+                let first = HelloReply {
+                    message: "first ok".into(),
+                };
+                if tx.send(Ok(first)).await.is_ok() {
+                    tx.closed().await;
+                    cancelled.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+            return Ok(Response::new(ReceiverStream::new(rx)));
+        }
+
         // let name = name.clone();
         tokio::spawn(async move {
             // Send one message first
@@ -117,30 +148,25 @@ impl Greeter for MyGreeter {
             while let Some(result) = inbound.next().await {
                 if let Ok(req) = result {
                     println!("Got a request: {:?}", req);
-                    if req.name == "client request 1" {
-                        if tx
+                    if req.name == "client request 1"
+                        && tx
                             .send(Ok(HelloReply {
                                 message: "first ok".into(),
                             }))
                             .await
                             .is_err()
-                        {
-                            return;
-                        }
+                    {
+                        return;
                     }
-                    match req.name == "client request 2" {
-                        true => {
-                            if tx
-                                .send(Ok(HelloReply {
-                                    message: "second ok".into(),
-                                }))
-                                .await
-                                .is_err()
-                            {
-                                return;
-                            }
-                        }
-                        false => (),
+                    if req.name == "client request 2"
+                        && tx
+                            .send(Ok(HelloReply {
+                                message: "second ok".into(),
+                            }))
+                            .await
+                            .is_err()
+                    {
+                        return;
                     }
                 }
                 // // Send one message first
@@ -1,37 +1,46 @@
-use crate::test_support::greeter::{MyGreeter, hello_world::greeter_server::GreeterServer};
+use crate::test_support::greeter::{
+    MyGreeter,
+    hello_world::{HelloRequest, greeter_server::GreeterServer},
+};
+use griffin::config::config::Config;
 use griffin::start_proxy;
-use tokio::sync::oneshot;
-use tower::BoxError;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tonic_health::server::HealthReporter;
+use tonic_web::GrpcWebLayer;
+use tower::{BoxError, Layer};
 
 pub async fn run_intergration<F, Fut>(call: F) -> Result<(), BoxError>
 where
     F: Fn(String) -> Fut,
     Fut: Future<Output = Result<(), BoxError>>,
 {
-    let mock = MyGreeter {};
+    run_intergration_with_config(Config::default(), call).await
+}
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let forward_address = listener.local_addr().unwrap().to_string();
-    let (backend_shutdown_tx, backend_shutdown_rx) = oneshot::channel::<()>();
-    // start mock server
-    let backend_task = tokio::spawn(async move {
-        tonic::transport::Server::builder()
-            .add_service(GreeterServer::new(mock))
-            .serve_with_incoming_shutdown(
-                tokio_stream::wrappers::TcpListenerStream::new(listener),
-                async {
-                    backend_shutdown_rx.await.ok();
-                },
-            )
-            .await
-            .unwrap();
-    });
+/// same as [`run_intergration`] but the proxy runs with `config`,
+/// its target is replaced by the mock server address
+pub async fn run_intergration_with_config<F, Fut>(
+    mut config: Config,
+    call: F,
+) -> Result<(), BoxError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(), BoxError>>,
+{
+    let (forward_address, backend_shutdown_tx, backend_task) = start_greeter().await;
+    config.target_host = forward_address.ip().to_string();
+    config.target_port = forward_address.port();
 
     let (proxy_shutdown_tx, proxy_shutdown_rx) = tokio::sync::watch::channel(false);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
     let proxy_address = listener.local_addr().unwrap().to_string();
-    let proxy_task = tokio::spawn(start_proxy(listener, forward_address, proxy_shutdown_rx));
+    let proxy_task = tokio::spawn(start_proxy(listener, config, proxy_shutdown_rx));
 
     call(proxy_address).await.unwrap();
 
@@ -45,3 +54,102 @@ where
 
     Ok(())
 }
+
+/// start the proxy with `config` on a free port,
+/// send `true` on the returned channel to stop it
+pub async fn start_proxy_with(config: Config) -> (String, watch::Sender<bool>) {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(start_proxy(listener, config, shutdown_rx));
+    (address, shutdown_tx)
+}
+
+/// `SayHelloStream` request the mock greeter answers
+/// with one reply, then keeps the stream open
+pub fn hold_open() -> tonic::Request<HelloRequest> {
+    tonic::Request::new(HelloRequest {
+        name: "hold open".into(),
+    })
+}
+
+/// wait until the greeter saw a held open stream go away
+pub async fn wait_cancelled(greeter: &MyGreeter) -> bool {
+    for _ in 0..50 {
+        if greeter.cancelled.load(Ordering::SeqCst) == 1 {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+/// start the mock greeter server,
+/// send on the returned channel to stop it
+pub async fn start_greeter() -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    start_greeter_with(MyGreeter::default()).await
+}
+
+/// same as [`start_greeter`] but serving `mock`,
+/// keep a clone to inspect it afterwards
+pub async fn start_greeter_with(
+    mock: MyGreeter,
+) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let (address, _, shutdown_tx, task) = start_greeter_with_health(mock).await;
+    (address, shutdown_tx, task)
+}
+
+/// same as [`start_greeter_with`], the server also answers
+/// `grpc.health.v1` with `helloworld.Greeter` serving,
+/// use the reporter to change that
+pub async fn start_greeter_with_health(
+    mock: MyGreeter,
+) -> (
+    SocketAddr,
+    HealthReporter,
+    oneshot::Sender<()>,
+    JoinHandle<()>,
+) {
+    let (reporter, health_service) = tonic_health::server::health_reporter();
+    reporter.set_serving::<GreeterServer<MyGreeter>>().await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    // start mock server
+    let task = tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(health_service)
+            .add_service(GreeterServer::new(mock))
+            .serve_with_incoming_shutdown(
+                tokio_stream::wrappers::TcpListenerStream::new(listener),
+                async {
+                    shutdown_rx.await.ok();
+                },
+            )
+            .await
+            .unwrap();
+    });
+    (address, reporter, shutdown_tx, task)
+}
+
+/// start the mock greeter behind gRPC-Web over HTTP/1.1 only,
+/// like a backend sitting behind a gRPC-Web gateway.
+/// Returns the greeter to inspect the calls it served
+pub async fn start_web_greeter() -> (SocketAddr, MyGreeter, JoinHandle<()>) {
+    let mock = MyGreeter::default();
+    let service = GrpcWebLayer::new().layer(GreeterServer::new(mock.clone()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let svc = TowerToHyperService::new(service.clone());
+            tokio::spawn(async move {
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), svc)
+                    .await;
+            });
+        }
+    });
+    (address, mock, task)
+}
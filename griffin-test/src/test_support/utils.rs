@@ -1,5 +1,8 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::{Bytes, BytesMut};
 use futures_core::Stream;
+use griffin_core::compression::Encoding;
+use griffin_core::frame::{FrameStream, GrpcFrame};
 use http_body::Frame;
 use http_body_util::StreamBody;
 use prost::Message;
@@ -9,48 +12,41 @@ use prost::DecodeError;
 
 //collect protobuf messages from stream body
 pub async fn collect_messages<M>(
-    mut body: StreamBody<impl Stream<Item = Result<Frame<Bytes>, hyper::Error>> + Send + Unpin>,
+    body: StreamBody<impl Stream<Item = Result<Frame<Bytes>, hyper::Error>> + Send + Unpin>,
 ) -> Result<Vec<M>, DecodeError>
 where
     M: Message + Default,
 {
-    let mut messages = Vec::new();
-    let mut buf = BytesMut::new();
-
-    while let Some(next) = body.next().await {
-        let frame = next.expect("body frame");
-
-        // data frame consumption
-        if let Ok(data) = frame.into_data() {
-            // collect data into buffer
-            buf.extend_from_slice(&data);
-            while buf.len() >= 5 {
-                let mut reader = &buf[..];
-
-                //https://datatracker.ietf.org/doc/html/rfc7540#section-4.1
-                //TODO: support compression
-                let _compressed_flag = reader.get_u8();
-                // Length: 24-bit, type =8-bit
-                let msg_len = reader.get_u32() as usize;
-
-                if buf.len() < 5 + msg_len {
-                    // incomplete message — wait for more data
-                    break;
-                }
-
-                // collect message bytes after getting message length
-                // by ignoring the first 5 bytes
-                let msg_bytes = &buf[5..5 + msg_len];
-                // bytes to message
-                let msg = M::decode(msg_bytes)?;
+    collect_encoded_messages(body, Encoding::Identity).await
+}
 
-                // add new message to list
-                messages.push(msg);
+// collect protobuf messages compressed with `encoding`
+pub async fn collect_encoded_messages<M>(
+    body: StreamBody<impl Stream<Item = Result<Frame<Bytes>, hyper::Error>> + Send + Unpin>,
+    encoding: Encoding,
+) -> Result<Vec<M>, DecodeError>
+where
+    M: Message + Default,
+{
+    let mut messages = Vec::new();
+    let mut frames = FrameStream::new(body);
 
-                // remove consumed bytes
-                buf.advance(5 + msg_len);
-            }
+    while let Some(frame) = frames.next().await {
+        // trailers are not messages
+        let GrpcFrame::Message {
+            compressed,
+            mut payload,
+        } = frame.expect("body frame")
+        else {
+            continue;
+        };
+        // compressed with the call encoding
+        if compressed {
+            payload = encoding
+                .decompress(&payload, usize::MAX)
+                .expect("compressed message");
         }
+        messages.push(M::decode(payload)?);
     }
 
     Ok(messages)
@@ -58,13 +54,59 @@ where
 
 // convert message to frame
 pub fn message_to_frame(message: &impl Message) -> BytesMut {
-    let mut buf = BytesMut::new();
-    message.encode(&mut buf).unwrap();
+    encoded_message_to_frame(message, Encoding::Identity)
+}
+
+// convert message to frame compressed with `encoding`
+pub fn encoded_message_to_frame(message: &impl Message, encoding: Encoding) -> BytesMut {
+    let payload = Bytes::from(message.encode_to_vec());
+    let frame = if encoding == Encoding::Identity {
+        GrpcFrame::Message {
+            compressed: false,
+            payload,
+        }
+    } else {
+        GrpcFrame::Message {
+            compressed: true,
+            payload: encoding.compress(&payload).unwrap(),
+        }
+    };
+    BytesMut::from(frame.encode().unwrap())
+}
 
-    let mut framed = BytesMut::new();
-    //TODO support compression
-    framed.put_u8(0); // 0: not compressed
-    framed.put_u32(buf.len() as u32);
-    framed.put_slice(&buf);
-    framed
+// decode a grpc-web-text body,
+// the proxy pads every chunk so the body
+// can contain several padded segments
+pub fn decode_web_text(body: &[u8]) -> Bytes {
+    let mut decoded = Vec::new();
+    let mut start = 0;
+    for (i, quantum) in body.chunks(4).enumerate() {
+        if quantum.ends_with(b"=") {
+            let end = (i + 1) * 4;
+            STANDARD
+                .decode_vec(&body[start..end], &mut decoded)
+                .unwrap();
+            start = end;
+        }
+    }
+    STANDARD.decode_vec(&body[start..], &mut decoded).unwrap();
+    Bytes::from(decoded)
+}
+
+// split decoded grpc-web body into
+// its message frames and the trailer block
+pub fn split_web_body(mut body: Bytes) -> (Bytes, Option<String>) {
+    let mut messages = BytesMut::new();
+    let mut trailers = None;
+    while body.len() >= 5 {
+        let flag = body[0];
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let frame = body.split_to(5 + len);
+        if flag & 0x80 != 0 {
+            trailers = Some(String::from_utf8_lossy(&frame[5..]).into_owned());
+        } else {
+            messages.extend_from_slice(&frame);
+        }
+    }
+    (messages.freeze(), trailers)
 }
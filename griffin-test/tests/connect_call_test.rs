@@ -0,0 +1,148 @@
+use bytes::{Buf, Bytes};
+use griffin::config::config::Config;
+use griffin_core::deadline::DeadlineConfig;
+use http::{Request, header::CONTENT_TYPE};
+use http_body_util::{BodyExt, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use prost::Message;
+use serde_json::{Value, json};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest},
+    preparation::{run_intergration, run_intergration_with_config},
+    utils::message_to_frame,
+};
+
+use tower::BoxError;
+
+/// flag and payload of each enveloped message
+fn split_envelopes(mut body: Bytes) -> Vec<(u8, Bytes)> {
+    let mut envelopes = Vec::new();
+    while body.has_remaining() {
+        let flag = body.get_u8();
+        let len = body.get_u32() as usize;
+        envelopes.push((flag, body.split_to(len)));
+    }
+    envelopes
+}
+
+fn hello(name: &str) -> HelloRequest {
+    HelloRequest {
+        name: name.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_connect_unary_call() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req = Request::post(url)
+            .header(CONTENT_TYPE, "application/proto")
+            .header("connect-protocol-version", "1")
+            .body(Full::new(Bytes::from(hello("Alice").encode_to_vec())))?;
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await?;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/proto");
+        assert!(res.headers().get("grpc-status").is_none());
+        let body = res.into_body().collect().await?.to_bytes();
+        assert_eq!(HelloReply::decode(body)?.message, "Hello Alice!");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_connect_unary_error_is_json() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req = Request::post(url)
+            .header(CONTENT_TYPE, "application/proto")
+            .body(Full::new(Bytes::from(hello("fail fast").encode_to_vec())))?;
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await?;
+
+        assert_eq!(res.status(), 400);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        let body = res.into_body().collect().await?.to_bytes();
+        let error: Value = serde_json::from_slice(&body)?;
+        assert_eq!(
+            error,
+            json!({"code": "invalid_argument", "message": "name is not allowed"})
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_connect_server_streaming_call() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHelloStream", proxy_address);
+        let req = Request::post(url)
+            .header(CONTENT_TYPE, "application/connect+proto")
+            .body(Full::new(message_to_frame(&hello("Alice")).freeze()))?;
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await?;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/connect+proto");
+        let body = res.into_body().collect().await?.to_bytes();
+        let mut envelopes = split_envelopes(body);
+        let (end_flag, end_stream) = envelopes.pop().unwrap();
+
+        let messages: Vec<String> = envelopes
+            .into_iter()
+            .map(|(flag, payload)| {
+                assert_eq!(flag, 0);
+                HelloReply::decode(payload).unwrap().message
+            })
+            .collect();
+        assert_eq!(messages, ["first ok", "second ok"]);
+
+        assert_eq!(end_flag, 0x02);
+        let end_stream: Value = serde_json::from_slice(&end_stream)?;
+        assert_eq!(
+            end_stream,
+            json!({"metadata": {"x-reason": ["server-stream-error"]}})
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_connect_timeout_ends_stream() -> Result<(), BoxError> {
+    let config = Config {
+        deadline: DeadlineConfig {
+            default_timeout_ms: None,
+            max_timeout_ms: Some(60_000),
+        },
+        ..Default::default()
+    };
+    run_intergration_with_config(config, async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHelloStream", proxy_address);
+        let req = Request::post(url)
+            .header(CONTENT_TYPE, "application/connect+proto")
+            .header("connect-timeout-ms", "200")
+            .body(Full::new(message_to_frame(&hello("hold open")).freeze()))?;
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await?;
+
+        assert_eq!(res.status(), 200);
+        let body = res.into_body().collect().await?.to_bytes();
+        let envelopes = split_envelopes(body);
+        assert_eq!(envelopes.len(), 2);
+        let (end_flag, end_stream) = &envelopes[1];
+        assert_eq!(*end_flag, 0x02);
+        let end_stream: Value = serde_json::from_slice(end_stream)?;
+        assert_eq!(end_stream["error"]["code"], "deadline_exceeded");
+        Ok(())
+    })
+    .await
+}
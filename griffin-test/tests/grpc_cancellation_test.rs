@@ -0,0 +1,78 @@
+use bytes::Bytes;
+use futures_util::StreamExt;
+use griffin::config::config::Config;
+use http::Request;
+use http_body_util::{BodyExt, Empty};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use tokio::sync::{oneshot, watch};
+use tonic_web::GrpcWebClientLayer;
+
+use griffin_test::test_support::{
+    greeter::{MyGreeter, hello_world::greeter_client::GreeterClient},
+    preparation::{hold_open, start_greeter_with, start_proxy_with, wait_cancelled},
+};
+use tower::BoxError;
+
+async fn start(greeter: MyGreeter) -> (String, watch::Sender<bool>, oneshot::Sender<()>) {
+    let (backend_address, backend_shutdown_tx, _) = start_greeter_with(greeter).await;
+    let config = Config {
+        target_host: backend_address.ip().to_string(),
+        target_port: backend_address.port(),
+        ..Default::default()
+    };
+    let (address, shutdown_tx) = start_proxy_with(config).await;
+    (address, shutdown_tx, backend_shutdown_tx)
+}
+
+async fn cancelled_metric(proxy_address: &str) -> Result<bool, BoxError> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let res = client
+        .request(Request::get(format!("http://{}/metrics", proxy_address)).body(Empty::new())?)
+        .await?;
+    let text = res.into_body().collect().await?.to_bytes();
+    Ok(String::from_utf8_lossy(&text)
+        .contains("grpc_cancelled_total{path=\"/helloworld.Greeter/SayHelloStream\"}"))
+}
+
+#[tokio::test]
+async fn test_grpc_client_reset_cancels_upstream_stream() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (proxy_address, proxy_shutdown_tx, backend_shutdown_tx) = start(greeter.clone()).await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let mut stream = client.say_hello_stream(hold_open()).await?.into_inner();
+    assert_eq!(stream.next().await.unwrap()?.message, "first ok");
+
+    // RST_STREAM(CANCEL) from the client
+    drop(stream);
+    assert!(wait_cancelled(&greeter).await, "upstream stream still open");
+    assert!(cancelled_metric(&proxy_address).await?);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_grpc_web_disconnect_cancels_upstream_stream() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (proxy_address, proxy_shutdown_tx, backend_shutdown_tx) = start(greeter.clone()).await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let svc = tower::ServiceBuilder::new()
+        .layer(GrpcWebClientLayer::new())
+        .service(client);
+    let mut client =
+        GreeterClient::with_origin(svc, format!("http://{}", proxy_address).try_into()?);
+    let mut stream = client.say_hello_stream(hold_open()).await?.into_inner();
+    assert_eq!(stream.next().await.unwrap()?.message, "first ok");
+
+    // the browser tab is closed
+    drop(stream);
+    drop(client);
+    assert!(wait_cancelled(&greeter).await, "upstream stream still open");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    Ok(())
+}
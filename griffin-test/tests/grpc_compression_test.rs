@@ -0,0 +1,156 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::compression::{CompressionRule, Encoding};
+use griffin_core::upstream::cluster::ClusterConfig;
+use http::{HeaderMap, Request, Response};
+use http_body::Frame;
+use http_body_util::{BodyExt, BodyStream, Full, StreamBody};
+use hyper::service::service_fn;
+use hyper_util::{
+    client::legacy::Client,
+    rt::{TokioExecutor, TokioIo},
+};
+use tokio::net::TcpListener;
+use tonic::codec::CompressionEncoding;
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest, greeter_client::GreeterClient},
+    preparation::{start_greeter, start_proxy_with},
+    utils::{collect_messages, encoded_message_to_frame, message_to_frame, split_web_body},
+};
+use tower::BoxError;
+
+fn config(endpoint: String, upstream_encoding: Option<Encoding>) -> Config {
+    Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![endpoint],
+            ..Default::default()
+        }),
+        compression: vec![CompressionRule {
+            prefix: "/helloworld.Greeter/".into(),
+            upstream_encoding,
+        }],
+        ..Default::default()
+    }
+}
+
+/// h2 upstream that gzips its reply
+/// whatever the client accepts
+async fn spawn_gzip_upstream() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let svc = service_fn(|_req| async {
+                let reply = HelloReply {
+                    message: "hello gzip".into(),
+                };
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", "0".parse().unwrap());
+                let frames = futures_util::stream::iter([
+                    Ok::<_, Infallible>(Frame::data(
+                        encoded_message_to_frame(&reply, Encoding::Gzip).freeze(),
+                    )),
+                    Ok(Frame::trailers(trailers)),
+                ]);
+                let res = Response::builder()
+                    .header("content-type", "application/grpc")
+                    .header("grpc-encoding", "gzip")
+                    .body(StreamBody::new(frames))
+                    .unwrap();
+                Ok::<_, Infallible>(res)
+            });
+            tokio::spawn(
+                hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), svc),
+            );
+        }
+    });
+    address
+}
+
+fn web_request(proxy_address: &str, encoding: Option<&str>) -> Request<Full<Bytes>> {
+    let req_msg = HelloRequest {
+        name: "Alice".into(),
+    };
+    let mut req = Request::post(format!(
+        "http://{}/helloworld.Greeter/SayHello",
+        proxy_address
+    ))
+    .header("content-type", "application/grpc-web+proto");
+    if let Some(encoding) = encoding {
+        req = req.header("grpc-encoding", encoding);
+    }
+    req.body(Full::from(message_to_frame(&req_msg).freeze()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_browser_without_gzip_reads_gzip_upstream() -> Result<(), BoxError> {
+    let upstream_address = spawn_gzip_upstream().await;
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(upstream_address, None)).await;
+
+    // the browser sends no grpc-accept-encoding
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let res = client.request(web_request(&proxy_address, None)).await?;
+    assert_eq!(res.status(), 200);
+    assert!(res.headers().get("grpc-encoding").is_none());
+
+    let body = res.into_body().collect().await?.to_bytes();
+    let (messages, trailers) = split_web_body(body);
+    assert_eq!(messages[0], 0, "message still compressed");
+    assert!(trailers.unwrap().contains("grpc-status:0"));
+    let body = StreamBody::new(BodyStream::new(
+        Full::new(messages).map_err(|never: Infallible| -> hyper::Error { match never {} }),
+    ));
+    let messages: Vec<HelloReply> = collect_messages(body).await?;
+    assert_eq!(messages[0].message, "hello gzip");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gzip_client_reaches_identity_upstream() -> Result<(), BoxError> {
+    // the greeter does not accept compressed messages
+    let (backend_address, backend_shutdown_tx, backend_task) = start_greeter().await;
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(
+        backend_address.to_string(),
+        Some(Encoding::Identity),
+    ))
+    .await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address))
+        .await?
+        .send_compressed(CompressionEncoding::Gzip);
+    let reply = client
+        .say_hello(HelloRequest {
+            name: "Alice".into(),
+        })
+        .await?;
+    assert_eq!(reply.into_inner().message, "Hello Alice!");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unsupported_client_encoding() -> Result<(), BoxError> {
+    let upstream_address = spawn_gzip_upstream().await;
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(upstream_address, None)).await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let res = client
+        .request(web_request(&proxy_address, Some("snappy")))
+        .await?;
+    let body = res.into_body().collect().await?.to_bytes();
+    let (_, trailers) = split_web_body(body);
+    assert!(trailers.unwrap().contains("grpc-status:12"));
+
+    proxy_shutdown_tx.send(true).unwrap();
+    Ok(())
+}
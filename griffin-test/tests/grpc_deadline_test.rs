@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use griffin::config::config::Config;
+use griffin_core::deadline::DeadlineConfig;
+use griffin_core::readiness::ReadinessConfig;
+use griffin_core::upstream::{cluster::ClusterConfig, health_check::HealthCheckConfig};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use tonic::Code;
+use tonic_web::GrpcWebClientLayer;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloRequest, greeter_client::GreeterClient},
+    },
+    preparation::{hold_open, start_greeter_with_health, start_proxy_with, wait_cancelled},
+};
+use tower::BoxError;
+
+fn config(endpoint: String, deadline: DeadlineConfig) -> Config {
+    Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![endpoint],
+            ..Default::default()
+        }),
+        deadline,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_client_deadline_is_capped() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (backend_address, _, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(greeter.clone()).await;
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(
+        backend_address.to_string(),
+        DeadlineConfig {
+            default_timeout_ms: None,
+            max_timeout_ms: Some(300),
+        },
+    ))
+    .await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let mut req = hold_open();
+    req.set_timeout(Duration::from_secs(60));
+    let mut stream = client.say_hello_stream(req).await?.into_inner();
+    assert_eq!(stream.next().await.unwrap()?.message, "first ok");
+
+    let status = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+    assert!(wait_cancelled(&greeter).await, "upstream stream still open");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_default_deadline_ends_grpc_web_stream() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (backend_address, _, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(greeter.clone()).await;
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(
+        backend_address.to_string(),
+        DeadlineConfig {
+            default_timeout_ms: Some(300),
+            max_timeout_ms: None,
+        },
+    ))
+    .await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let svc = tower::ServiceBuilder::new()
+        .layer(GrpcWebClientLayer::new())
+        .service(client);
+    let mut client =
+        GreeterClient::with_origin(svc, format!("http://{}", proxy_address).try_into()?);
+    let mut stream = client.say_hello_stream(hold_open()).await?.into_inner();
+    assert_eq!(stream.next().await.unwrap()?.message, "first ok");
+
+    let status = stream.next().await.unwrap().unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+    assert!(wait_cancelled(&greeter).await, "upstream stream still open");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deadline_passes_while_waiting_for_upstream() -> Result<(), BoxError> {
+    let (backend_address, reporter, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(MyGreeter::default()).await;
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+    let mut config = config(
+        backend_address.to_string(),
+        DeadlineConfig {
+            default_timeout_ms: Some(200),
+            max_timeout_ms: None,
+        },
+    );
+    config.upstream.as_mut().unwrap().health_check = Some(HealthCheckConfig {
+        interval_ms: 50,
+        ..Default::default()
+    });
+    config.readiness = Some(ReadinessConfig {
+        max_waiting: 10,
+        wait_timeout_ms: 5000,
+    });
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config).await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let status = client
+        .say_hello(HelloRequest {
+            name: "Alice".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::transcoding::TranscodingConfig;
+use http::{Method, Request, StatusCode, header::CONTENT_TYPE};
+use http_body_util::{BodyExt, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde_json::{Value, json};
+
+use griffin_test::test_support::{
+    greeter::{
+        DESCRIPTOR_SET_PATH,
+        hello_world::{HelloRequest, greeter_client::GreeterClient},
+    },
+    preparation::run_intergration_with_config,
+    utils::{message_to_frame, split_web_body},
+};
+
+use tower::BoxError;
+
+fn config() -> Config {
+    Config {
+        transcoding: Some(TranscodingConfig {
+            descriptor_set: DESCRIPTOR_SET_PATH.into(),
+        }),
+        ..Default::default()
+    }
+}
+
+/// status and JSON body of an HTTP/1.1 call
+async fn call(method: Method, url: String, body: &'static str) -> (StatusCode, Value) {
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let req = Request::builder()
+        .method(method)
+        .uri(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from_static(body.as_bytes())))
+        .unwrap();
+    let res = client.request(req).await.unwrap();
+    let status = res.status();
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_post_with_json_body() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        let url = format!("http://{}/v1/greeter/hello", proxy_address);
+        let (status, body) = call(Method::POST, url, r#"{"name":"Alice"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"message": "Hello Alice!"}));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_get_with_path_variable() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        let url = format!("http://{}/v1/greeter/hello/Bob%20Smith", proxy_address);
+        let (status, body) = call(Method::GET, url, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"message": "Hello Bob Smith!"}));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_status_is_mapped_to_http() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        let url = format!("http://{}/v1/greeter/hello/fail%20fast", proxy_address);
+        let (status, body) = call(Method::GET, url, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({"code": 3, "message": "name is not allowed"}));
+
+        // never reaches the upstream
+        let url = format!("http://{}/v1/greeter/hello", proxy_address);
+        let (status, body) = call(Method::POST, url, r#"{"nickname":"Al"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], 3);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_server_streaming_is_a_json_array() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        let url = format!("http://{}/v1/greeter/stream/Alice", proxy_address);
+        let (status, body) = call(Method::GET, url, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([{"message": "first ok"}, {"message": "second ok"}])
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_calls_still_pass_through() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+        let reply = client
+            .say_hello(HelloRequest {
+                name: "Alice".into(),
+            })
+            .await?;
+        assert_eq!(reply.into_inner().message, "Hello Alice!");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_framed_calls_are_never_transcoded() -> Result<(), BoxError> {
+    run_intergration_with_config(config(), async move |proxy_address| {
+        // a gRPC-Web call on the path of a rule goes upstream as it is
+        let url = format!("http://{}/v1/greeter/hello", proxy_address);
+        let req = Request::post(url)
+            .header(CONTENT_TYPE, "application/grpc-web")
+            .body(Full::new(
+                message_to_frame(&HelloRequest {
+                    name: "Alice".into(),
+                })
+                .freeze(),
+            ))?;
+        let res = Client::builder(TokioExecutor::new())
+            .build_http()
+            .request(req)
+            .await?;
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/grpc-web+proto");
+        // the upstream has no such method
+        let trailers_only = res.headers().get("grpc-status").cloned();
+        let body = res.into_body().collect().await?.to_bytes();
+        let (_, trailers) = split_web_body(body);
+        match trailers_only {
+            Some(status) => assert_eq!(status, "12"),
+            None => assert!(trailers.unwrap().contains("grpc-status:12")),
+        }
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,217 @@
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::compression::Encoding;
+use griffin_core::message_size::{MessageSizeConfig, MessageSizeRoute};
+use griffin_core::upstream::cluster::ClusterConfig;
+use http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http2;
+use hyper_util::{
+    client::legacy::Client,
+    rt::{TokioExecutor, TokioIo},
+};
+use prost::Message;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tonic::Code;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloRequest, greeter_client::GreeterClient},
+    },
+    preparation::{start_greeter_with, start_proxy_with},
+    utils::{encoded_message_to_frame, message_to_frame, split_web_body},
+};
+use tower::BoxError;
+
+async fn start(endpoint: String, message_size: MessageSizeConfig) -> (String, watch::Sender<bool>) {
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![endpoint],
+            ..Default::default()
+        }),
+        message_size,
+        ..Default::default()
+    };
+    start_proxy_with(config).await
+}
+
+fn hello(name: &str) -> tonic::Request<HelloRequest> {
+    tonic::Request::new(HelloRequest { name: name.into() })
+}
+
+#[tokio::test]
+async fn test_large_request_never_reaches_upstream() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (backend_address, backend_shutdown_tx, backend_task) =
+        start_greeter_with(greeter.clone()).await;
+    let (proxy_address, proxy_shutdown_tx) = start(
+        backend_address.to_string(),
+        MessageSizeConfig {
+            max_request_bytes: Some(16),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let reply = client.say_hello(hello("Alice")).await?;
+    assert_eq!(reply.get_ref().message, "Hello Alice!");
+
+    let status = client.say_hello(hello(&"a".repeat(64))).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(
+        status
+            .message()
+            .contains("larger than the limit of 16 bytes")
+    );
+    assert_eq!(greeter.calls.load(Ordering::SeqCst), 1);
+
+    // gRPC-Web clients get the status in a trailer frame
+    let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+    let req = Request::post(url)
+        .header("content-type", "application/grpc-web")
+        .body(Full::<Bytes>::from(
+            message_to_frame(&HelloRequest {
+                name: "a".repeat(64),
+            })
+            .freeze(),
+        ))?;
+    let res = Client::builder(TokioExecutor::new())
+        .build_http()
+        .request(req)
+        .await?;
+    let body = res.into_body().collect().await?.to_bytes();
+    let (messages, trailers) = split_web_body(body);
+    assert!(messages.is_empty());
+    assert!(trailers.unwrap().contains("grpc-status:8"));
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_response_limit_per_route() -> Result<(), BoxError> {
+    let (backend_address, backend_shutdown_tx, backend_task) =
+        start_greeter_with(MyGreeter::default()).await;
+    let (proxy_address, proxy_shutdown_tx) = start(
+        backend_address.to_string(),
+        MessageSizeConfig {
+            max_response_bytes: Some(8),
+            routes: vec![MessageSizeRoute {
+                prefix: "/helloworld.Greeter/SayHelloStream".into(),
+                max_response_bytes: Some(1024),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let status = client.say_hello(hello("Alice")).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    // the route lifts the listener limit
+    let mut stream = client.say_hello_stream(hello("Tonic")).await?.into_inner();
+    assert_eq!(stream.message().await?.unwrap().message, "first ok");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_truncated_request_frame_is_internal() -> Result<(), BoxError> {
+    let (backend_address, backend_shutdown_tx, backend_task) =
+        start_greeter_with(MyGreeter::default()).await;
+    let (proxy_address, proxy_shutdown_tx) =
+        start(backend_address.to_string(), MessageSizeConfig::default()).await;
+
+    let stream = TcpStream::connect(&proxy_address).await?;
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake::<_, Full<Bytes>>(TokioIo::new(stream))
+        .await?;
+    tokio::spawn(conn);
+
+    // the length prefix announces more than the body holds
+    let frame = message_to_frame(&HelloRequest {
+        name: "Alice".into(),
+    })
+    .freeze();
+    let req = Request::post(format!(
+        "http://{}/helloworld.Greeter/SayHello",
+        proxy_address
+    ))
+    .header("content-type", "application/grpc")
+    .header("te", "trailers")
+    .body(Full::new(frame.slice(..frame.len() - 2)))?;
+    let res = sender.send_request(req).await?;
+    let headers = res.headers().clone();
+    let collected = res.into_body().collect().await?;
+    let status = collected
+        .trailers()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .or(headers.get("grpc-status"))
+        .unwrap()
+        .clone();
+    assert_eq!(status, "13");
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_limits_count_the_full_message() -> Result<(), BoxError> {
+    let greeter = MyGreeter::default();
+    let (backend_address, backend_shutdown_tx, backend_task) =
+        start_greeter_with(greeter.clone()).await;
+    let (proxy_address, proxy_shutdown_tx) = start(
+        backend_address.to_string(),
+        MessageSizeConfig {
+            max_request_bytes: Some(256),
+            ..Default::default()
+        },
+    )
+    .await;
+    let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let large = HelloRequest {
+        name: "a".repeat(4096),
+    };
+
+    // a small gzipped message that inflates past the limit
+    let frame = encoded_message_to_frame(&large, Encoding::Gzip).freeze();
+    assert!(frame.len() < 256);
+    let req = Request::post(&url)
+        .header("content-type", "application/grpc-web")
+        .header("grpc-encoding", "gzip")
+        .body(Full::new(frame))?;
+    let body = client.request(req).await?.into_body().collect().await?;
+    let (messages, trailers) = split_web_body(body.to_bytes());
+    assert!(messages.is_empty());
+    assert!(trailers.unwrap().contains("grpc-status:8"));
+
+    // a Connect unary body is refused while it is buffered
+    let req = Request::post(&url)
+        .header("content-type", "application/proto")
+        .body(Full::new(Bytes::from(large.encode_to_vec())))?;
+    let res = client.request(req).await?;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body = res.into_body().collect().await?.to_bytes();
+    assert!(String::from_utf8_lossy(&body).contains("resource_exhausted"));
+
+    assert_eq!(greeter.calls.load(Ordering::SeqCst), 0);
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use griffin::config::config::Config;
+use griffin_core::mirror::MirrorConfig;
+use griffin_core::routing::{PathMatch, RouteConfig, RouteTarget};
+use griffin_core::telemetry::metrics::Metrics;
+use griffin_core::upstream::cluster::ClusterConfig;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloRequest, greeter_client::GreeterClient},
+    },
+    preparation::{start_greeter_with, start_proxy_with},
+};
+use tower::BoxError;
+
+fn cluster(name: &str, address: SocketAddr) -> ClusterConfig {
+    ClusterConfig {
+        name: name.into(),
+        endpoints: vec![address.to_string()],
+        ..Default::default()
+    }
+}
+
+/// every call of the greeter goes to `primary`
+/// and is mirrored to `shadow`
+fn mirrored(primary: &str, shadow: &str) -> RouteConfig {
+    RouteConfig {
+        matcher: PathMatch::Prefix("/helloworld.Greeter/".into()),
+        headers: Vec::new(),
+        target: RouteTarget::Cluster(primary.into()),
+        mirror: Some(MirrorConfig {
+            cluster: shadow.into(),
+            max_body_bytes: 1024,
+            ..Default::default()
+        }),
+    }
+}
+
+fn mirror_rpcs(cluster: &str, code: &str) -> f64 {
+    Metrics::new()
+        .mirror_rpcs()
+        .with_label_values(&[cluster, code])
+        .get()
+}
+
+fn mirror_skipped(cluster: &str, reason: &str) -> f64 {
+    Metrics::new()
+        .mirror_skipped()
+        .with_label_values(&[cluster, reason])
+        .get()
+}
+
+/// mirrored calls finish after the primary one
+async fn wait_for(check: impl Fn() -> bool) -> bool {
+    for _ in 0..50 {
+        if check() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+fn hello(name: &str) -> HelloRequest {
+    HelloRequest { name: name.into() }
+}
+
+#[tokio::test]
+async fn test_calls_are_mirrored_to_the_shadow() -> Result<(), BoxError> {
+    let primary_greeter = MyGreeter::default();
+    let shadow_greeter = MyGreeter::default();
+    let (primary_address, primary_shutdown_tx, primary_task) =
+        start_greeter_with(primary_greeter.clone()).await;
+    let (shadow_address, shadow_shutdown_tx, shadow_task) =
+        start_greeter_with(shadow_greeter.clone()).await;
+
+    let config = Config {
+        upstream: Some(cluster("mirror-primary", primary_address)),
+        clusters: vec![cluster("mirror-shadow", shadow_address)],
+        routes: vec![mirrored("mirror-primary", "mirror-shadow")],
+        ..Default::default()
+    };
+    let (proxy_address, shutdown_tx) = start_proxy_with(config).await;
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+
+    let reply = client.say_hello(hello("Alice")).await?;
+    assert_eq!(reply.into_inner().message, "Hello Alice!");
+    assert_eq!(primary_greeter.calls.load(Ordering::SeqCst), 1);
+    assert!(wait_for(|| mirror_rpcs("mirror-shadow", "OK") == 1.0).await);
+    assert_eq!(shadow_greeter.calls.load(Ordering::SeqCst), 1);
+
+    // the primary call gets the whole body, the shadow nothing
+    let large = "a".repeat(4096);
+    let reply = client.say_hello(hello(&large)).await?;
+    assert_eq!(reply.into_inner().message, format!("Hello {}!", large));
+    assert_eq!(mirror_skipped("mirror-shadow", "body_too_large"), 1.0);
+
+    // client streams are not mirrored
+    let requests = tokio_stream::iter([hello("client request 1"), hello("client request 2")]);
+    let mut replies = client.say_hello_bi_stream(requests).await?.into_inner();
+    while replies.message().await?.is_some() {}
+    assert_eq!(mirror_skipped("mirror-shadow", "streaming"), 1.0);
+
+    // server streams are mirrored, the shadow replies are drained
+    let mut replies = client.say_hello_stream(hello("Alice")).await?.into_inner();
+    while replies.message().await?.is_some() {}
+    assert!(wait_for(|| mirror_rpcs("mirror-shadow", "OK") == 2.0).await);
+
+    assert_eq!(primary_greeter.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(shadow_greeter.calls.load(Ordering::SeqCst), 1);
+
+    shutdown_tx.send(true).unwrap();
+    primary_shutdown_tx.send(()).unwrap();
+    shadow_shutdown_tx.send(()).unwrap();
+    primary_task.await.unwrap();
+    shadow_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failing_shadow_does_not_fail_the_call() -> Result<(), BoxError> {
+    let primary_greeter = MyGreeter::default();
+    let (primary_address, primary_shutdown_tx, primary_task) =
+        start_greeter_with(primary_greeter.clone()).await;
+    // nothing listens on the shadow address
+    let shadow_address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+
+    let config = Config {
+        upstream: Some(cluster("down-primary", primary_address)),
+        clusters: vec![cluster("down-shadow", shadow_address)],
+        routes: vec![mirrored("down-primary", "down-shadow")],
+        ..Default::default()
+    };
+    let (proxy_address, shutdown_tx) = start_proxy_with(config).await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let reply = client.say_hello(hello("Alice")).await?;
+    assert_eq!(reply.into_inner().message, "Hello Alice!");
+    assert!(wait_for(|| mirror_rpcs("down-shadow", "UNAVAILABLE") == 1.0).await);
+
+    shutdown_tx.send(true).unwrap();
+    primary_shutdown_tx.send(()).unwrap();
+    primary_task.await.unwrap();
+    Ok(())
+}
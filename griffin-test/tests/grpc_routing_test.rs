@@ -0,0 +1,310 @@
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use griffin::{
+    config::{config::Config, controller::ConfigController},
+    connection::proxy_connection_handler::ProxyConnectionHandler,
+    proxy::proxy_supervisor::ProxySupervisor,
+};
+use griffin_core::routing::{
+    HeaderMatchConfig, PathMatch, RouteConfig, RouteTarget, ValueMatch, WeightedCluster,
+};
+use griffin_core::telemetry::metrics::Metrics;
+use griffin_core::upstream::cluster::ClusterConfig;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloRequest, greeter_client::GreeterClient},
+    },
+    preparation::{start_greeter_with, start_proxy_with},
+};
+use tower::BoxError;
+
+fn cluster(name: &str, address: SocketAddr) -> ClusterConfig {
+    ClusterConfig {
+        name: name.into(),
+        endpoints: vec![address.to_string()],
+        ..Default::default()
+    }
+}
+
+fn route(matcher: PathMatch, cluster: &str) -> RouteConfig {
+    RouteConfig {
+        matcher,
+        headers: Vec::new(),
+        target: RouteTarget::Cluster(cluster.into()),
+        mirror: None,
+    }
+}
+
+async fn say_hello(proxy_address: &str) -> Result<String, BoxError> {
+    say_hello_with(proxy_address, &[]).await
+}
+
+async fn say_hello_with(
+    proxy_address: &str,
+    metadata: &[(&'static str, &'static str)],
+) -> Result<String, BoxError> {
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let mut req = tonic::Request::new(HelloRequest {
+        name: "Alice".into(),
+    });
+    for (name, value) in metadata {
+        req.metadata_mut().insert(*name, value.parse()?);
+    }
+    let reply = client.say_hello(req).await?;
+    Ok(reply.into_inner().message)
+}
+
+#[tokio::test]
+async fn test_calls_follow_routes() -> Result<(), BoxError> {
+    let default_greeter = MyGreeter::default();
+    let routed_greeter = MyGreeter::default();
+    let (default_address, default_shutdown_tx, default_task) =
+        start_greeter_with(default_greeter.clone()).await;
+    let (routed_address, routed_shutdown_tx, routed_task) =
+        start_greeter_with(routed_greeter.clone()).await;
+
+    let config = Config {
+        upstream: Some(cluster("default", default_address)),
+        clusters: vec![cluster("greeter", routed_address)],
+        routes: vec![route(
+            PathMatch::Prefix("/helloworld.Greeter/".into()),
+            "greeter",
+        )],
+        ..Default::default()
+    };
+    let (proxy_address, shutdown_tx) = start_proxy_with(config).await;
+
+    assert_eq!(say_hello(&proxy_address).await?, "Hello Alice!");
+    assert_eq!(routed_greeter.calls.load(Ordering::SeqCst), 1);
+    assert_eq!(default_greeter.calls.load(Ordering::SeqCst), 0);
+
+    shutdown_tx.send(true).unwrap();
+    default_shutdown_tx.send(()).unwrap();
+    routed_shutdown_tx.send(()).unwrap();
+    default_task.await.unwrap();
+    routed_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_canary_metadata_picks_the_cluster() -> Result<(), BoxError> {
+    let stable_greeter = MyGreeter::default();
+    let canary_greeter = MyGreeter::default();
+    let (stable_address, stable_shutdown_tx, stable_task) =
+        start_greeter_with(stable_greeter.clone()).await;
+    let (canary_address, canary_shutdown_tx, canary_task) =
+        start_greeter_with(canary_greeter.clone()).await;
+
+    let config = Config {
+        upstream: Some(cluster("stable", stable_address)),
+        clusters: vec![cluster("canary", canary_address)],
+        routes: vec![RouteConfig {
+            headers: vec![HeaderMatchConfig {
+                name: "x-canary".into(),
+                value: ValueMatch::Exact("true".into()),
+            }],
+            ..route(PathMatch::Prefix("/helloworld.".into()), "canary")
+        }],
+        ..Default::default()
+    };
+    let (proxy_address, shutdown_tx) = start_proxy_with(config).await;
+
+    say_hello(&proxy_address).await?;
+    say_hello_with(&proxy_address, &[("x-canary", "false")]).await?;
+    assert_eq!(stable_greeter.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(canary_greeter.calls.load(Ordering::SeqCst), 0);
+
+    say_hello_with(&proxy_address, &[("x-canary", "true")]).await?;
+    assert_eq!(stable_greeter.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(canary_greeter.calls.load(Ordering::SeqCst), 1);
+
+    shutdown_tx.send(true).unwrap();
+    stable_shutdown_tx.send(()).unwrap();
+    canary_shutdown_tx.send(()).unwrap();
+    stable_task.await.unwrap();
+    canary_task.await.unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_route_to_unknown_cluster_is_rejected() {
+    let config = Config {
+        routes: vec![route(PathMatch::Path("/a.B/C".into()), "missing")],
+        ..Default::default()
+    };
+    let metrics = Default::default();
+    let pool = Default::default();
+    assert!(config.proxy_context(metrics, pool).is_err());
+}
+
+#[tokio::test]
+async fn test_routes_hot_reload() -> Result<(), BoxError> {
+    let default_greeter = MyGreeter::default();
+    let routed_greeter = MyGreeter::default();
+    let (default_address, default_shutdown_tx, default_task) =
+        start_greeter_with(default_greeter.clone()).await;
+    let (routed_address, routed_shutdown_tx, routed_task) =
+        start_greeter_with(routed_greeter.clone()).await;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        listen_port: port,
+        upstream: Some(cluster("default", default_address)),
+        clusters: vec![cluster("greeter", routed_address)],
+        ..Default::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    std::fs::write(&config_path, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+    let mut controller = ConfigController::new(config.clone());
+    controller.watch_file(config_path.clone()).unwrap();
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await.unwrap();
+    let store = controller.store.clone();
+    let reload_task = tokio::spawn(async move {
+        while controller.reload.rx.recv().await.is_some() {
+            let config = store.get().as_ref().clone();
+            let _ = supervisor.load_listener(config).await;
+        }
+    });
+
+    let proxy_address = config.listen_address();
+    assert_eq!(say_hello(&proxy_address).await?, "Hello Alice!");
+    assert_eq!(default_greeter.calls.load(Ordering::SeqCst), 1);
+
+    // send the method to the other cluster
+    let routed = Config {
+        routes: vec![route(
+            PathMatch::Regex(r"/helloworld\.Greeter/SayHello".into()),
+            "greeter",
+        )],
+        ..config
+    };
+    std::fs::write(&config_path, serde_yaml::to_string(&routed).unwrap()).unwrap();
+
+    let mut rerouted = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // a new connection is served with the new routes
+        if say_hello(&proxy_address).await.is_ok()
+            && routed_greeter.calls.load(Ordering::SeqCst) > 0
+        {
+            rerouted = true;
+            break;
+        }
+    }
+    assert!(rerouted, "calls did not follow the reloaded routes");
+
+    reload_task.abort();
+    default_shutdown_tx.send(()).unwrap();
+    routed_shutdown_tx.send(()).unwrap();
+    default_task.await.unwrap();
+    routed_task.await.unwrap();
+    Ok(())
+}
+
+fn split(stable: u32, canary: u32) -> Vec<RouteConfig> {
+    let weighted = |name: &str, weight| WeightedCluster {
+        name: name.into(),
+        weight,
+    };
+    vec![RouteConfig {
+        target: RouteTarget::WeightedClusters(vec![
+            weighted("split-stable", stable),
+            weighted("split-canary", canary),
+        ]),
+        ..route(PathMatch::Prefix("/helloworld.".into()), "")
+    }]
+}
+
+fn ok_calls(cluster: &str) -> f64 {
+    Metrics::new()
+        .upstream_rpcs()
+        .with_label_values(&[cluster, "OK"])
+        .get()
+}
+
+#[tokio::test]
+async fn test_weights_reload_in_place() -> Result<(), BoxError> {
+    let stable_greeter = MyGreeter::default();
+    let canary_greeter = MyGreeter::default();
+    let (stable_address, stable_shutdown_tx, stable_task) =
+        start_greeter_with(stable_greeter.clone()).await;
+    let (canary_address, canary_shutdown_tx, canary_task) =
+        start_greeter_with(canary_greeter.clone()).await;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        listen_port: port,
+        upstream: Some(cluster("split-stable", stable_address)),
+        clusters: vec![cluster("split-canary", canary_address)],
+        routes: split(100, 0),
+        ..Default::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("config.yaml");
+    std::fs::write(&config_path, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+    let mut controller = ConfigController::new(config.clone());
+    controller.watch_file(config_path.clone()).unwrap();
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await.unwrap();
+    let store = controller.store.clone();
+    let reload_task = tokio::spawn(async move {
+        while controller.reload.rx.recv().await.is_some() {
+            let config = store.get().as_ref().clone();
+            let _ = supervisor.load_listener(config).await;
+        }
+    });
+
+    // one connection for the whole test
+    let mut client = GreeterClient::connect(format!("http://{}", config.listen_address())).await?;
+    let hello = || HelloRequest {
+        name: "Alice".into(),
+    };
+    for _ in 0..5 {
+        client.say_hello(hello()).await?;
+    }
+    assert_eq!(stable_greeter.calls.load(Ordering::SeqCst), 5);
+    assert_eq!(ok_calls("split-stable"), 5.0);
+
+    // all traffic to the canary
+    let shifted = Config {
+        routes: split(0, 100),
+        ..config
+    };
+    std::fs::write(&config_path, serde_yaml::to_string(&shifted).unwrap()).unwrap();
+
+    let mut shifted = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // the connection opened before the reload sees the new weights
+        client.say_hello(hello()).await?;
+        if canary_greeter.calls.load(Ordering::SeqCst) > 0 {
+            shifted = true;
+            break;
+        }
+    }
+    assert!(shifted, "calls did not follow the reloaded weights");
+    assert_eq!(ok_calls("split-canary"), 1.0);
+
+    reload_task.abort();
+    stable_shutdown_tx.send(()).unwrap();
+    canary_shutdown_tx.send(()).unwrap();
+    stable_task.await.unwrap();
+    canary_task.await.unwrap();
+    Ok(())
+}
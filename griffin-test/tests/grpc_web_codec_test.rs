@@ -0,0 +1,120 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::upstream::cluster::ClusterConfig;
+use http::{HeaderMap, HeaderValue, Request, Response, header::CONTENT_TYPE};
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::service::service_fn;
+use hyper_util::{
+    client::legacy::Client,
+    rt::{TokioExecutor, TokioIo},
+};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use griffin_test::test_support::{preparation::start_proxy_with, utils::split_web_body};
+use tower::BoxError;
+
+/// h2 upstream echoing the request messages,
+/// the content type it received is sent back
+/// in the response and in `x-received-content-type`
+async fn spawn_echo_upstream() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let svc = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                let content_type = req.headers()[CONTENT_TYPE].clone();
+                let messages = req.into_body().collect().await.unwrap().to_bytes();
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                let frames = futures_util::stream::iter([
+                    Ok::<_, Infallible>(Frame::data(messages)),
+                    Ok(Frame::trailers(trailers)),
+                ]);
+                let res = Response::builder()
+                    .header(CONTENT_TYPE, content_type.clone())
+                    .header("x-received-content-type", content_type)
+                    .body(StreamBody::new(frames))
+                    .unwrap();
+                Ok::<_, Infallible>(res)
+            });
+            tokio::spawn(
+                hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), svc),
+            );
+        }
+    });
+    address
+}
+
+async fn start(upstream_address: String) -> (String, watch::Sender<bool>) {
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![upstream_address],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    start_proxy_with(config).await
+}
+
+/// a JSON message in gRPC framing
+fn json_frame() -> Bytes {
+    let message = br#"{"name":"Alice"}"#;
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    Bytes::from(frame)
+}
+
+#[tokio::test]
+async fn test_codec_suffix_is_carried_through() -> Result<(), BoxError> {
+    let upstream_address = spawn_echo_upstream().await;
+    let (proxy_address, proxy_shutdown_tx) = start(upstream_address).await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let req = Request::post(format!("http://{}/echo.Echo/Echo", proxy_address))
+        .header(CONTENT_TYPE, "application/grpc-web+json; charset=utf-8")
+        .body(Full::new(json_frame()))?;
+    let res = client.request(req).await?;
+
+    assert_eq!(
+        res.headers()["x-received-content-type"],
+        "application/grpc+json"
+    );
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/grpc-web+json");
+    let body = res.into_body().collect().await?.to_bytes();
+    let (messages, trailers) = split_web_body(body);
+    assert_eq!(messages, json_frame());
+    assert!(trailers.unwrap().contains("grpc-status:0"));
+
+    proxy_shutdown_tx.send(true).unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_web_text_custom_codec() -> Result<(), BoxError> {
+    let upstream_address = spawn_echo_upstream().await;
+    let (proxy_address, proxy_shutdown_tx) = start(upstream_address).await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+    let req = Request::post(format!("http://{}/echo.Echo/Echo", proxy_address))
+        .header(CONTENT_TYPE, "application/grpc-web-text+thrift")
+        .body(Full::new(Bytes::new()))?;
+    let res = client.request(req).await?;
+
+    assert_eq!(
+        res.headers()["x-received-content-type"],
+        "application/grpc+thrift"
+    );
+    assert_eq!(
+        res.headers()[CONTENT_TYPE],
+        "application/grpc-web-text+thrift"
+    );
+
+    proxy_shutdown_tx.send(true).unwrap();
+    Ok(())
+}
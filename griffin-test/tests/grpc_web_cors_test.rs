@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::cors::{CorsConfig, OriginConfig};
+use http::{Method, Request, StatusCode};
+use http_body_util::{Empty, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+use griffin_test::test_support::{
+    greeter::hello_world::HelloRequest, preparation::run_intergration_with_config,
+    utils::message_to_frame,
+};
+
+use tower::BoxError;
+
+fn cors_config() -> Config {
+    Config {
+        cors: Some(CorsConfig {
+            allow_origins: vec![
+                OriginConfig::Exact("https://app.example.com".into()),
+                OriginConfig::Wildcard("https://*.preview.example.com".into()),
+            ],
+            allow_headers: vec!["authorization".into()],
+            expose_headers: vec!["x-request-id".into()],
+            allow_credentials: true,
+            max_age: Some(600),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_cors_preflight_is_answered_by_proxy() -> Result<(), BoxError> {
+    run_intergration_with_config(cors_config(), async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(url)
+            .header("origin", "https://pr-7.preview.example.com")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type,x-grpc-web")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let headers = res.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://pr-7.preview.example.com"
+        );
+        assert_eq!(headers["access-control-allow-credentials"], "true");
+        assert_eq!(headers["access-control-allow-methods"], "POST");
+        assert_eq!(headers["access-control-max-age"], "600");
+        let allow_headers = headers["access-control-allow-headers"].to_str().unwrap();
+        assert!(allow_headers.contains("x-grpc-web"));
+        assert!(allow_headers.contains("authorization"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_cors_preflight_from_unknown_origin() -> Result<(), BoxError> {
+    run_intergration_with_config(cors_config(), async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(url)
+            .header("origin", "https://evil.example.net")
+            .header("access-control-request-method", "POST")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!res.headers().contains_key("access-control-allow-origin"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_cors_headers_on_grpc_web_response() -> Result<(), BoxError> {
+    run_intergration_with_config(cors_config(), async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req_msg = HelloRequest {
+            name: "Alice".to_string(),
+        };
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web+proto")
+            .header("origin", "https://app.example.com")
+            .body(Full::<Bytes>::from(message_to_frame(&req_msg).freeze()))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+        let res = client.request(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+        let expose = headers["access-control-expose-headers"].to_str().unwrap();
+        assert!(expose.contains("grpc-status"));
+        assert!(expose.contains("grpc-message"));
+        assert!(expose.contains("x-request-id"));
+        // metadata set by the greeter
+        assert!(expose.contains("custom-header"));
+
+        Ok(())
+    })
+    .await
+}
@@ -38,7 +38,7 @@ async fn test_grpc_web_server_streaming_call() -> Result<(), BoxError> {
                 Ok(msg) => {
                     replies.push(msg.message.clone());
                 }
-                Err(e) => {
+                Err(_) => {
                     break;
                 }
             }
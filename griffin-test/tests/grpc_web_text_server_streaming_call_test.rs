@@ -0,0 +1,57 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures_util::stream;
+use http::Request;
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest},
+    preparation::run_intergration,
+    utils::{collect_messages, decode_web_text, message_to_frame, split_web_body},
+};
+
+use tower::BoxError;
+
+#[tokio::test]
+async fn test_grpc_web_text_server_streaming_call() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHelloStream", proxy_address);
+        let req_msg = HelloRequest {
+            name: "Tonic".to_string(),
+        };
+        let encoded = STANDARD.encode(message_to_frame(&req_msg));
+
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web-text")
+            .body(Full::<Bytes>::from(encoded))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let (messages, trailers) = split_web_body(decode_web_text(&body));
+
+        let body = StreamBody::new(stream::iter([Ok(Frame::data(messages))]));
+        let replies: Vec<HelloReply> = collect_messages(body).await.unwrap();
+        let replies = replies
+            .into_iter()
+            .map(|reply| reply.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            replies,
+            vec!["first ok".to_string(), "second ok".to_string()]
+        );
+
+        let trailers = trailers.unwrap();
+        assert!(trailers.contains("grpc-status:0"));
+        assert!(trailers.contains("x-reason:server-stream-error"));
+
+        Ok(())
+    })
+    .await
+}
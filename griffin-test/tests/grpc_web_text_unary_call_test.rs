@@ -0,0 +1,117 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures_util::stream;
+use http::Request;
+use http_body::Frame;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest},
+    preparation::run_intergration,
+    utils::{collect_messages, decode_web_text, message_to_frame, split_web_body},
+};
+
+use tower::BoxError;
+
+#[tokio::test]
+async fn test_grpc_web_text_unary_call() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req_msg = HelloRequest {
+            name: "Alice".to_string(),
+        };
+        let encoded = STANDARD.encode(message_to_frame(&req_msg));
+
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web-text")
+            .header("accept", "application/grpc-web-text")
+            .body(Full::<Bytes>::from(encoded))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers()["content-type"],
+            "application/grpc-web-text+proto"
+        );
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let (messages, trailers) = split_web_body(decode_web_text(&body));
+
+        let body = StreamBody::new(stream::iter([Ok(Frame::data(messages))]));
+        let messages: Vec<HelloReply> = collect_messages(body).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages.first().unwrap().message, "Hello Alice!");
+        assert!(trailers.unwrap().contains("grpc-status:0"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_web_text_request_split_across_chunks() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req_msg = HelloRequest {
+            name: "Bob".to_string(),
+        };
+        let encoded = Bytes::from(STANDARD.encode(message_to_frame(&req_msg)));
+
+        // split the base64 text in the middle of a quantum
+        let chunks = [encoded.slice(..7), encoded.slice(7..)]
+            .into_iter()
+            .map(|chunk| Ok::<_, BoxError>(Frame::data(chunk)));
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web-text+proto")
+            .body(StreamBody::new(stream::iter(chunks)))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let (messages, _) = split_web_body(decode_web_text(&body));
+
+        let body = StreamBody::new(stream::iter([Ok(Frame::data(messages))]));
+        let messages: Vec<HelloReply> = collect_messages(body).await.unwrap();
+        assert_eq!(messages.first().unwrap().message, "Hello Bob!");
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_web_text_unary_call_trailers_only() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req_msg = HelloRequest {
+            name: "fail fast".to_string(),
+        };
+        let encoded = STANDARD.encode(message_to_frame(&req_msg));
+
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web-text")
+            .body(Full::<Bytes>::from(encoded))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let (messages, trailers) = split_web_body(decode_web_text(&body));
+        assert!(messages.is_empty());
+        assert!(trailers.unwrap().contains("grpc-status:3"));
+
+        Ok(())
+    })
+    .await
+}
@@ -1,32 +1,17 @@
-use async_stream::try_stream;
 use bytes::Bytes;
-use griffin_core::core::stream_response::StreamResponse;
 use http::Request;
 use http_body_util::Full;
-use http_body_util::{BodyExt, StreamBody};
-use hyper::body::Incoming;
+use http_body_util::{BodyExt, BodyStream, StreamBody};
 use hyper_util::{client::legacy::Client, rt::TokioExecutor};
 
 use griffin_test::test_support::{
     greeter::hello_world::{HelloReply, HelloRequest},
     preparation::run_intergration,
-    utils::{collect_messages, message_to_frame},
+    utils::{collect_messages, message_to_frame, split_web_body},
 };
 
-use http::Response;
 use tower::BoxError;
 
-fn incoming_to_stream_body(res: Response<Incoming>) -> StreamResponse {
-    let forward_stream = try_stream! {
-            let mut incoming = res.into_body();
-        while let Some(frame) = incoming.frame().await {
-            let frame = frame?;
-            yield frame;
-        }
-    };
-    StreamResponse::new(StreamBody::new(Box::pin(forward_stream)))
-}
-
 #[tokio::test]
 async fn test_grpc_web_unary_call() -> Result<(), BoxError> {
     run_intergration(async move |proxy_address| {
@@ -44,10 +29,8 @@ async fn test_grpc_web_unary_call() -> Result<(), BoxError> {
 
         let res = client.request(req).await.unwrap();
 
-        // let res = res.map(|body| incoming_to_stream_body(body));
-        let res = incoming_to_stream_body(res);
         assert_eq!(res.status(), 200);
-        let body = res.into_body();
+        let body = StreamBody::new(BodyStream::new(res.into_body()));
         let messages: Vec<HelloReply> = collect_messages(body).await.unwrap();
         assert_eq!(messages.len(), 1);
         assert_eq!(messages.first().unwrap().message, "Hello Alice!");
@@ -56,3 +39,37 @@ async fn test_grpc_web_unary_call() -> Result<(), BoxError> {
     })
     .await
 }
+
+#[tokio::test]
+async fn test_grpc_web_unary_call_trailers_only() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let url = format!("http://{}/helloworld.Greeter/SayHello", proxy_address);
+        let req_msg = HelloRequest {
+            name: "fail fast".to_string(),
+        };
+
+        let req = Request::post(url)
+            .header("content-type", "application/grpc-web")
+            .body(Full::<Bytes>::from(message_to_frame(&req_msg).freeze()))
+            .unwrap();
+
+        let client = Client::builder(TokioExecutor::new()).build_http();
+
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+        // status is mirrored in the headers
+        assert_eq!(res.headers()["grpc-status"], "3");
+
+        // and sent as a trailer frame in the body
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let (messages, trailers) = split_web_body(body);
+        assert!(messages.is_empty());
+        let trailers = trailers.unwrap();
+        assert!(trailers.contains("grpc-status:3"));
+        assert!(trailers.contains("grpc-message:name%20is%20not%20allowed"));
+        assert!(!trailers.contains("content-type"));
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use griffin::config::config::Config;
+use griffin_core::cors::{CorsConfig, OriginConfig};
+use http::{Method, Request, StatusCode};
+use http_body_util::Empty;
+use hyper::{client::conn::http2, ext::Protocol, upgrade::Upgraded};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use prost::Message as _;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{Error, Message, client::IntoClientRequest, protocol::Role};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest},
+    preparation::{run_intergration, run_intergration_with_config},
+    utils::{message_to_frame, split_web_body},
+};
+
+use tower::BoxError;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// the HTTP/1.1 upgrade of a grpc-websockets call
+async fn connect(proxy_address: &str, method: &str, origin: Option<&str>) -> Result<Socket, Error> {
+    let url = format!("ws://{}/helloworld.Greeter/{}", proxy_address, method);
+    let mut req = url.into_client_request().unwrap();
+    req.headers_mut()
+        .insert("sec-websocket-protocol", "grpc-websockets".parse().unwrap());
+    if let Some(origin) = origin {
+        req.headers_mut().insert("origin", origin.parse().unwrap());
+    }
+    let (socket, res) = connect_async(req).await?;
+    assert_eq!(res.headers()["sec-websocket-protocol"], "grpc-websockets");
+    Ok(socket)
+}
+
+// open a grpc-websockets call and send its metadata
+async fn open_call(proxy_address: &str, method: &str) -> Socket {
+    let mut socket = connect(proxy_address, method, None).await.unwrap();
+    send_metadata(&mut socket).await;
+    socket
+}
+
+// open a grpc-websockets call over an HTTP/2 extended CONNECT
+async fn open_h2_call(proxy_address: &str, method: &str) -> WebSocketStream<TokioIo<Upgraded>> {
+    let stream = TcpStream::connect(proxy_address).await.unwrap();
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake::<_, Empty<Bytes>>(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+
+    let url = format!("http://{}/helloworld.Greeter/{}", proxy_address, method);
+    let connect = || {
+        Request::builder()
+            .method(Method::CONNECT)
+            .uri(&url)
+            .extension(Protocol::from_static("websocket"))
+            .header("sec-websocket-protocol", "grpc-websockets")
+            .header("sec-websocket-version", "13")
+            .body(Empty::new())
+            .unwrap()
+    };
+    // extended CONNECT waits for the server SETTINGS enabling it
+    let mut res = None;
+    for _ in 0..50 {
+        if let Ok(response) = sender.send_request(connect()).await {
+            res = Some(response);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut res = res.expect("extended CONNECT accepted");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["sec-websocket-protocol"], "grpc-websockets");
+
+    let upgraded = hyper::upgrade::on(&mut res).await.unwrap();
+    let mut socket =
+        WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Client, None).await;
+    send_metadata(&mut socket).await;
+    socket
+}
+
+async fn send_metadata<S>(socket: &mut WebSocketStream<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let headers = "content-type: application/grpc-web+proto\r\nx-grpc-web: 1\r\n";
+    socket
+        .send(Message::Binary(Bytes::from_static(headers.as_bytes())))
+        .await
+        .unwrap();
+}
+
+async fn send_request<S>(socket: &mut WebSocketStream<S>, name: &str)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let req_msg = HelloRequest {
+        name: name.to_string(),
+    };
+    let mut message = BytesMut::new();
+    message.put_u8(0);
+    message.extend_from_slice(&message_to_frame(&req_msg));
+    socket
+        .send(Message::Binary(message.freeze()))
+        .await
+        .unwrap();
+}
+
+async fn finish_send<S>(socket: &mut WebSocketStream<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    socket
+        .send(Message::Binary(Bytes::from_static(&[1])))
+        .await
+        .unwrap();
+}
+
+// the next binary message, `None` once the call is closed
+async fn next_frames<S>(socket: &mut WebSocketStream<S>) -> Option<Bytes>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(message) = socket.next().await {
+        match message.unwrap() {
+            Message::Binary(data) => return Some(data),
+            Message::Close(_) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn decode_reply(frames: Bytes) -> HelloReply {
+    let (messages, trailers) = split_web_body(frames);
+    assert!(trailers.is_none());
+    HelloReply::decode(&messages[5..]).unwrap()
+}
+
+#[tokio::test]
+async fn test_grpc_websocket_bidi_stream() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let mut socket = open_call(&proxy_address.to_string(), "SayHelloBiStream").await;
+
+        // replies arrive while the client is still sending
+        send_request(&mut socket, "client request 1").await;
+        let (_, headers) = split_web_body(next_frames(&mut socket).await.unwrap());
+        assert!(
+            headers
+                .unwrap()
+                .contains("content-type:application/grpc-web+proto")
+        );
+        let reply = decode_reply(next_frames(&mut socket).await.unwrap());
+        assert_eq!(reply.message, "first ok");
+
+        send_request(&mut socket, "client request 2").await;
+        let reply = decode_reply(next_frames(&mut socket).await.unwrap());
+        assert_eq!(reply.message, "second ok");
+
+        // the call ends once the client finished sending
+        finish_send(&mut socket).await;
+        let mut rest = BytesMut::new();
+        while let Some(frames) = next_frames(&mut socket).await {
+            rest.extend_from_slice(&frames);
+        }
+        let (messages, trailers) = split_web_body(rest.freeze());
+        assert!(messages.is_empty());
+        assert!(trailers.unwrap().contains("grpc-status:0"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_websocket_unary_trailers_only() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let mut socket = open_call(&proxy_address.to_string(), "SayHello").await;
+        send_request(&mut socket, "fail fast").await;
+        finish_send(&mut socket).await;
+
+        // the status is in the headers and the trailer frame
+        let (_, headers) = split_web_body(next_frames(&mut socket).await.unwrap());
+        assert!(headers.unwrap().contains("grpc-status:3"));
+        let mut rest = BytesMut::new();
+        while let Some(frames) = next_frames(&mut socket).await {
+            rest.extend_from_slice(&frames);
+        }
+        let (messages, trailers) = split_web_body(rest.freeze());
+        assert!(messages.is_empty());
+        let trailers = trailers.unwrap();
+        assert!(trailers.contains("grpc-status:3"));
+        assert!(trailers.contains("grpc-message:name%20is%20not%20allowed"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_websocket_over_http2_extended_connect() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let mut socket = open_h2_call(&proxy_address, "SayHelloBiStream").await;
+
+        send_request(&mut socket, "client request 1").await;
+        let (_, headers) = split_web_body(next_frames(&mut socket).await.unwrap());
+        assert!(
+            headers
+                .unwrap()
+                .contains("content-type:application/grpc-web+proto")
+        );
+        let reply = decode_reply(next_frames(&mut socket).await.unwrap());
+        assert_eq!(reply.message, "first ok");
+
+        finish_send(&mut socket).await;
+        let mut rest = BytesMut::new();
+        while let Some(frames) = next_frames(&mut socket).await {
+            rest.extend_from_slice(&frames);
+        }
+        let (_, trailers) = split_web_body(rest.freeze());
+        assert!(trailers.unwrap().contains("grpc-status:0"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_grpc_websocket_checks_origin() -> Result<(), BoxError> {
+    let config = Config {
+        cors: Some(CorsConfig {
+            allow_origins: vec![OriginConfig::Exact("https://app.example.com".into())],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    run_intergration_with_config(config, async move |proxy_address| {
+        // another site cannot open a call with the cookies of the user
+        let refused = connect(&proxy_address, "SayHello", Some("https://evil.example.com")).await;
+        match refused {
+            Err(Error::Http(res)) => assert_eq!(res.status(), StatusCode::FORBIDDEN),
+            other => panic!("upgrade not refused: {:?}", other.map(|_| ())),
+        }
+
+        let mut socket = connect(&proxy_address, "SayHello", Some("https://app.example.com"))
+            .await
+            .unwrap();
+        send_metadata(&mut socket).await;
+        send_request(&mut socket, "Alice").await;
+        finish_send(&mut socket).await;
+        next_frames(&mut socket).await.unwrap();
+        let reply = decode_reply(next_frames(&mut socket).await.unwrap());
+        assert_eq!(reply.message, "Hello Alice!");
+
+        Ok(())
+    })
+    .await
+}
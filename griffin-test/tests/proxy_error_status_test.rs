@@ -0,0 +1,120 @@
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::upstream::cluster::ClusterConfig;
+use http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+use griffin_test::test_support::{
+    greeter::hello_world::HelloRequest,
+    preparation::{run_intergration, run_intergration_with_config},
+    utils::{decode_web_text, message_to_frame, split_web_body},
+};
+use tower::BoxError;
+
+/// an upstream nobody listens on
+fn refused_config() -> Config {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![format!("127.0.0.1:{}", port)],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn hello(proxy_address: &str, content_type: &str, body: Bytes) -> Request<Full<Bytes>> {
+    Request::post(format!(
+        "http://{}/helloworld.Greeter/SayHello",
+        proxy_address
+    ))
+    .header("content-type", content_type)
+    .body(Full::from(body))
+    .unwrap()
+}
+
+fn hello_frame() -> Bytes {
+    message_to_frame(&HelloRequest {
+        name: "Alice".into(),
+    })
+    .freeze()
+}
+
+#[tokio::test]
+async fn test_connect_refused_is_unavailable_for_web_clients() -> Result<(), BoxError> {
+    run_intergration_with_config(refused_config(), async move |proxy_address| {
+        let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+        let res = client
+            .request(hello(
+                &proxy_address,
+                "application/grpc-web+proto",
+                hello_frame(),
+            ))
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let (messages, trailers) = split_web_body(body);
+        assert!(messages.is_empty());
+        assert!(trailers.unwrap().contains("grpc-status:14"));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_connect_refused_is_unavailable_for_web_text_clients() -> Result<(), BoxError> {
+    run_intergration_with_config(refused_config(), async move |proxy_address| {
+        let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+        let encoded =
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, hello_frame());
+        let res = client
+            .request(hello(
+                &proxy_address,
+                "application/grpc-web-text",
+                Bytes::from(encoded),
+            ))
+            .await?;
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let (_, trailers) = split_web_body(decode_web_text(&body));
+        assert!(trailers.unwrap().contains("grpc-status:14"));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_connect_refused_is_unavailable_for_grpc_clients() -> Result<(), BoxError> {
+    run_intergration_with_config(refused_config(), async move |proxy_address| {
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http::<Full<Bytes>>();
+        let res = client
+            .request(hello(&proxy_address, "application/grpc", hello_frame()))
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["grpc-status"], "14");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_unsupported_content_type_is_415() -> Result<(), BoxError> {
+    run_intergration(async move |proxy_address| {
+        let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+        let res = client
+            .request(hello(&proxy_address, "application/xml", hello_frame()))
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(res.headers()["grpc-status"], "12");
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use griffin::{
+    config::config::Config, connection::proxy_connection_handler::ProxyConnectionHandler,
+    proxy::proxy_supervisor::ProxySupervisor,
+};
+use griffin_core::deadline::DeadlineConfig;
+use griffin_core::readiness::ReadinessConfig;
+use griffin_core::upstream::{cluster::ClusterConfig, health_check::HealthCheckConfig};
+use http::{Request, StatusCode};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+use griffin_test::test_support::{
+    greeter::{MyGreeter, hello_world::HelloRequest},
+    preparation::{start_greeter_with_health, start_proxy_with},
+    utils::{message_to_frame, split_web_body},
+};
+use tower::BoxError;
+
+fn config(endpoint: String, readiness: ReadinessConfig) -> Config {
+    Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![endpoint],
+            health_check: Some(HealthCheckConfig {
+                interval_ms: 50,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        readiness: Some(readiness),
+        ..Default::default()
+    }
+}
+
+async fn readyz(address: &str) -> Result<StatusCode, BoxError> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+    let res = client
+        .request(Request::get(format!("http://{}/readyz", address)).body(Empty::new())?)
+        .await?;
+    Ok(res.status())
+}
+
+/// unary gRPC-Web call, returns the status
+/// and the length of the message part
+async fn say_hello(address: &str) -> Result<(String, usize), BoxError> {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+    let req = Request::post(format!("http://{}/helloworld.Greeter/SayHello", address))
+        .header("content-type", "application/grpc-web+proto")
+        .body(Full::from(
+            message_to_frame(&HelloRequest {
+                name: "Alice".into(),
+            })
+            .freeze(),
+        ))?;
+    let res = client.request(req).await?;
+    if let Some(status) = res.headers().get("grpc-status") {
+        // Trailers-Only
+        return Ok((format!("grpc-status:{}", status.to_str()?), 0));
+    }
+    let body = res.into_body().collect().await?.to_bytes();
+    let (messages, trailers) = split_web_body(body);
+    Ok((trailers.unwrap_or_default(), messages.len()))
+}
+
+#[tokio::test]
+async fn test_calls_wait_for_upstream_cold_start() -> Result<(), BoxError> {
+    let (backend_address, reporter, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(MyGreeter::default()).await;
+    // upstream is still starting
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(
+        backend_address.to_string(),
+        ReadinessConfig {
+            max_waiting: 10,
+            wait_timeout_ms: 5000,
+        },
+    ))
+    .await;
+    assert_eq!(
+        readyz(&proxy_address).await?,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let call = tokio::spawn({
+        let proxy_address = proxy_address.clone();
+        async move { say_hello(&proxy_address).await.unwrap() }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!call.is_finished(), "call did not wait for the upstream");
+
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::Serving)
+        .await;
+    let (trailers, message_len) = call.await?;
+    assert!(trailers.contains("grpc-status:0"), "{}", trailers);
+    assert!(message_len > 0);
+    assert_eq!(readyz(&proxy_address).await?, StatusCode::OK);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_waiting_call_times_out_with_unavailable() -> Result<(), BoxError> {
+    let (backend_address, reporter, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(MyGreeter::default()).await;
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config(
+        backend_address.to_string(),
+        ReadinessConfig {
+            max_waiting: 10,
+            wait_timeout_ms: 100,
+        },
+    ))
+    .await;
+    let (trailers, message_len) = say_hello(&proxy_address).await?;
+    assert!(trailers.contains("grpc-status:14"), "{}", trailers);
+    assert_eq!(message_len, 0);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reload_keeps_the_upstream_ready() -> Result<(), BoxError> {
+    let (backend_address, reporter, backend_shutdown_tx, backend_task) =
+        start_greeter_with_health(MyGreeter::default()).await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let config = Config {
+        listen_port: port,
+        ..config(backend_address.to_string(), ReadinessConfig::default())
+    };
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await?;
+    let proxy_address = config.listen_address();
+
+    let mut ready = false;
+    for _ in 0..50 {
+        if readyz(&proxy_address).await? == StatusCode::OK {
+            ready = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(ready, "upstream never became ready");
+
+    // probes after the reload would keep the gate closed
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::NotServing)
+        .await;
+    let reloaded = Config {
+        deadline: DeadlineConfig {
+            default_timeout_ms: Some(5_000),
+            ..Default::default()
+        },
+        ..config
+    };
+    supervisor.load_listener(reloaded).await?;
+    assert_eq!(readyz(&proxy_address).await?, StatusCode::OK);
+
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
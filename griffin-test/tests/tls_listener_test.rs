@@ -0,0 +1,156 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use griffin::{
+    config::{config::Config, controller::ConfigController},
+    connection::proxy_connection_handler::ProxyConnectionHandler,
+    proxy::proxy_supervisor::ProxySupervisor,
+    tls::tls_config::TlsConfig,
+};
+use http::Request;
+use http_body_util::{BodyStream, Full, StreamBody};
+use hyper::client::conn::http2;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::{CertificateDer, ServerName, pem::PemObject};
+use rustls::{ClientConfig, RootCertStore};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsConnector, client::TlsStream};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloReply, HelloRequest},
+    preparation::start_greeter,
+    utils::{collect_messages, message_to_frame},
+};
+use tower::BoxError;
+
+const CERTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test_support/certs");
+
+fn cert_der(path: &Path) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(path).unwrap()
+}
+
+async fn connect(address: &str) -> std::io::Result<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(cert_der(&Path::new(CERTS).join("ca.pem")))
+        .unwrap();
+    let mut config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    let stream = TcpStream::connect(address).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+}
+
+fn peer_certificate(stream: &TlsStream<TcpStream>) -> CertificateDer<'static> {
+    stream.get_ref().1.peer_certificates().unwrap()[0].clone()
+}
+
+async fn say_hello(
+    sender: &mut http2::SendRequest<Full<Bytes>>,
+    address: &str,
+    name: &str,
+) -> String {
+    let req = Request::post(format!("https://{}/helloworld.Greeter/SayHello", address))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Full::from(
+            message_to_frame(&HelloRequest { name: name.into() }).freeze(),
+        ))
+        .unwrap();
+    let res = sender.send_request(req).await.unwrap();
+    let body = StreamBody::new(BodyStream::new(res.into_body()));
+    let messages: Vec<HelloReply> = collect_messages(body).await.unwrap();
+    messages.first().unwrap().message.clone()
+}
+
+#[tokio::test]
+async fn test_tls_listener_reloads_certificate() -> Result<(), BoxError> {
+    let (backend_address, backend_shutdown_tx, backend_task) = start_greeter().await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cert_path = dir.path().join("cert.pem");
+    let key_path = dir.path().join("key.pem");
+    std::fs::copy(Path::new(CERTS).join("server_a.pem"), &cert_path).unwrap();
+    std::fs::copy(Path::new(CERTS).join("server_a.key"), &key_path).unwrap();
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let config = Config {
+        listen_port: port,
+        target_host: backend_address.ip().to_string(),
+        target_port: backend_address.port(),
+        tls: Some(TlsConfig {
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+        }),
+        ..Default::default()
+    };
+    let config_path = dir.path().join("config.yaml");
+    std::fs::write(&config_path, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+    let mut controller = ConfigController::new(config.clone());
+    controller.watch_file(config_path).unwrap();
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await.unwrap();
+
+    let store = controller.store.clone();
+    let reload_task = tokio::spawn(async move {
+        while controller.reload.rx.recv().await.is_some() {
+            let config = store.get().as_ref().clone();
+            let _ = supervisor.load_listener(config).await;
+        }
+    });
+
+    // h2 is negotiated with ALPN
+    let address = config.listen_address();
+    let stream = connect(&address).await.unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(
+        peer_certificate(&stream),
+        cert_der(&Path::new(CERTS).join("server_a.pem"))
+    );
+
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    assert_eq!(
+        say_hello(&mut sender, &address, "Alice").await,
+        "Hello Alice!"
+    );
+
+    // rotate certificate on disk
+    std::fs::copy(Path::new(CERTS).join("server_b.key"), &key_path).unwrap();
+    std::fs::copy(Path::new(CERTS).join("server_b.pem"), &cert_path).unwrap();
+
+    let cert_b = cert_der(&Path::new(CERTS).join("server_b.pem"));
+    let mut rotated = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if let Ok(stream) = connect(&address).await
+            && peer_certificate(&stream) == cert_b
+        {
+            rotated = true;
+            break;
+        }
+    }
+    assert!(rotated, "new connections did not get the new certificate");
+
+    // connection opened before the rotation is still alive
+    assert_eq!(say_hello(&mut sender, &address, "Bob").await, "Hello Bob!");
+
+    reload_task.abort();
+    backend_shutdown_tx.send(()).unwrap();
+    backend_task.await.unwrap();
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use griffin::{
+    config::config::Config, connection::proxy_connection_handler::ProxyConnectionHandler,
+    proxy::proxy_supervisor::ProxySupervisor,
+};
+use griffin_core::deadline::DeadlineConfig;
+use griffin_core::upstream::cluster::ClusterConfig;
+use tokio::net::{TcpListener, TcpStream};
+
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloRequest, greeter_client::GreeterClient},
+    preparation::start_greeter,
+};
+use tower::BoxError;
+
+/// forwards to `upstream` and counts
+/// the connections the proxy opens
+async fn counting_forwarder(upstream: SocketAddr) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        while let Ok((mut downstream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let mut upstream = TcpStream::connect(upstream).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await;
+            });
+        }
+    });
+    (address, accepted)
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn say_hello(proxy_address: &str) -> Result<(), BoxError> {
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    client
+        .say_hello(HelloRequest {
+            name: "Alice".into(),
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upstream_connections_survive_reload() -> Result<(), BoxError> {
+    let (greeter_address, greeter_shutdown_tx, greeter_task) = start_greeter().await;
+    let (upstream_address, accepted) = counting_forwarder(greeter_address).await;
+
+    let config = Config {
+        listen_port: free_port(),
+        upstream: Some(ClusterConfig {
+            endpoints: vec![upstream_address.to_string()],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await?;
+    say_hello(&config.listen_address()).await?;
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    // a new listener on another port, same upstream
+    let reloaded = Config {
+        listen_port: free_port(),
+        deadline: DeadlineConfig {
+            default_timeout_ms: Some(5_000),
+            ..Default::default()
+        },
+        ..config
+    };
+    supervisor.load_listener(reloaded.clone()).await?;
+    for _ in 0..3 {
+        say_hello(&reloaded.listen_address()).await?;
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+    greeter_shutdown_tx.send(()).unwrap();
+    greeter_task.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zero_streams_per_connection_is_refused() {
+    let config = Config {
+        upstream_max_concurrent_streams: 0,
+        ..Default::default()
+    };
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    assert!(supervisor.load_listener(config).await.is_err());
+}
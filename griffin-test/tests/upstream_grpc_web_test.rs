@@ -0,0 +1,67 @@
+use std::sync::atomic::Ordering;
+
+use griffin::config::config::Config;
+use griffin_core::upstream::cluster::{ClusterConfig, UpstreamProtocol};
+use griffin_test::test_support::{
+    greeter::hello_world::{HelloRequest, greeter_client::GreeterClient},
+    preparation::{start_proxy_with, start_web_greeter},
+};
+use tonic::{Code, Request};
+use tower::BoxError;
+
+#[tokio::test]
+async fn test_native_grpc_to_grpc_web_upstream() -> Result<(), BoxError> {
+    let (address, greeter, task) = start_web_greeter().await;
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![address.to_string()],
+            protocol: UpstreamProtocol::GrpcWeb,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config).await;
+
+    let mut client = GreeterClient::connect(format!("http://{}", proxy_address)).await?;
+    let reply = client
+        .say_hello(Request::new(HelloRequest {
+            name: "Alice".into(),
+        }))
+        .await?;
+    assert_eq!(reply.get_ref().message, "Hello Alice!");
+    assert_eq!(
+        reply.metadata().get("custom-header").unwrap(),
+        "custom-value"
+    );
+
+    // the trailer frame of the upstream body
+    // arrives as HTTP/2 trailers
+    let mut stream = client
+        .say_hello_stream(Request::new(HelloRequest {
+            name: "Tonic".into(),
+        }))
+        .await?
+        .into_inner();
+    let mut replies = Vec::new();
+    while let Some(reply) = stream.message().await? {
+        replies.push(reply.message);
+    }
+    assert_eq!(replies, vec!["first ok", "second ok"]);
+    let trailers = stream.trailers().await?.unwrap();
+    assert_eq!(trailers.get("x-reason").unwrap(), "server-stream-error");
+
+    // Trailers-Only responses keep the status in the headers
+    let status = client
+        .say_hello(Request::new(HelloRequest {
+            name: "fail fast".into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "name is not allowed");
+    assert_eq!(greeter.calls.load(Ordering::SeqCst), 2);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    task.abort();
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bytes::Bytes;
+use griffin::{
+    config::config::Config, connection::proxy_connection_handler::ProxyConnectionHandler,
+    proxy::proxy_supervisor::ProxySupervisor,
+};
+use griffin_core::deadline::DeadlineConfig;
+use griffin_core::upstream::{cluster::ClusterConfig, health_check::HealthCheckConfig};
+use http::Request;
+use http_body_util::{BodyExt, BodyStream, Empty, Full, StreamBody};
+use hyper::client::conn::http2;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::TcpStream;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloReply, HelloRequest, greeter_server::GreeterServer},
+    },
+    preparation::{start_greeter_with_health, start_proxy_with},
+    utils::{collect_messages, message_to_frame},
+};
+use tower::BoxError;
+
+async fn say_hello(
+    sender: &mut http2::SendRequest<Full<Bytes>>,
+    address: &str,
+) -> Result<(), BoxError> {
+    let req = Request::post(format!("http://{}/helloworld.Greeter/SayHello", address))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Full::from(
+            message_to_frame(&HelloRequest {
+                name: "Alice".into(),
+            })
+            .freeze(),
+        ))?;
+    let res = sender.send_request(req).await?;
+    let body = StreamBody::new(BodyStream::new(res.into_body()));
+    let messages: Vec<HelloReply> = collect_messages(body).await?;
+    assert_eq!(messages[0].message, "Hello Alice!");
+    Ok(())
+}
+
+async fn metrics(address: &str) -> Result<String, BoxError> {
+    let stream = TcpStream::connect(address).await?;
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake::<_, Empty<Bytes>>(TokioIo::new(stream))
+        .await?;
+    tokio::spawn(conn);
+    let req = Request::get(format!("http://{}/metrics", address)).body(Empty::new())?;
+    let body = sender
+        .send_request(req)
+        .await?
+        .into_body()
+        .collect()
+        .await?;
+    Ok(String::from_utf8(body.to_bytes().to_vec())?)
+}
+
+#[tokio::test]
+async fn test_unhealthy_endpoint_gets_no_calls() -> Result<(), BoxError> {
+    let (greeter_a, greeter_b) = (MyGreeter::default(), MyGreeter::default());
+    let (address_a, _reporter_a, shutdown_a, task_a) =
+        start_greeter_with_health(greeter_a.clone()).await;
+    let (address_b, reporter_b, shutdown_b, task_b) =
+        start_greeter_with_health(greeter_b.clone()).await;
+    reporter_b
+        .set_not_serving::<GreeterServer<MyGreeter>>()
+        .await;
+
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            name: "greeter".into(),
+            endpoints: vec![address_a.to_string(), address_b.to_string()],
+            health_check: Some(HealthCheckConfig {
+                service: "helloworld.Greeter".into(),
+                interval_ms: 50,
+                timeout_ms: 500,
+                healthy_threshold: 1,
+                unhealthy_threshold: 1,
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config).await;
+
+    // wait for the first probes
+    let unhealthy = format!(
+        "upstream_endpoint_healthy{{cluster=\"greeter\",endpoint=\"{}\"}} 0",
+        address_b
+    );
+    let healthy = format!(
+        "upstream_endpoint_healthy{{cluster=\"greeter\",endpoint=\"{}\"}} 1",
+        address_a
+    );
+    let mut probed = false;
+    for _ in 0..50 {
+        let text = metrics(&proxy_address).await?;
+        if text.contains(&unhealthy) && text.contains(&healthy) {
+            probed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(probed, "health gauge was not exported");
+
+    let stream = TcpStream::connect(&proxy_address).await?;
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake::<_, Full<Bytes>>(TokioIo::new(stream))
+        .await?;
+    tokio::spawn(conn);
+    for _ in 0..4 {
+        say_hello(&mut sender, &proxy_address).await?;
+    }
+    assert_eq!(greeter_a.calls.load(Ordering::SeqCst), 4);
+    assert_eq!(greeter_b.calls.load(Ordering::SeqCst), 0);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    shutdown_a.send(()).unwrap();
+    shutdown_b.send(()).unwrap();
+    task_a.await.unwrap();
+    task_b.await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reload_keeps_the_health_gauge() -> Result<(), BoxError> {
+    let (address, _reporter, shutdown, task) =
+        start_greeter_with_health(MyGreeter::default()).await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let config = Config {
+        listen_port: port,
+        upstream: Some(ClusterConfig {
+            name: "reloaded".into(),
+            endpoints: vec![address.to_string()],
+            health_check: Some(HealthCheckConfig {
+                interval_ms: 200,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let supervisor = ProxySupervisor::new(ProxyConnectionHandler);
+    supervisor.load_listener(config.clone()).await?;
+    let proxy_address = config.listen_address();
+
+    let healthy = format!(
+        "upstream_endpoint_healthy{{cluster=\"reloaded\",endpoint=\"{}\"}} 1",
+        address
+    );
+    let mut probed = false;
+    for _ in 0..50 {
+        if metrics(&proxy_address).await?.contains(&healthy) {
+            probed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(probed, "health gauge was not exported");
+
+    // the same endpoint after the reload, the probes
+    // of the old endpoint stop within an interval
+    let reloaded = Config {
+        deadline: DeadlineConfig {
+            default_timeout_ms: Some(5_000),
+            ..Default::default()
+        },
+        ..config
+    };
+    supervisor.load_listener(reloaded).await?;
+    for _ in 0..30 {
+        assert!(
+            metrics(&proxy_address).await?.contains(&healthy),
+            "health gauge went missing after the reload"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    shutdown.send(()).unwrap();
+    task.await.unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_zero_probe_interval_is_rejected() {
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec!["127.0.0.1:3000".into()],
+            health_check: Some(HealthCheckConfig {
+                interval_ms: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let metrics = Default::default();
+    let pool = Default::default();
+    assert!(config.proxy_context(metrics, pool).is_err());
+}
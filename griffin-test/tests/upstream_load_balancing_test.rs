@@ -0,0 +1,71 @@
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use griffin::config::config::Config;
+use griffin_core::upstream::{cluster::ClusterConfig, load_balancer::LbPolicy};
+use http::Request;
+use http_body_util::{BodyStream, Full, StreamBody};
+use hyper::client::conn::http2;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::TcpStream;
+
+use griffin_test::test_support::{
+    greeter::{
+        MyGreeter,
+        hello_world::{HelloReply, HelloRequest},
+    },
+    preparation::{start_greeter_with, start_proxy_with},
+    utils::{collect_messages, message_to_frame},
+};
+use tower::BoxError;
+
+#[tokio::test]
+async fn test_round_robin_spreads_calls_of_one_connection() -> Result<(), BoxError> {
+    let (greeter_a, greeter_b) = (MyGreeter::default(), MyGreeter::default());
+    let (address_a, shutdown_a, task_a) = start_greeter_with(greeter_a.clone()).await;
+    let (address_b, shutdown_b, task_b) = start_greeter_with(greeter_b.clone()).await;
+
+    let config = Config {
+        upstream: Some(ClusterConfig {
+            endpoints: vec![address_a.to_string(), address_b.to_string()],
+            lb_policy: LbPolicy::RoundRobin,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (proxy_address, proxy_shutdown_tx) = start_proxy_with(config).await;
+
+    // a single downstream HTTP/2 connection
+    let stream = TcpStream::connect(&proxy_address).await?;
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake::<_, Full<Bytes>>(TokioIo::new(stream))
+        .await?;
+    tokio::spawn(conn);
+
+    for i in 0..4 {
+        let name = format!("caller {}", i);
+        let req = Request::post(format!(
+            "http://{}/helloworld.Greeter/SayHello",
+            proxy_address
+        ))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Full::from(
+            message_to_frame(&HelloRequest { name: name.clone() }).freeze(),
+        ))?;
+        let res = sender.send_request(req).await?;
+        let body = StreamBody::new(BodyStream::new(res.into_body()));
+        let messages: Vec<HelloReply> = collect_messages(body).await?;
+        assert_eq!(messages[0].message, format!("Hello {}!", name));
+    }
+
+    assert_eq!(greeter_a.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(greeter_b.calls.load(Ordering::SeqCst), 2);
+
+    proxy_shutdown_tx.send(true).unwrap();
+    shutdown_a.send(()).unwrap();
+    shutdown_b.send(()).unwrap();
+    task_a.await.unwrap();
+    task_b.await.unwrap();
+    Ok(())
+}
@@ -1,5 +1,20 @@
 use anyhow::Result;
+use griffin_core::compression::CompressionRule;
+use griffin_core::context::ProxyContext;
+use griffin_core::cors::{CorsConfig, CorsPolicy};
+use griffin_core::deadline::DeadlineConfig;
+use griffin_core::message_size::MessageSizeConfig;
+use griffin_core::readiness::{ReadinessConfig, ReadinessGate};
+use griffin_core::routing::{RouteConfig, RouteTarget, Router};
+use griffin_core::telemetry::metrics::Metrics;
+use griffin_core::transcoding::{Transcoder, TranscodingConfig};
+use griffin_core::upstream::cluster::{Cluster, ClusterConfig};
+use griffin_core::upstream::connection_pool::{ConnectionPool, DEFAULT_MAX_CONCURRENT_STREAMS};
 use std::path::Path;
+use std::sync::Arc;
+use tower::BoxError;
+
+use crate::tls::tls_config::TlsConfig;
 
 use serde::{Deserialize, Serialize};
 
@@ -13,6 +28,41 @@ pub struct Config {
     pub listen_port: u16,
     pub target_host: String,
     pub target_port: u16,
+    /// replicas and load balancing policy,
+    /// replaces `target_host`/`target_port` when set
+    pub upstream: Option<ClusterConfig>,
+    /// named clusters that routes send calls to
+    pub clusters: Vec<ClusterConfig>,
+    /// calls matching a route go to its cluster,
+    /// the first matching route is used and
+    /// other calls go to `upstream`
+    pub routes: Vec<RouteConfig>,
+    /// streams per upstream HTTP/2 connection
+    /// before another connection is opened, at least 1.
+    /// A lower SETTINGS_MAX_CONCURRENT_STREAMS
+    /// announced by the upstream wins
+    pub upstream_max_concurrent_streams: usize,
+    /// answer CORS preflights and add CORS headers,
+    /// disabled when missing
+    pub cors: Option<CorsConfig>,
+    /// terminate TLS on the listener,
+    /// plain TCP when missing
+    pub tls: Option<TlsConfig>,
+    /// hold calls until the upstream passes a health probe,
+    /// calls go straight to the upstream when missing
+    pub readiness: Option<ReadinessConfig>,
+    /// default and maximum `grpc-timeout` of calls,
+    /// calls only have the deadline the client sent when empty
+    pub deadline: DeadlineConfig,
+    /// routes whose messages are recompressed
+    /// between client and upstream
+    pub compression: Vec<CompressionRule>,
+    /// largest client and upstream messages,
+    /// unlimited when empty
+    pub message_size: MessageSizeConfig,
+    /// expose methods with `google.api.http` rules
+    /// as HTTP/JSON endpoints, disabled when missing
+    pub transcoding: Option<TranscodingConfig>,
 }
 
 impl Config {
@@ -28,6 +78,94 @@ impl Config {
         let config = serde_yaml::from_str(&txt)?;
         Ok(config)
     }
+
+    pub fn listen_address(&self) -> String {
+        format!("{}:{}", self.listen_host, self.listen_port)
+    }
+
+    /// the `upstream` cluster, or a single endpoint
+    /// cluster built from `target_host`/`target_port`
+    pub fn cluster_config(&self) -> ClusterConfig {
+        self.upstream.clone().unwrap_or_else(|| ClusterConfig {
+            endpoints: vec![format!("{}:{}", self.target_host, self.target_port)],
+            ..Default::default()
+        })
+    }
+
+    /// the same config but for the weights of
+    /// weighted routes, a running listener takes
+    /// them without dropping its connections
+    pub fn only_weights_differ(&self, other: &Config) -> bool {
+        self != other && self.without_weights() == other.without_weights()
+    }
+
+    fn without_weights(&self) -> Config {
+        let mut config = self.clone();
+        for route in &mut config.routes {
+            if let RouteTarget::WeightedClusters(clusters) = &mut route.target {
+                clusters.iter_mut().for_each(|cluster| cluster.weight = 0);
+            }
+        }
+        config
+    }
+
+    /// build the runtime state shared by
+    /// every connection of the listener
+    pub fn proxy_context(
+        &self,
+        metrics: Arc<Metrics>,
+        pool: Arc<ConnectionPool>,
+    ) -> Result<ProxyContext, BoxError> {
+        if self.upstream_max_concurrent_streams == 0 {
+            return Err("upstream_max_concurrent_streams must be at least 1".into());
+        }
+        let mut cluster_config = self.cluster_config();
+        let readiness = self.readiness.as_ref().map(|readiness| {
+            // the gate is opened by a health probe,
+            // probe with the defaults if none is configured
+            cluster_config.health_check.get_or_insert_default();
+            Arc::new(ReadinessGate::new(readiness))
+        });
+
+        let cluster = Cluster::new(&cluster_config)?;
+        let clusters = self
+            .clusters
+            .iter()
+            .map(Cluster::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let router = Router::new(&self.routes, clusters, cluster.name())?;
+        cluster.start_health_checks(
+            pool.clone(),
+            self.upstream_max_concurrent_streams,
+            metrics.clone(),
+            readiness.clone(),
+        );
+        // only the `upstream` cluster opens the readiness gate
+        for cluster in router.clusters() {
+            cluster.start_health_checks(
+                pool.clone(),
+                self.upstream_max_concurrent_streams,
+                metrics.clone(),
+                None,
+            );
+        }
+        let mut ctx = ProxyContext::new(cluster, metrics)
+            .with_router(router)
+            .with_pool(pool, self.upstream_max_concurrent_streams)
+            .with_deadline(self.deadline.clone())
+            .with_compression(self.compression.clone())
+            .with_message_size(self.message_size.clone());
+        if let Some(readiness) = readiness {
+            ctx = ctx.with_readiness(readiness);
+        }
+        if let Some(transcoding) = &self.transcoding {
+            ctx = ctx.with_transcoder(Transcoder::from_config(transcoding)?);
+        }
+        if let Some(cors) = &self.cors {
+            ctx = ctx.with_cors(CorsPolicy::new(cors)?);
+        }
+        Ok(ctx)
+    }
 }
 impl Default for Config {
     fn default() -> Self {
@@ -39,6 +177,17 @@ impl Default for Config {
             listen_port: 8080,
             target_host: "127.0.0.1".into(),
             target_port: 3000,
+            upstream: None,
+            clusters: Vec::new(),
+            routes: Vec::new(),
+            upstream_max_concurrent_streams: DEFAULT_MAX_CONCURRENT_STREAMS,
+            cors: None,
+            tls: None,
+            readiness: None,
+            deadline: DeadlineConfig::default(),
+            compression: Vec::new(),
+            message_size: MessageSizeConfig::default(),
+            transcoding: None,
         }
     }
 }
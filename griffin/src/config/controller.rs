@@ -3,7 +3,11 @@ use crate::config::reload_channel::ReloadChannel;
 use super::{config::Config, store::ConfigStore};
 use anyhow::Result;
 use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind};
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 use tokio::sync::mpsc;
 use tower::BoxError;
 
@@ -13,7 +17,18 @@ use tower::BoxError;
 pub struct ConfigController {
     pub store: ConfigStore,
     pub reload: ReloadChannel,
-    pub watcher: Option<RecommendedWatcher>,
+    /// shared with the reload task,
+    /// it adds TLS files of a new config to the watch list
+    pub watcher: Option<Arc<Mutex<RecommendedWatcher>>>,
+}
+
+/// which watched file has changed
+enum FileChange {
+    Config,
+    /// TLS certificate or key,
+    /// config content stays the same
+    /// but the listener has to reload
+    Tls,
 }
 
 impl ConfigController {
@@ -26,17 +41,50 @@ impl ConfigController {
     }
 
     /// Watch file changes and auto-reload
+    ///
+    /// TLS certificate and key files named
+    /// in the config are watched as well
     pub fn watch_file(&mut self, path: PathBuf) -> Result<(), BoxError> {
-        let (file_change_tx, mut file_change_rx) = mpsc::unbounded_channel::<()>();
+        let (file_change_tx, mut file_change_rx) = mpsc::unbounded_channel::<FileChange>();
         let store = self.store.clone();
+        // events carry absolute paths
+        let path = path.canonicalize()?;
         let path_copy = path.clone();
 
         let reload_tx = self.reload.tx.clone();
 
+        // watch file content changes
+        // NOTE: that we cannot call tokio inside this callback,
+        // so we just send a signal to the
+        // background task
+        let config_path = path.clone();
+        let watcher =
+            notify::recommended_watcher(move |res: Result<notify::Event, notify::Error>| {
+                if let Ok(event) = res
+                    && matches!(event.kind, EventKind::Modify(ModifyKind::Data(_)))
+                {
+                    let change = if event.paths.contains(&config_path) {
+                        FileChange::Config
+                    } else {
+                        FileChange::Tls
+                    };
+                    let _ = file_change_tx.send(change);
+                }
+            })?;
+        let watcher = Arc::new(Mutex::new(watcher));
+
+        watcher
+            .lock()
+            .map_err(|_| "config watcher lock poisoned")?
+            .watch(&path, RecursiveMode::NonRecursive)?;
+        let mut tls_paths = HashSet::new();
+        watch_tls_files(&watcher, &self.store.get(), &mut tls_paths)?;
+
         // Background tokio task to reload config when notified
+        let task_watcher = watcher.clone();
         tokio::spawn(async move {
             let ok = Ok::<(), BoxError>(());
-            while file_change_rx.recv().await.is_some() {
+            while let Some(change) = file_change_rx.recv().await {
                 // TODO: Maybe we just need to
                 // print error here and continue
                 // instead of return error
@@ -44,10 +92,11 @@ impl ConfigController {
                 let txt = tokio::fs::read_to_string(&path_copy).await?;
                 let cfg = serde_yaml::from_str::<Config>(&txt)?;
                 let current_config = store.get();
-                if Arc::new(cfg.clone()) == current_config {
+                if Arc::new(cfg.clone()) == current_config && matches!(change, FileChange::Config) {
                     // no change, skip reload
                     continue;
                 }
+                watch_tls_files(&task_watcher, &cfg, &mut tls_paths)?;
                 store.set(cfg);
                 // notify reload to use new config
                 reload_tx.send(())?;
@@ -55,21 +104,6 @@ impl ConfigController {
             ok
         });
 
-        // watch file content changes
-        // NOTE: that we cannot call tokio inside this callback,
-        // so we just send a signal to the
-        // background task
-        let mut watcher =
-            notify::recommended_watcher(move |res: Result<notify::Event, notify::Error>| {
-                if let Ok(event) = res
-                    && matches!(event.kind, EventKind::Modify(ModifyKind::Data(_)))
-                {
-                    let _ = file_change_tx.send(());
-                }
-            })?;
-
-        watcher.watch(&path, RecursiveMode::NonRecursive)?;
-
         // INFO: when when assign new watcher
         // the old watcher and old variables
         // related to it also get dropped
@@ -78,6 +112,30 @@ impl ConfigController {
     }
 }
 
+/// add TLS files of `config` to the watch list,
+/// `watched` keeps paths that are already watched
+fn watch_tls_files(
+    watcher: &Mutex<RecommendedWatcher>,
+    config: &Config,
+    watched: &mut HashSet<PathBuf>,
+) -> Result<(), BoxError> {
+    let Some(tls) = &config.tls else {
+        return Ok(());
+    };
+    for path in tls.watched_paths() {
+        let path = path.canonicalize()?;
+        if watched.contains(&path) {
+            continue;
+        }
+        watcher
+            .lock()
+            .map_err(|_| "config watcher lock poisoned")?
+            .watch(&path, RecursiveMode::NonRecursive)?;
+        watched.insert(path);
+    }
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_hot_reload_on_file_change() {
     use tempfile::tempdir;
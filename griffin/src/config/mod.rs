@@ -6,6 +6,7 @@
 //! [`ConfigManager`], which maintains the active
 //! configuration using an [`ArcSwap`] container.
 //!
+#[allow(clippy::module_inception)]
 pub mod config;
 pub mod controller;
 pub mod reload_channel;
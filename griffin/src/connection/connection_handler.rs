@@ -1,12 +1,15 @@
-use griffin_core::telemetry::metrics::Metrics;
-use http::uri::Authority;
+use griffin_core::context::ProxyContext;
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 
 pub trait ConnectionHandler: Send + Sync + 'static {
-    fn serve_connection(
+    /// `stream` is either a plain TCP stream
+    /// or a TLS stream after the handshake
+    fn serve_connection<S>(
         &self,
-        stream: tokio::net::TcpStream,
-        metrics: Arc<Metrics>,
-        authority: Authority,
-    ) -> impl Future<Output = ()> + Send;
+        stream: S,
+        ctx: Arc<ProxyContext>,
+    ) -> impl Future<Output = ()> + Send
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static;
 }
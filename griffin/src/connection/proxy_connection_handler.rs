@@ -1,32 +1,29 @@
+use griffin_core::context::ProxyContext;
 use griffin_core::proxy_request;
-use griffin_core::telemetry::metrics::Metrics;
-use http::uri::Authority;
 use hyper_util::server::conn::auto::Builder as AutoBuilder;
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     service::TowerToHyperService,
 };
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::connection::connection_handler::ConnectionHandler;
 #[derive(Clone)]
 pub struct ProxyConnectionHandler;
 
 impl ConnectionHandler for ProxyConnectionHandler {
-    async fn serve_connection(
-        &self,
-        stream: tokio::net::TcpStream,
-        metrics: Arc<Metrics>,
-        authority: Authority,
-    ) {
+    async fn serve_connection<S>(&self, stream: S, ctx: Arc<ProxyContext>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let io = TokioIo::new(stream);
-        let svc =
-            tower::service_fn(move |req| proxy_request(req, authority.clone(), metrics.clone()));
+        let svc = tower::service_fn(move |req| proxy_request(req, ctx.clone()));
         let svc = TowerToHyperService::new(svc);
-        if let Err(err) = AutoBuilder::new(TokioExecutor::new())
-            .serve_connection(io, svc)
-            .await
-        {
+        // upgrades and extended CONNECT carry gRPC-Web over WebSocket
+        let mut builder = AutoBuilder::new(TokioExecutor::new());
+        builder.http2().enable_connect_protocol();
+        if let Err(err) = builder.serve_connection_with_upgrades(io, svc).await {
             eprintln!("proxy error: {:?}", err);
         }
     }
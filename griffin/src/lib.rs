@@ -1,36 +1,53 @@
+use griffin_core::context::ProxyContext;
 use griffin_core::proxy_request;
 use griffin_core::telemetry::metrics::Metrics;
-use http::uri::Authority;
+use griffin_core::upstream::connection_pool::ConnectionPool;
 use hyper_util::server::conn::auto::Builder as AutoBuilder;
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     service::TowerToHyperService,
 };
-use std::str::FromStr;
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tower::BoxError;
 
+use crate::config::config::Config;
+
 pub mod args;
 pub mod config;
 pub mod connection;
 pub mod proxy;
+pub mod tls;
 
+/// Serve `listener` with the proxy described by `config`,
+/// listen address in the config is ignored
 pub async fn start_proxy(
     listener: TcpListener,
-    forward_authority: String,
+    config: Config,
     mut shutdown_rx: watch::Receiver<bool>,
 ) -> Result<(), BoxError> {
-    let forward_authority = Authority::from_str(&forward_authority)?;
-    let metrics = Arc::new(Metrics::new());
+    let ctx =
+        Arc::new(config.proxy_context(Arc::new(Metrics::new()), Arc::new(ConnectionPool::new()))?);
+    let tls = config.tls.as_ref().map(|tls| tls.acceptor()).transpose()?;
     loop {
         tokio::select! {
             accept_result = listener.accept() => {
                 match accept_result {
-                    Ok((stream, _)) => {
-                        run_proxy(stream, metrics.clone(), forward_authority.clone());
-                    }
+                    Ok((stream, _)) => match &tls {
+                        Some(acceptor) => {
+                            let accept = acceptor.accept(stream);
+                            let ctx = ctx.clone();
+                            tokio::spawn(async move {
+                                match accept.await {
+                                    Ok(stream) => run_proxy(stream, ctx),
+                                    Err(e) => eprintln!("TLS handshake error: {:?}", e),
+                                }
+                            });
+                        }
+                        None => run_proxy(stream, ctx.clone()),
+                    },
                     Err(e) => {
                         eprintln!("Failed to accept connection: {:?}", e);
                     }
@@ -48,25 +65,19 @@ pub async fn start_proxy(
     Ok(())
 }
 
-pub fn run_proxy(
-    stream: tokio::net::TcpStream,
-    metrics: Arc<Metrics>,
-    forward_authority: Authority,
-) {
+pub fn run_proxy<S>(stream: S, ctx: Arc<ProxyContext>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     // Placeholder for potential future implementation
     let io = TokioIo::new(stream);
     tokio::task::spawn(async move {
-        let forward_authority = forward_authority.clone();
-        let svc = tower::service_fn(move |req| {
-            let forward_authority = forward_authority.clone();
-            let metrics = metrics.clone();
-            proxy_request(req, forward_authority, metrics)
-        });
+        let svc = tower::service_fn(move |req| proxy_request(req, ctx.clone()));
         let svc = TowerToHyperService::new(svc);
-        if let Err(err) = AutoBuilder::new(TokioExecutor::new())
-            .serve_connection(io, svc)
-            .await
-        {
+        // upgrades and extended CONNECT carry gRPC-Web over WebSocket
+        let mut builder = AutoBuilder::new(TokioExecutor::new());
+        builder.http2().enable_connect_protocol();
+        if let Err(err) = builder.serve_connection_with_upgrades(io, svc).await {
             eprintln!("Error serving connection: {:?}", err);
         }
     });
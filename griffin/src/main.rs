@@ -35,7 +35,11 @@ async fn main() -> Result<(), BoxError> {
                 let config = config_store.get().as_ref().clone();
                 println!("Config changed, reloading listener");
                 // println!("New config:{:?}", config);
-                proxy_supervisor.load_listener(config).await?;
+                // keep the current listener when
+                // the new config or certificates are invalid
+                if let Err(err) = proxy_supervisor.load_listener(config).await {
+                    eprintln!("Failed to reload listener: {:?}", err);
+                }
             }
             Ok::<(), BoxError>(())
         })
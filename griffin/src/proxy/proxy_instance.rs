@@ -1,9 +1,12 @@
+use griffin_core::context::ProxyContext;
 use std::sync::Arc;
 use tokio::{
     sync::{Mutex, watch},
     task::JoinHandle,
 };
 
+use crate::config::config::Config;
+
 pub struct ProxyInstance {
     /// running async task
     /// which is a spawned accept loop
@@ -15,9 +18,9 @@ pub struct ProxyInstance {
     ///
     ///
     /// 2. use `pub task: Arc<JoinHandle<()>>`
-    /// to get value because cloning an Arc
-    /// does not move its inner value.
-    /// but we can’t consume something inside an Arc.
+    ///    to get value because cloning an Arc
+    ///    does not move its inner value.
+    ///    but we can’t consume something inside an Arc.
     ///
     /// in summary we can:
     ///- check .is_finished()
@@ -31,7 +34,7 @@ pub struct ProxyInstance {
     ///
     ///
     /// 3. wrap more with Mutex<Option<>>
-    /// lets us:
+    ///    lets us:
     ///- get full ownership
     ///- call .await
     ///- get result
@@ -39,6 +42,11 @@ pub struct ProxyInstance {
     pub accept_conns: Arc<Mutex<Option<JoinHandle<()>>>>,
 
     pub listen_address: String,
+    /// config the listener runs with,
+    /// weights of its routes included
+    pub config: std::sync::Mutex<Config>,
+    /// shared by every connection of the listener
+    pub ctx: Arc<ProxyContext>,
     /// shutdown signal sender
     /// sending true tells the accept loop
     /// to stop accepting new connections
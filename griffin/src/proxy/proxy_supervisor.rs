@@ -1,9 +1,10 @@
+use griffin_core::context::ProxyContext;
 use griffin_core::telemetry::metrics::Metrics;
-use http::uri::Authority;
-use std::str::FromStr;
+use griffin_core::upstream::connection_pool::ConnectionPool;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::{Mutex, watch};
+use tokio_rustls::TlsAcceptor;
 use tower::BoxError;
 
 use crate::config::config::Config;
@@ -15,6 +16,9 @@ pub struct ProxySupervisor<H: ConnectionHandler + Clone> {
     ///ArcSwap allows instant pointer swap with no locks.
     pub active_proxy: arc_swap::ArcSwapOption<ProxyInstance>,
     pub connection_handler: Arc<H>,
+    /// upstream connections outlive listeners,
+    /// a reload keeps reusing them when the target is the same
+    pub pool: Arc<ConnectionPool>,
 }
 
 impl<H: ConnectionHandler + Clone> ProxySupervisor<H> {
@@ -22,24 +26,25 @@ impl<H: ConnectionHandler + Clone> ProxySupervisor<H> {
         ProxySupervisor {
             active_proxy: arc_swap::ArcSwapOption::<ProxyInstance>::from(None),
             connection_handler: Arc::new(connection_handler),
+            pool: Arc::new(ConnectionPool::new()),
         }
     }
 
     /// Start a TCP accept loop
     pub async fn spawn_proxy_server(
         config: Config,
+        ctx: Arc<ProxyContext>,
+        tls: Option<TlsAcceptor>,
         connection_handler: Arc<H>,
     ) -> Arc<ProxyInstance> {
-        let listen_address = format!("{}:{}", config.listen_host, config.listen_port);
-        let target_authority = format!("{}:{}", config.target_host, config.target_port);
+        let listen_address = config.listen_address();
+        let instance_ctx = ctx.clone();
 
         let listener = TcpListener::bind(listen_address.clone()).await.unwrap();
 
         println!("[server: {}] start listening", listen_address);
 
         let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
-        let metrics = Arc::new(Metrics::new());
-        let target_authority = Authority::from_str(&target_authority).unwrap();
 
         let listen_address_clone = listen_address.clone();
         let accept_conns = tokio::spawn(async move {
@@ -69,12 +74,26 @@ impl<H: ConnectionHandler + Clone> ProxySupervisor<H> {
                             Ok(( stream, _peer)) => {
                                 let connection_handler = Arc::clone(&connection_handler);
                                 tokio::spawn({
-                                    let metrics = metrics.clone();
-                                    let target_authority = target_authority.clone();
+                                    let ctx = ctx.clone();
+                                    let tls = tls.clone();
                                     async move {
-                                        connection_handler
-                                            .serve_connection(stream, metrics, target_authority)
-                                            .await;
+                                        // handshake runs in the connection task
+                                        // so a slow client cannot block accepting
+                                        match tls {
+                                            Some(acceptor) => match acceptor.accept(stream).await {
+                                                Ok(stream) => {
+                                                    connection_handler
+                                                        .serve_connection(stream, ctx)
+                                                        .await
+                                                }
+                                                Err(e) => eprintln!("TLS handshake error: {}", e),
+                                            },
+                                            None => {
+                                                connection_handler
+                                                    .serve_connection(stream, ctx)
+                                                    .await
+                                            }
+                                        }
                                     }
                                 });
                             }
@@ -90,11 +109,63 @@ impl<H: ConnectionHandler + Clone> ProxySupervisor<H> {
             accept_conns: Arc::new(Mutex::new(Some(accept_conns))),
             shutdown_tx,
             listen_address: listen_address_clone,
+            config: std::sync::Mutex::new(config),
+            ctx: instance_ctx,
         })
     }
 
+    /// a reload keeping the `upstream` cluster keeps the
+    /// ready state, so `/readyz` and new calls do not
+    /// wait for another probe after every config edit
+    fn carry_readiness(&self, config: &Config, ctx: &ProxyContext) -> Result<(), BoxError> {
+        let (Some(active), Some(gate)) = (self.active_proxy.load_full(), &ctx.readiness) else {
+            return Ok(());
+        };
+        let same_upstream = active
+            .config
+            .lock()
+            .map_err(|_| "listener config lock poisoned")?
+            .cluster_config()
+            == config.cluster_config();
+        let was_ready = active
+            .ctx
+            .readiness
+            .as_ref()
+            .is_some_and(|gate| gate.is_ready());
+        if same_upstream && was_ready {
+            gate.mark_ready();
+        }
+        Ok(())
+    }
+
     /// Hot-reload: start new listener, drain old one
     pub async fn load_listener(&self, config: Config) -> Result<(), BoxError> {
+        // new weights are taken by the running listener,
+        // its connections and calls in flight stay as they are
+        if let Some(active) = self.active_proxy.load_full() {
+            let mut current = active
+                .config
+                .lock()
+                .map_err(|_| "listener config lock poisoned")?;
+            if current.only_weights_differ(&config) {
+                active.ctx.router.set_weights(&config.routes)?;
+                *current = config;
+                return Ok(());
+            }
+        }
+
+        // validate the new config before
+        // touching the running listener
+        let ctx = Arc::new(config.proxy_context(Arc::new(Metrics::new()), self.pool.clone())?);
+        self.carry_readiness(&config, &ctx)?;
+        // certificates are read again on every reload
+        let tls = config.tls.as_ref().map(|tls| tls.acceptor()).transpose()?;
+        let authorities = std::iter::once(&ctx.cluster)
+            .chain(ctx.router.clusters().iter().map(AsRef::as_ref))
+            .flat_map(|cluster| cluster.endpoints())
+            .map(|endpoint| endpoint.authority().clone())
+            .collect::<Vec<_>>();
+
         let maybe_old_pi = self.active_proxy.load_full();
         if let Some(old_pi) = maybe_old_pi {
             // stop the old accept loop first
@@ -108,11 +179,16 @@ impl<H: ConnectionHandler + Clone> ProxySupervisor<H> {
 
         // let cloned_config = config.clone();
         //  bind new listener
-        let new_pi = ProxySupervisor::spawn_proxy_server(config, connection_handler).await;
+        let new_pi =
+            ProxySupervisor::spawn_proxy_server(config, ctx, tls, connection_handler).await;
 
         // swap pointers
         let maybe_old_pi = self.active_proxy.swap(Some(new_pi.clone()));
 
+        // forget connections to endpoints no longer configured
+        self.pool
+            .retain(|authority| authorities.contains(authority));
+
         if let Some(old_pi) = maybe_old_pi {
             // println!("Hot-reloading config {:#?}", cloned_config);
             let listen_address = old_pi.listen_address.clone();
@@ -149,14 +225,13 @@ mod tests {
     }
 
     impl ConnectionHandler for MockStreamHandler {
-        async fn serve_connection(
-            &self,
-            mut stream: tokio::net::TcpStream,
-            _metrics: Arc<Metrics>,
-            authority: Authority,
-        ) {
+        async fn serve_connection<S>(&self, mut stream: S, ctx: Arc<ProxyContext>)
+        where
+            S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+        {
             let notify = self.notify.clone();
 
+            let authority = ctx.cluster.endpoints()[0].authority();
             let host = authority.host();
             let port = authority.port_u16().unwrap_or(80);
             let address: SocketAddr = format!("{}:{}", host, port)
@@ -232,10 +307,7 @@ mod tests {
         let cfg1 = Config {
             listen_host: "127.0.0.1".into(),
             listen_port: port1,
-            target_host: "".into(),
-            target_port: 0,
-            #[cfg(test)]
-            message: "".into(),
+            ..Default::default()
         };
 
         let mock_handler = MockStreamHandler {
@@ -246,7 +318,7 @@ mod tests {
 
         let proxy_supervisor = ProxySupervisor::new(mock_handler.clone());
 
-        proxy_supervisor.load_listener(cfg1.clone()).await;
+        proxy_supervisor.load_listener(cfg1.clone()).await.unwrap();
         let address_1 = format!("{}:{}", cfg1.listen_host, cfg1.listen_port);
         let client = spawn_real_tcp_client(&address_1).await;
 
@@ -258,14 +330,11 @@ mod tests {
         let cfg2 = Config {
             listen_host: "127.0.0.1".into(),
             listen_port: port2, // NEW PORT!
-            target_host: "".into(),
-            target_port: 0,
-            #[cfg(test)]
-            message: "".into(),
+            ..Default::default()
         };
         // trigger reload
 
-        proxy_supervisor.load_listener(cfg2.clone()).await;
+        proxy_supervisor.load_listener(cfg2.clone()).await.unwrap();
         // old listener should not accept new connections
         assert!(
             TcpStream::connect(address_1).await.is_err(),
@@ -304,10 +373,7 @@ mod tests {
         let cfg1 = Config {
             listen_host: "127.0.0.1".into(),
             listen_port: port,
-            target_host: "".into(),
-            target_port: 0,
-            #[cfg(test)]
-            message: "".into(),
+            ..Default::default()
         };
 
         let mock_handler = MockStreamHandler {
@@ -318,7 +384,7 @@ mod tests {
 
         let proxy_supervisor = ProxySupervisor::new(mock_handler.clone());
 
-        proxy_supervisor.load_listener(cfg1.clone()).await;
+        proxy_supervisor.load_listener(cfg1.clone()).await.unwrap();
 
         let address = format!("{}:{}", cfg1.listen_host, cfg1.listen_port);
         let client = spawn_real_tcp_client(&address).await;
@@ -329,14 +395,11 @@ mod tests {
         let cfg2 = Config {
             listen_host: "127.0.0.1".into(),
             listen_port: port, // SAME PORT!
-            target_host: "".into(),
-            target_port: 0,
-            #[cfg(test)]
-            message: "".into(),
+            ..Default::default()
         };
         // trigger reload
 
-        proxy_supervisor.load_listener(cfg2.clone()).await;
+        proxy_supervisor.load_listener(cfg2.clone()).await.unwrap();
 
         // listener should still accept new connections
         assert!(
@@ -371,8 +434,7 @@ mod tests {
             listen_port: port,
             target_host: "127.0.0.1".into(),
             target_port: 1234,
-            #[cfg(test)]
-            message: "".into(),
+            ..Default::default()
         };
 
         let mock_handler = MockStreamHandler {
@@ -382,7 +444,7 @@ mod tests {
         //     ProxySupervisor::spawn_proxy_server(cfg.clone(), mock_handler.clone()).await;
 
         let proxy_supervisor = ProxySupervisor::new(mock_handler.clone());
-        proxy_supervisor.load_listener(cfg.clone()).await;
+        proxy_supervisor.load_listener(cfg.clone()).await.unwrap();
 
         let address = format!("{}:{}", cfg.listen_host, cfg.listen_port);
         let client = spawn_real_tcp_client(&address).await;
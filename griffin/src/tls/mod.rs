@@ -0,0 +1,6 @@
+//!
+//! The [`tls`] module terminates TLS on the downstream
+//! listener. Certificates are read from disk
+//! every time the listener is (re)loaded.
+//!
+pub mod tls_config;
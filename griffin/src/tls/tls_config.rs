@@ -0,0 +1,43 @@
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tower::BoxError;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Load certificate and key from disk
+    /// and advertise both h2 and http/1.1 with ALPN
+    /// so browsers can keep using HTTP/1.1 for gRPC-Web
+    pub fn acceptor(&self) -> Result<TlsAcceptor, BoxError> {
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .map_err(|e| format!("{}: {}", self.cert_path.display(), e))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(format!("{}: no certificate found", self.cert_path.display()).into());
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)
+            .map_err(|e| format!("{}: {}", self.key_path.display(), e))?;
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// files that trigger a reload when they change
+    pub fn watched_paths(&self) -> [&PathBuf; 2] {
+        [&self.cert_path, &self.key_path]
+    }
+}